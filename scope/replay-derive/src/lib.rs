@@ -0,0 +1,206 @@
+// Derives replay's ToValue for structs and enums, so that they can be recorded
+// without converting them by hand.
+//
+//     #[derive(ToValue)]
+//     struct Node {
+//         key: i32,
+//         #[scope(display = "tree")]
+//         children: Vec<Node>,
+//         #[scope(skip)]
+//         cache: Option<String>,
+//     }
+//
+// Structs become records with their fields in order.  Tuple fields are named
+// by their index.  Enums become records tagged with the variant, as in
+// `Shape::Circle { radius: 1 }`.
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta};
+
+// What the #[scope(...)] attributes on a field ask for.
+struct FieldOptions {
+    skip: bool,
+    display: Option<Ident>,
+}
+
+// A field that's recorded: its name in the record, and how to get its value.
+struct RecordField {
+    name: String,
+    access: TokenStream2,
+    display: Option<Ident>,
+}
+
+#[proc_macro_derive(ToValue, attributes(scope))]
+pub fn derive_to_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    expand(input).unwrap_or_else(|e| e.to_compile_error()).into()
+}
+
+fn expand(mut input: DeriveInput) -> syn::Result<TokenStream2> {
+    let type_name = &input.ident;
+    let (to_value_body, display_types_body) = match &input.data {
+        Data::Struct(data) => {
+            let (pattern, fields) = destructure(quote!(#type_name), &data.fields)?;
+            let arm = record_arm(&pattern, &type_name.to_string(), &fields);
+            let display_arm = display_types_arm(&pattern, &fields);
+
+            (quote!(match self { #arm }), quote!(match self { #display_arm }))
+        }
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            let mut display_arms = Vec::new();
+            for variant in &data.variants {
+                let variant_name = &variant.ident;
+                let (pattern, fields) = destructure(quote!(#type_name::#variant_name), &variant.fields)?;
+                let tag = format!("{}::{}", type_name, variant_name);
+                arms.push(record_arm(&pattern, &tag, &fields));
+                display_arms.push(display_types_arm(&pattern, &fields));
+            }
+
+            (quote!(match self { #(#arms)* }), quote!(match self { #(#display_arms)* }))
+        }
+        Data::Union(data) => {
+            return Err(syn::Error::new_spanned(data.union_token, "ToValue can't be derived for unions"));
+        }
+    };
+
+    // Type parameters must be convertible for their fields to be.
+    let type_params: Vec<Ident> = input.generics.type_params().map(|param| param.ident.clone()).collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause.predicates.push(parse_quote!(#param: ::replay::recorder::ToValue));
+    }
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::replay::recorder::ToValue for #type_name #type_generics #where_clause {
+            fn to_value(&self) -> ::replay::event::Value {
+                #to_value_body
+            }
+
+            fn field_display_types(&self) -> ::std::vec::Vec<(::replay::event::Identifier, ::replay::event::DisplayType)> {
+                #display_types_body
+            }
+        }
+    })
+}
+
+// Makes a pattern that binds the fields that aren't skipped, and lists them.
+fn destructure(path: TokenStream2, fields: &Fields) -> syn::Result<(TokenStream2, Vec<RecordField>)> {
+    let mut record_fields = Vec::new();
+    let pattern = match fields {
+        Fields::Named(named) => {
+            let mut bindings = Vec::new();
+            for field in &named.named {
+                let options = field_options(&field.attrs)?;
+                if options.skip {
+                    continue;
+                }
+                let ident = field.ident.as_ref().expect("Named field without a name");
+                let binding = Ident::new(&format!("__scope_{}", ident), Span::call_site());
+                bindings.push(quote!(#ident: #binding));
+                record_fields.push(RecordField {
+                    name: ident.to_string(),
+                    access: quote!(#binding),
+                    display: options.display,
+                });
+            }
+
+            quote!(#path { #(#bindings,)* .. })
+        }
+        Fields::Unnamed(unnamed) => {
+            let mut bindings = Vec::new();
+            for (i, field) in unnamed.unnamed.iter().enumerate() {
+                let options = field_options(&field.attrs)?;
+                if options.skip {
+                    bindings.push(quote!(_));
+                    continue;
+                }
+                let binding = Ident::new(&format!("__scope_{}", i), Span::call_site());
+                bindings.push(quote!(#binding));
+                record_fields.push(RecordField {
+                    name: i.to_string(),
+                    access: quote!(#binding),
+                    display: options.display,
+                });
+            }
+
+            quote!(#path(#(#bindings),*))
+        }
+        Fields::Unit => quote!(#path),
+    };
+
+    Ok((pattern, record_fields))
+}
+
+fn record_arm(pattern: &TokenStream2, tag: &str, fields: &[RecordField]) -> TokenStream2 {
+    let names = fields.iter().map(|field| &field.name);
+    let accesses = fields.iter().map(|field| &field.access);
+
+    quote! {
+        #[allow(unused_variables)]
+        #pattern => ::replay::event::Value::RecordVal(
+            ::std::option::Option::Some(#tag.to_string()),
+            vec![#((#names.to_string(), ::replay::recorder::ToValue::to_value(#accesses))),*],
+        ),
+    }
+}
+
+fn display_types_arm(pattern: &TokenStream2, fields: &[RecordField]) -> TokenStream2 {
+    let displayed: Vec<&RecordField> = fields.iter().filter(|field| field.display.is_some()).collect();
+    let names = displayed.iter().map(|field| &field.name);
+    let display_types = displayed.iter().map(|field| field.display.as_ref().unwrap());
+
+    quote! {
+        #[allow(unused_variables)]
+        #pattern => vec![#((#names.to_string(), ::replay::event::DisplayType::#display_types)),*],
+    }
+}
+
+// Reads `#[scope(skip)]` and `#[scope(display = "tree")]`.
+fn field_options(attrs: &[syn::Attribute]) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions { skip: false, display: None };
+    for attr in attrs {
+        if !attr.path.is_ident("scope") {
+            continue;
+        }
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(syn::Error::new_spanned(meta, "Expected #[scope(...)]")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("skip") => {
+                    options.skip = true;
+                }
+                NestedMeta::Meta(Meta::NameValue(ref name_value)) if name_value.path.is_ident("display") => {
+                    let display = match &name_value.lit {
+                        Lit::Str(s) => s,
+                        lit => return Err(syn::Error::new_spanned(lit, "Expected a display type string")),
+                    };
+                    let variant = match display.value().as_str() {
+                        "default" => "Default",
+                        "tree" => "Tree",
+                        "table" => "Table",
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                display,
+                                "Unknown display type; expected \"default\", \"tree\" or \"table\"",
+                            ));
+                        }
+                    };
+                    options.display = Some(Ident::new(variant, display.span()));
+                }
+                nested => {
+                    return Err(syn::Error::new_spanned(nested, "Expected `skip` or `display = \"...\"`"));
+                }
+            }
+        }
+    }
+
+    Ok(options)
+}
@@ -0,0 +1,214 @@
+// Which part of the canvas is shown in the window, and how large, for states
+// too large to fit.
+use crate::core::*;
+use crate::math;
+use crate::render::Rect;
+
+const MIN_ZOOM: Scalar = 0.1;
+const MAX_ZOOM: Scalar = 4.0;
+// How quickly the camera moves to where it's going.  It goes about two
+// thirds of the rest of the way every 1 / CAMERA_SPEED seconds.
+const CAMERA_SPEED: Scalar = 8.0;
+// Space kept between what the camera moves to show and the edge of the view.
+const CAMERA_MARGIN: Scalar = 20.0;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CameraMode {
+    // Stays where the user puts it.
+    Free,
+    // Zooms out to show everything, as it grows.
+    FitAll,
+    // Moves to show the cells that the last op played changed.
+    FollowChanges,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Camera {
+    // Where the canvas's origin is drawn in the window, and how many pixels
+    // across a unit of the canvas is drawn.
+    pub offset: Vec2d,
+    pub zoom: Scalar,
+    // Where the camera is moving to.
+    pub target_offset: Vec2d,
+    pub target_zoom: Scalar,
+    pub mode: CameraMode,
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new()
+    }
+}
+
+impl Camera {
+    // Shows the canvas at its actual size, like without a camera.
+    pub fn new() -> Camera {
+        Camera {
+            offset: [0.0, 0.0],
+            zoom: 1.0,
+            target_offset: [0.0, 0.0],
+            target_zoom: 1.0,
+            mode: CameraMode::Free,
+        }
+    }
+
+    pub fn to_screen(&self, pos: Vec2d) -> Vec2d {
+        [pos[0] * self.zoom + self.offset[0], pos[1] * self.zoom + self.offset[1]]
+    }
+
+    pub fn to_world(&self, pos: Vec2d) -> Vec2d {
+        [(pos[0] - self.offset[0]) / self.zoom, (pos[1] - self.offset[1]) / self.zoom]
+    }
+
+    // Moves the canvas by a distance in the window, like when it's dragged.
+    pub fn pan(&mut self, delta: Vec2d) {
+        self.mode = CameraMode::Free;
+        self.offset = [self.offset[0] + delta[0], self.offset[1] + delta[1]];
+        self.stop();
+    }
+
+    // Zooms in or out by a factor, keeping the point of the canvas under a
+    // point in the window in place.
+    pub fn zoom_at(&mut self, pos: Vec2d, factor: Scalar) {
+        self.mode = CameraMode::Free;
+        let world_pos = self.to_world(pos);
+        self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.offset = [pos[0] - world_pos[0] * self.zoom, pos[1] - world_pos[1] * self.zoom];
+        self.stop();
+    }
+
+    // Moves back to showing the canvas at its actual size.
+    pub fn reset(&mut self) {
+        self.mode = CameraMode::Free;
+        self.target_offset = [0.0, 0.0];
+        self.target_zoom = 1.0;
+    }
+
+    fn stop(&mut self) {
+        self.target_offset = self.offset;
+        self.target_zoom = self.zoom;
+    }
+
+    // Moves to show the canvas from its origin to a point in a part of the
+    // window, zooming out if needed but never in.
+    pub fn fit(&mut self, extent: Vec2d, viewport: Rect) {
+        let width = extent[0] + CAMERA_MARGIN;
+        let height = extent[1] + CAMERA_MARGIN;
+        self.target_zoom = (viewport[2] / width).min(viewport[3] / height).min(1.0).clamp(MIN_ZOOM, MAX_ZOOM);
+        self.target_offset = [viewport[0], viewport[1]];
+    }
+
+    // Moves as little as it can to show a part of the canvas in a part of the
+    // window, with a margin around it, unless it's already shown.  A part
+    // too large to show is shown from its top left.
+    pub fn reveal(&mut self, rect: Rect, viewport: Rect) {
+        let zoom = self.target_zoom;
+        for axis in 0..2 {
+            let start = rect[axis] * zoom + self.target_offset[axis];
+            let end = start + rect[axis + 2] * zoom;
+            let view_start = viewport[axis];
+            let view_end = viewport[axis] + viewport[axis + 2];
+            let shift = if start < view_start || end - start > view_end - view_start {
+                view_start + CAMERA_MARGIN - start
+            } else if end > view_end {
+                view_end - CAMERA_MARGIN - end
+            } else {
+                0.0
+            };
+            self.target_offset[axis] += shift;
+        }
+    }
+
+    // Moves part of the way to where the camera is going.
+    pub fn update(&mut self, dt: Scalar) {
+        let percent = 1.0 - (-dt * CAMERA_SPEED).exp();
+        self.zoom = math::mix_scalar(self.zoom, self.target_zoom, percent);
+        for axis in 0..2 {
+            self.offset[axis] = math::mix_scalar(self.offset[axis], self.target_offset[axis], percent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIEWPORT: Rect = [100.0, 50.0, 400.0, 300.0];
+
+    // The camera once it's got where it's going.
+    fn settled(camera: &Camera) -> Camera {
+        Camera { offset: camera.target_offset, zoom: camera.target_zoom, ..camera.clone() }
+    }
+
+    fn assert_near(a: Vec2d, b: Vec2d) {
+        assert!((a[0] - b[0]).abs() < 1e-9 && (a[1] - b[1]).abs() < 1e-9, "{:?} isn't {:?}", a, b);
+    }
+
+    fn is_shown(camera: &Camera, rect: Rect) -> bool {
+        let top_left = camera.to_screen([rect[0], rect[1]]);
+        let bottom_right = camera.to_screen([rect[0] + rect[2], rect[1] + rect[3]]);
+        top_left[0] >= VIEWPORT[0] && top_left[1] >= VIEWPORT[1]
+            && bottom_right[0] <= VIEWPORT[0] + VIEWPORT[2] && bottom_right[1] <= VIEWPORT[1] + VIEWPORT[3]
+    }
+
+    #[test]
+    fn to_world_undoes_to_screen() {
+        let camera = Camera { offset: [30.0, -12.5], zoom: 2.5, ..Camera::new() };
+        for pos in [[0.0, 0.0], [17.0, -3.0], [-250.5, 1000.0]].iter() {
+            assert_near(camera.to_world(camera.to_screen(*pos)), *pos);
+            assert_near(camera.to_screen(camera.to_world(*pos)), *pos);
+        }
+    }
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_cursor() {
+        let mut camera = Camera { offset: [30.0, -12.5], zoom: 1.5, mode: CameraMode::FitAll, ..Camera::new() };
+        let cursor = [220.0, 140.0];
+        let world_pos = camera.to_world(cursor);
+        camera.zoom_at(cursor, 2.0);
+        assert_eq!(camera.zoom, 3.0);
+        assert_near(camera.to_screen(world_pos), cursor);
+        assert_eq!(camera.mode, CameraMode::Free);
+
+        // Past the most it zooms.
+        camera.zoom_at(cursor, 100.0);
+        assert_eq!(camera.zoom, MAX_ZOOM);
+        assert_near(camera.to_screen(world_pos), cursor);
+        assert_eq!(settled(&camera), camera);
+    }
+
+    #[test]
+    fn fitting_shows_everything_and_never_zooms_in() {
+        let rects = [[0.0, 0.0, 50.0, 20.0], [600.0, 40.0, 80.0, 20.0], [10.0, 700.0, 30.0, 30.0]];
+        let extent = [680.0, 730.0];
+        let mut camera = Camera::new();
+        camera.fit(extent, VIEWPORT);
+        let camera = settled(&camera);
+        assert!(camera.zoom < 1.0);
+        for rect in rects.iter() {
+            assert!(is_shown(&camera, *rect), "{:?} isn't shown", rect);
+        }
+
+        let mut camera = Camera::new();
+        camera.fit([50.0, 20.0], VIEWPORT);
+        assert_eq!(settled(&camera).zoom, 1.0);
+        assert!(is_shown(&settled(&camera), rects[0]));
+    }
+
+    #[test]
+    fn revealing_moves_only_for_what_isnt_shown() {
+        let mut camera = Camera { offset: [100.0, 50.0], target_offset: [100.0, 50.0], ..Camera::new() };
+        camera.reveal([10.0, 10.0, 40.0, 20.0], VIEWPORT);
+        assert_eq!(camera.target_offset, [100.0, 50.0]);
+
+        // Past the right edge, and above the top.
+        let rect = [500.0, -100.0, 40.0, 20.0];
+        camera.reveal(rect, VIEWPORT);
+        let camera = settled(&camera);
+        assert!(is_shown(&camera, rect));
+        let top_left = camera.to_screen([rect[0], rect[1]]);
+        let bottom_right = camera.to_screen([rect[0] + rect[2], rect[1] + rect[3]]);
+        assert_eq!(bottom_right[0], VIEWPORT[0] + VIEWPORT[2] - CAMERA_MARGIN);
+        assert_eq!(top_left[1], VIEWPORT[1] + CAMERA_MARGIN);
+    }
+}
@@ -0,0 +1,442 @@
+// Two traces shown side by side and played together, like the output of a
+// program before and after a change to it, with the first step where their
+// variables differ found.
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::config::Theme;
+use crate::core::*;
+use crate::event::*;
+use crate::font::FontMetrics;
+use crate::node::{NId, NodeType};
+use crate::render::Rect;
+use crate::state::*;
+
+// Aligning by events compares every op of one trace with every op of the
+// other, so traces with more pairs of ops than this are aligned by index.
+const MAX_EVENT_ALIGNMENT_PAIRS: usize = 16_000_000;
+// Room below the traces for what step they're at.
+pub const COMPARISON_STATUS_HEIGHT: Scalar = 40.0;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Alignment {
+    // Op n of one trace is played with op n of the other.  The shorter trace
+    // stays at its end while the rest of the longer one plays.
+    ByIndex,
+    // Ops with the same events are played together, and ops that only one
+    // trace has are played while the other waits, so that an op added in the
+    // middle doesn't put the rest out of step.
+    ByEvent,
+}
+
+pub struct Comparison {
+    pub before: AppState,
+    pub after: AppState,
+    // How the steps were made.  Traces too long to align by events are
+    // aligned by index instead.
+    pub alignment: Alignment,
+    // The op of each trace to show at each step.  Step 0 is the start of
+    // both.
+    pub steps: Vec<(usize, usize)>,
+    pub step_index: usize,
+    // The first step where a variable is in one trace and not the other, or
+    // has a different value.
+    pub first_difference: Option<usize>,
+    pub is_playing: bool,
+    // Seconds until the next step is played.
+    pub playback_wait: Scalar,
+    pub window_size: Vec2d,
+}
+
+impl Comparison {
+    pub fn new(font: FontMetrics,
+               before: Vec<(EventMeta, Event)>,
+               after: Vec<(EventMeta, Event)>,
+               alignment: Alignment) -> Comparison {
+        let (before, before_events) = load(font.clone(), before);
+        let (after, after_events) = load(font, after);
+        let alignment = match alignment {
+            Alignment::ByEvent if before_events.len() * after_events.len() > MAX_EVENT_ALIGNMENT_PAIRS => {
+                Alignment::ByIndex
+            }
+            _ => alignment,
+        };
+        let steps = match alignment {
+            Alignment::ByEvent => steps_by_event(&before_events, &after_events),
+            Alignment::ByIndex => steps_by_index(before_events.len(), after_events.len()),
+        };
+        let mut comparison = Comparison {
+            before,
+            after,
+            alignment,
+            steps,
+            step_index: 0,
+            first_difference: None,
+            is_playing: false,
+            playback_wait: 0.0,
+            window_size: [0.0, 0.0],
+        };
+        comparison.first_difference = comparison.find_first_difference();
+        comparison.set_window_size([1200.0, 700.0]);
+        comparison.share_layout();
+        comparison.update_mismatches();
+
+        comparison
+    }
+
+    fn states_mut(&mut self) -> [&mut AppState; 2] {
+        [&mut self.before, &mut self.after]
+    }
+
+    // Where each trace is drawn in the window.
+    pub fn viewports(&self) -> [Rect; 2] {
+        let width = (self.window_size[0] / 2.0).floor();
+        let height = (self.window_size[1] - COMPARISON_STATUS_HEIGHT).max(0.0);
+
+        [[0.0, 0.0, width, height], [width, 0.0, self.window_size[0] - width, height]]
+    }
+
+    pub fn set_window_size(&mut self, window_size: Vec2d) {
+        self.window_size = window_size;
+        let viewports = self.viewports();
+        for (state, viewport) in self.states_mut().iter_mut().zip(viewports.iter()) {
+            state.window_size = [viewport[2], viewport[3]];
+        }
+    }
+
+    // Gives the mouse position to the trace it's over, relative to where the
+    // trace is drawn.
+    pub fn set_cursor(&mut self, cursor: Option<Vec2d>) {
+        let viewports = self.viewports();
+        for (state, viewport) in self.states_mut().iter_mut().zip(viewports.iter()) {
+            state.cursor = cursor
+                .filter(|p| p[0] >= viewport[0] && p[0] < viewport[0] + viewport[2] && p[1] < viewport[3])
+                .map(|p| [p[0] - viewport[0], p[1] - viewport[1]]);
+        }
+    }
+
+    // Lays out both traces with the same variables in the same places, and
+    // the same columns as wide as the wider of the two.
+    fn share_layout(&mut self) {
+        let slots = merge_slots(self.before.layout_slots(), self.after.layout_slots());
+        for state in self.states_mut().iter_mut() {
+            state.shared_layout = Some(SharedLayout { slots: slots.clone(), column_widths: Vec::new() });
+            state.update_layout();
+        }
+        let num_cols = self.before.column_widths.len().max(self.after.column_widths.len());
+        let column_widths: Vec<Scalar> = (0..num_cols).map(|col| {
+            let before_width = self.before.column_widths.get(col).copied().unwrap_or(0.0);
+            let after_width = self.after.column_widths.get(col).copied().unwrap_or(0.0);
+
+            before_width.max(after_width)
+        }).collect();
+        for state in self.states_mut().iter_mut() {
+            state.shared_layout = Some(SharedLayout { slots: slots.clone(), column_widths: column_widths.clone() });
+            state.update_layout();
+        }
+    }
+
+    // Themes both traces, keeping their layouts shared.
+    pub fn apply_theme(&mut self, theme: &Theme) {
+        for state in self.states_mut().iter_mut() {
+            state.apply_theme(theme);
+        }
+        self.share_layout();
+    }
+
+    pub fn advance(&mut self) {
+        if self.step_index + 1 < self.steps.len() {
+            self.go_to_step(self.step_index + 1);
+        }
+    }
+
+    pub fn rewind(&mut self) {
+        if self.step_index > 0 {
+            self.go_to_step(self.step_index - 1);
+        }
+    }
+
+    pub fn go_to_first_difference(&mut self) {
+        if let Some(step_index) = self.first_difference {
+            self.go_to_step(step_index);
+        }
+    }
+
+    fn go_to_step(&mut self, step_index: usize) {
+        self.step_index = step_index;
+        let (before_op, after_op) = self.steps[step_index];
+        self.before.player_go_to(before_op);
+        self.after.player_go_to(after_op);
+        self.update_mismatches();
+    }
+
+    pub fn toggle_playing(&mut self) {
+        self.is_playing = !self.is_playing;
+        if self.is_playing && self.step_index + 1 >= self.steps.len() {
+            // Start over once the end is reached.
+            for state in self.states_mut().iter_mut() {
+                state.player_reset_to_start();
+                state.init_draw_states();
+                state.update_layout();
+            }
+            self.step_index = 0;
+            self.update_mismatches();
+        }
+        self.playback_wait = 0.0;
+    }
+
+    // Plays the next step once it's time to.
+    pub fn update_playback(&mut self, dt: Scalar) {
+        if !self.is_playing {
+            return;
+        }
+        self.playback_wait -= dt;
+        if self.playback_wait > 0.0 {
+            return;
+        }
+        if self.step_index + 1 >= self.steps.len() {
+            self.is_playing = false;
+            return;
+        }
+        self.advance();
+        self.playback_wait = PLAYBACK_DELAY;
+    }
+
+    pub fn toggle_lanes(&mut self) {
+        for state in self.states_mut().iter_mut() {
+            state.show_lanes = !state.show_lanes;
+        }
+        self.share_layout();
+    }
+
+    pub fn toggle_code_pane(&mut self) {
+        for state in self.states_mut().iter_mut() {
+            state.toggle_code_pane();
+        }
+    }
+
+    // Marks the cells shown in each trace that the other doesn't have, or
+    // has with another value.
+    fn update_mismatches(&mut self) {
+        let before_values = cell_values(&self.before, |id| self.before.draw_states[id].to_opacity > 0.0);
+        let after_values = cell_values(&self.after, |id| self.after.draw_states[id].to_opacity > 0.0);
+        self.before.mismatches = mismatched_ids(&before_values, &after_values);
+        self.after.mismatches = mismatched_ids(&after_values, &before_values);
+    }
+
+    // Plays both traces from the start on the model alone until their
+    // variables differ, then goes back to the start.
+    fn find_first_difference(&mut self) -> Option<usize> {
+        let mut before_live = HashSet::new();
+        let mut after_live = HashSet::new();
+        let mut first_difference = None;
+        for (step_index, &(before_op, after_op)) in self.steps.iter().enumerate() {
+            for change in self.before.graph_seek_changes(before_op) {
+                update_live(&mut before_live, &change);
+            }
+            for change in self.after.graph_seek_changes(after_op) {
+                update_live(&mut after_live, &change);
+            }
+            let before_values = cell_values(&self.before, |id| before_live.contains(&id));
+            let after_values = cell_values(&self.after, |id| after_live.contains(&id));
+            if !mismatched_ids(&before_values, &after_values).is_empty()
+                || !mismatched_ids(&after_values, &before_values).is_empty() {
+                first_difference = Some(step_index);
+                break;
+            }
+        }
+        for state in self.states_mut().iter_mut() {
+            state.player_reset_to_start();
+        }
+
+        first_difference
+    }
+}
+
+// A trace at its start, and the text of the event of each op.
+fn load(font: FontMetrics, events: Vec<(EventMeta, Event)>) -> (AppState, Vec<String>) {
+    let mut state = AppState::new(font);
+    let mut op_events = Vec::new();
+    for (meta, event) in events {
+        let text = event.to_string();
+        let num_ops = state.ops.len();
+        state.add_event(meta, event);
+        if state.ops.len() > num_ops {
+            op_events.push(text);
+        }
+    }
+    state.player_reset_to_start();
+    state.init_draw_states();
+
+    (state, op_events)
+}
+
+fn steps_by_index(num_before: usize, num_after: usize) -> Vec<(usize, usize)> {
+    (0..=num_before.max(num_after)).map(|i| (i.min(num_before), i.min(num_after))).collect()
+}
+
+// Plays the longest run of events the traces have in common together, in
+// order.
+fn steps_by_event(before: &[String], after: &[String]) -> Vec<(usize, usize)> {
+    let (n, m) = (before.len(), after.len());
+    // lengths[i][j] is how many events before[i..] and after[j..] have in
+    // common.
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if before[i] == after[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut steps = vec![(0, 0)];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && before[i] == after[j] {
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            i += 1;
+        } else {
+            j += 1;
+        }
+        steps.push((i, j));
+    }
+
+    steps
+}
+
+// The slots of the first trace in order, then the ones only the second has.
+// A variable that's large in either trace is large in both.
+fn merge_slots(mut slots: Vec<(Identifier, bool)>, other: Vec<(Identifier, bool)>) -> Vec<(Identifier, bool)> {
+    let mut is_used = vec![false; slots.len()];
+    for (name, is_complex) in other {
+        match (0..slots.len()).find(|&i| !is_used[i] && slots[i].0 == name) {
+            Some(i) => {
+                is_used[i] = true;
+                slots[i].1 |= is_complex;
+            }
+            None => {
+                is_used.push(true);
+                slots.push((name, is_complex));
+            }
+        }
+    }
+
+    slots
+}
+
+fn update_live(live: &mut HashSet<NId>, change: &ChangeType) {
+    match change {
+        ChangeType::Parallel { changes } => {
+            for change in changes.iter() {
+                update_live(live, change);
+            }
+        }
+        ChangeType::AddCell { id } => {
+            live.insert(*id);
+        }
+        ChangeType::RemoveCell { id } => {
+            live.remove(id);
+        }
+        _ => (),
+    }
+}
+
+// The cells that are there, with their values, by where they are.  Arrays,
+// records and frames have no value of their own but count as being there.
+// Variables are told apart by thread and by frame, counting frames of the
+// same function from the outermost.
+fn cell_values<F: Fn(NId) -> bool>(state: &AppState, is_live: F) -> BTreeMap<String, (NId, String)> {
+    let graph = &state.graph;
+    let mut frame_keys = HashMap::new();
+    let mut frame_counts = HashMap::new();
+    for node in graph.nodes_iter().filter(|node| node.node_type == NodeType::Frame && is_live(node.id())) {
+        let mut key = graph.header_label(node.id());
+        if let Some(thread_id) = graph.thread_id(node.id()) {
+            key = format!("{} / {}", graph.header_label(thread_id), key);
+        }
+        let count = frame_counts.entry(key.clone()).or_insert(0);
+        *count += 1;
+        frame_keys.insert(node.id(), format!("{}#{}", key, count));
+    }
+
+    let mut values = BTreeMap::new();
+    for node in graph.nodes_iter().filter(|node| !node.is_header() && is_live(node.id())) {
+        let mut root_id = node.id();
+        while let Some(parent_id) = graph.node(root_id).parent_id() {
+            root_id = *parent_id;
+        }
+        let label = graph.location_label(node.id());
+        let key = match (graph.frame_id(root_id), graph.thread_id(root_id)) {
+            (Some(frame_id), _) => format!("{} / {}", frame_keys.get(&frame_id).cloned().unwrap_or_default(), label),
+            (None, Some(thread_id)) => format!("{} / {}", graph.header_label(thread_id), label),
+            (None, None) => label,
+        };
+        let value = if node.should_show_value() {
+            node.value().display_string()
+        } else {
+            String::new()
+        };
+        values.insert(key, (node.id(), value));
+    }
+
+    values
+}
+
+// The cells of one trace that the other doesn't have or has with another
+// value.
+fn mismatched_ids(values: &BTreeMap<String, (NId, String)>, other: &BTreeMap<String, (NId, String)>) -> HashSet<NId> {
+    values.iter()
+        .filter(|(key, (_, value))| other.get(*key).map(|(_, other_value)| other_value) != Some(value))
+        .map(|(_, (id, _))| *id)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn slots(names: &[(&str, bool)]) -> Vec<(Identifier, bool)> {
+        names.iter().map(|(name, is_complex)| (name.to_string(), *is_complex)).collect()
+    }
+
+    #[test]
+    fn same_events_are_played_together() {
+        let steps = steps_by_event(&events(&["a", "b"]), &events(&["a", "b"]));
+        assert_eq!(steps, vec![(0, 0), (1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn an_event_only_one_trace_has_is_played_while_the_other_waits() {
+        let steps = steps_by_event(&events(&["a", "c"]), &events(&["a", "b", "c"]));
+        assert_eq!(steps, vec![(0, 0), (1, 1), (1, 2), (2, 3)]);
+        let steps = steps_by_event(&events(&["a", "x", "c"]), &events(&["a", "y", "c"]));
+        assert_eq!(steps, vec![(0, 0), (1, 1), (2, 1), (2, 2), (3, 3)]);
+    }
+
+    #[test]
+    fn traces_with_nothing_in_common_are_played_one_then_the_other() {
+        let steps = steps_by_event(&events(&["a"]), &events(&["b", "c"]));
+        assert_eq!(steps, vec![(0, 0), (1, 0), (1, 1), (1, 2)]);
+        assert_eq!(steps_by_event(&[], &[]), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn merged_slots_keep_the_first_order_and_are_large_if_either_is() {
+        let merged = merge_slots(slots(&[("a", false), ("b", true)]), slots(&[("b", false), ("c", false), ("a", true)]));
+        assert_eq!(merged, slots(&[("a", true), ("b", true), ("c", false)]));
+    }
+
+    #[test]
+    fn merged_slots_match_variables_of_the_same_name_in_order() {
+        let merged = merge_slots(slots(&[("x", false)]), slots(&[("x", false), ("x", true)]));
+        assert_eq!(merged, slots(&[("x", false), ("x", true)]));
+    }
+}
@@ -0,0 +1,384 @@
+// How the viewer looks, read from a config file that's reread when it
+// changes.  A config file has a setting on each line, like:
+//
+//     # Start from a built-in theme: dark, light or high-contrast.
+//     theme = light
+//     cell_bg_color = #fffbe8
+//     cell_width = 100
+//     tag_colors = #d8c7ea, #c2e6cc, #f5d6b3
+//     font = fonts/DejaVuSansMono.ttf
+//
+// Any setting of Theme can be given by its field name.  Colors are #rrggbb
+// or #rrggbbaa, and the font is a path relative to the config file.  The
+// theme is applied first, wherever its line is.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::core::*;
+use crate::trace::ParseError;
+
+pub const DEFAULT_CONFIG_PATH: &str = "replay.conf";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub bg_color: Color,
+    pub cell_bg_color: Color,
+    pub cell_label_color: Color,
+    pub cell_value_color: Color,
+    // Cell backgrounds for tags, used in turn.
+    pub tag_colors: Vec<Color>,
+    pub code_highlight_color: Color,
+    pub added_color: Color,
+    pub removed_color: Color,
+    pub changed_color: Color,
+    pub mismatch_color: Color,
+    pub selection_color: Color,
+    // Cells grow from cell_width to fit their values, up to max_cell_width.
+    pub cell_width: Scalar,
+    pub max_cell_width: Scalar,
+    pub cell_height: Scalar,
+    pub named_cell_height: Scalar,
+    pub index_label_width: Scalar,
+    // Space between the columns and rows of variables.
+    pub named_cell_margin_x: Scalar,
+    pub named_cell_margin_y: Scalar,
+    // Space between the cells of arrays and records.
+    pub cell_margin_x: Scalar,
+    pub cell_margin_y: Scalar,
+    // Space above each cell in its slot, and either side of the text in it.
+    pub window_margin_y: Scalar,
+    pub cell_padding_x: Scalar,
+    // Space above and below the tag legend, the size of its color swatches
+    // and the space between its tags.
+    pub legend_margin_y: Scalar,
+    pub legend_swatch_size: Scalar,
+    pub legend_spacing_x: Scalar,
+    pub status_line_height: Scalar,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ViewConfig {
+    pub theme: Theme,
+    // The bundled font is used when there's none.
+    pub font: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+// Rereads a config file when it's changed.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    // When the file was last changed, as last read.  None when it didn't
+    // exist.
+    modified: Option<SystemTime>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::dark()
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Theme {
+        Theme {
+            bg_color: [0.26, 0.26, 0.24, 1.0],
+            cell_bg_color: [0.16, 0.16, 0.14, 1.0],
+            cell_label_color: [0.58, 0.58, 0.58, 1.0],
+            cell_value_color: [0.97, 0.97, 0.95, 1.0],
+            tag_colors: vec![
+                [0.36, 0.25, 0.45, 1.0],
+                [0.22, 0.38, 0.30, 1.0],
+                [0.47, 0.31, 0.18, 1.0],
+                [0.20, 0.32, 0.48, 1.0],
+                [0.47, 0.22, 0.27, 1.0],
+                [0.40, 0.40, 0.20, 1.0],
+            ],
+            code_highlight_color: [0.36, 0.36, 0.24, 1.0],
+            added_color: [0.45, 0.75, 0.35, 1.0],
+            removed_color: [0.85, 0.35, 0.35, 1.0],
+            changed_color: [0.90, 0.75, 0.30, 1.0],
+            mismatch_color: [0.85, 0.45, 0.85, 1.0],
+            selection_color: [0.40, 0.70, 0.95, 1.0],
+            cell_width: 120.0,
+            max_cell_width: 240.0,
+            cell_height: 20.0,
+            named_cell_height: 20.0,
+            index_label_width: 15.0,
+            named_cell_margin_x: 30.0,
+            named_cell_margin_y: 10.0,
+            cell_margin_x: 20.0,
+            cell_margin_y: 10.0,
+            window_margin_y: 5.0,
+            cell_padding_x: 4.0,
+            legend_margin_y: 20.0,
+            legend_swatch_size: 12.0,
+            legend_spacing_x: 15.0,
+            status_line_height: 16.0,
+        }
+    }
+
+    pub fn light() -> Theme {
+        Theme {
+            bg_color: [0.95, 0.95, 0.93, 1.0],
+            cell_bg_color: [1.0, 1.0, 1.0, 1.0],
+            cell_label_color: [0.40, 0.40, 0.40, 1.0],
+            cell_value_color: [0.10, 0.10, 0.10, 1.0],
+            tag_colors: vec![
+                [0.85, 0.78, 0.92, 1.0],
+                [0.76, 0.90, 0.80, 1.0],
+                [0.96, 0.84, 0.70, 1.0],
+                [0.75, 0.85, 0.96, 1.0],
+                [0.96, 0.78, 0.80, 1.0],
+                [0.92, 0.92, 0.70, 1.0],
+            ],
+            code_highlight_color: [0.98, 0.93, 0.65, 1.0],
+            added_color: [0.20, 0.60, 0.20, 1.0],
+            removed_color: [0.80, 0.20, 0.20, 1.0],
+            changed_color: [0.80, 0.55, 0.05, 1.0],
+            mismatch_color: [0.70, 0.25, 0.70, 1.0],
+            selection_color: [0.15, 0.45, 0.85, 1.0],
+            ..Theme::dark()
+        }
+    }
+
+    // White on black with saturated colors, and more room between rows.
+    pub fn high_contrast() -> Theme {
+        Theme {
+            bg_color: [0.0, 0.0, 0.0, 1.0],
+            cell_bg_color: [0.20, 0.20, 0.20, 1.0],
+            cell_label_color: [0.90, 0.90, 0.90, 1.0],
+            cell_value_color: [1.0, 1.0, 1.0, 1.0],
+            tag_colors: vec![
+                [0.55, 0.0, 0.75, 1.0],
+                [0.0, 0.50, 0.15, 1.0],
+                [0.75, 0.35, 0.0, 1.0],
+                [0.0, 0.30, 0.80, 1.0],
+                [0.75, 0.0, 0.15, 1.0],
+                [0.50, 0.50, 0.0, 1.0],
+            ],
+            code_highlight_color: [0.40, 0.40, 0.0, 1.0],
+            added_color: [0.0, 1.0, 0.0, 1.0],
+            removed_color: [1.0, 0.20, 0.20, 1.0],
+            changed_color: [1.0, 0.85, 0.0, 1.0],
+            mismatch_color: [1.0, 0.30, 1.0, 1.0],
+            selection_color: [0.0, 0.80, 1.0, 1.0],
+            named_cell_margin_y: 14.0,
+            cell_margin_y: 14.0,
+            ..Theme::dark()
+        }
+    }
+
+    pub fn by_name(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::dark()),
+            "light" => Some(Theme::light()),
+            "high-contrast" => Some(Theme::high_contrast()),
+            _ => None,
+        }
+    }
+
+    // Returns an error message when the key is unknown or the value doesn't
+    // fit it.
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if key == "tag_colors" {
+            self.tag_colors = value.split(',').map(|c| parse_color(c.trim())).collect::<Result<_, _>>()?;
+        } else if let Some(color) = self.color_mut(key) {
+            *color = parse_color(value)?;
+        } else if let Some(size) = self.size_mut(key) {
+            *size = match value.parse::<Scalar>() {
+                Ok(n) if n.is_finite() && n >= 0.0 => n,
+                _ => return Err(format!("Expected a size, got: {}", value)),
+            };
+        } else {
+            return Err(format!("Unknown setting: {}", key));
+        }
+
+        Ok(())
+    }
+
+    fn color_mut(&mut self, key: &str) -> Option<&mut Color> {
+        match key {
+            "bg_color" => Some(&mut self.bg_color),
+            "cell_bg_color" => Some(&mut self.cell_bg_color),
+            "cell_label_color" => Some(&mut self.cell_label_color),
+            "cell_value_color" => Some(&mut self.cell_value_color),
+            "code_highlight_color" => Some(&mut self.code_highlight_color),
+            "added_color" => Some(&mut self.added_color),
+            "removed_color" => Some(&mut self.removed_color),
+            "changed_color" => Some(&mut self.changed_color),
+            "mismatch_color" => Some(&mut self.mismatch_color),
+            "selection_color" => Some(&mut self.selection_color),
+            _ => None,
+        }
+    }
+
+    fn size_mut(&mut self, key: &str) -> Option<&mut Scalar> {
+        match key {
+            "cell_width" => Some(&mut self.cell_width),
+            "max_cell_width" => Some(&mut self.max_cell_width),
+            "cell_height" => Some(&mut self.cell_height),
+            "named_cell_height" => Some(&mut self.named_cell_height),
+            "index_label_width" => Some(&mut self.index_label_width),
+            "named_cell_margin_x" => Some(&mut self.named_cell_margin_x),
+            "named_cell_margin_y" => Some(&mut self.named_cell_margin_y),
+            "cell_margin_x" => Some(&mut self.cell_margin_x),
+            "cell_margin_y" => Some(&mut self.cell_margin_y),
+            "window_margin_y" => Some(&mut self.window_margin_y),
+            "cell_padding_x" => Some(&mut self.cell_padding_x),
+            "legend_margin_y" => Some(&mut self.legend_margin_y),
+            "legend_swatch_size" => Some(&mut self.legend_swatch_size),
+            "legend_spacing_x" => Some(&mut self.legend_spacing_x),
+            "status_line_height" => Some(&mut self.status_line_height),
+            _ => None,
+        }
+    }
+}
+
+pub fn read_config_file<P: AsRef<Path>>(path: P) -> Result<ViewConfig, ConfigError> {
+    let path = path.as_ref();
+    let source = fs::read_to_string(path).map_err(ConfigError::Io)?;
+
+    parse_config(&source, path.parent().unwrap_or_else(|| Path::new(""))).map_err(ConfigError::Parse)
+}
+
+// Paths are relative to base_dir.
+pub fn parse_config(source: &str, base_dir: &Path) -> Result<ViewConfig, ParseError> {
+    let error = |line: usize, column: usize, message: String| ParseError { line, column, message };
+    // Each setting with its line and the column of its value.
+    let mut settings = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let equals = line.find('=').ok_or_else(|| error(i + 1, 1, "Expected key = value".to_string()))?;
+        let value = &line[equals + 1..];
+        let column = equals + 2 + (value.len() - value.trim_start().len());
+        settings.push((i + 1, column, line[..equals].trim(), value.trim()));
+    }
+
+    let mut config = ViewConfig::default();
+    for &(line, column, key, value) in settings.iter() {
+        if key == "theme" {
+            config.theme = Theme::by_name(value).ok_or_else(|| error(line, column, format!("Unknown theme: {}", value)))?;
+        }
+    }
+    for &(line, column, key, value) in settings.iter() {
+        match key {
+            "theme" => (),
+            "font" => config.font = Some(base_dir.join(value)),
+            _ => config.theme.set(key, value).map_err(|message| error(line, column, message))?,
+        }
+    }
+
+    Ok(config)
+}
+
+fn parse_color(s: &str) -> Result<Color, String> {
+    let hex = s.strip_prefix('#').filter(|hex| (hex.len() == 6 || hex.len() == 8) && hex.is_ascii());
+    let hex = hex.ok_or_else(|| format!("Expected a color like #rrggbb, got: {}", s))?;
+    let mut color = [1.0; 4];
+    for (i, component) in color.iter_mut().enumerate().take(hex.len() / 2) {
+        let byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| format!("Expected a color like #rrggbb, got: {}", s))?;
+        *component = byte as f32 / 255.0;
+    }
+
+    Ok(color)
+}
+
+impl ConfigWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> ConfigWatcher {
+        ConfigWatcher {
+            path: path.as_ref().to_path_buf(),
+            modified: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Reads the config when the file has changed since the last time.  A
+    // file that's removed goes back to the default config.
+    pub fn poll(&mut self) -> Result<Option<ViewConfig>, ConfigError> {
+        let modified = match fs::metadata(&self.path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                if self.modified.take().is_some() {
+                    return Ok(Some(ViewConfig::default()));
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(ConfigError::Io(e)),
+            Ok(metadata) => metadata.modified().map_err(ConfigError::Io)?,
+        };
+        if self.modified == Some(modified) {
+            return Ok(None);
+        }
+        // A file with a mistake isn't reread until it's changed again.
+        self.modified = Some(modified);
+
+        read_config_file(&self.path).map(Some)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "{}", e),
+            ConfigError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<ViewConfig, ParseError> {
+        parse_config(source, Path::new("conf"))
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff0000"), Ok([1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(parse_color("#00ff0080"), Ok([0.0, 1.0, 0.0, 128.0 / 255.0]));
+        for bad in ["ff0000", "#ff00", "#ff00000", "#gg0000", "#ff00é"].iter() {
+            assert!(parse_color(bad).is_err(), "{} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn settings_apply_over_the_theme_wherever_it_is() {
+        let config = parse("# A comment\n\ncell_width = 90\ntheme = light\ncell_bg_color = #000000\n\
+                            tag_colors = #ff0000, #0000ff\nlegend_swatch_size = 8\nfont = fonts/Mono.ttf\n")
+            .expect("Invalid config");
+        let theme = Theme {
+            cell_width: 90.0,
+            cell_bg_color: [0.0, 0.0, 0.0, 1.0],
+            tag_colors: vec![[1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]],
+            legend_swatch_size: 8.0,
+            ..Theme::light()
+        };
+        assert_eq!(config, ViewConfig { theme, font: Some(Path::new("conf").join("fonts/Mono.ttf")) });
+        assert_eq!(parse(""), Ok(ViewConfig::default()));
+    }
+
+    #[test]
+    fn mistakes_are_reported_where_they_are() {
+        let error = |line, column, message: &str| Err(ParseError { line, column, message: message.to_string() });
+        assert_eq!(parse("cell_width = 90\nbogus\n"), error(2, 1, "Expected key = value"));
+        assert_eq!(parse("theme = sepia\n"), error(1, 9, "Unknown theme: sepia"));
+        assert_eq!(parse("cell_colour = #ffffff\n"), error(1, 15, "Unknown setting: cell_colour"));
+        assert_eq!(parse("cell_width = -1\n"), error(1, 14, "Expected a size, got: -1"));
+        assert_eq!(parse("bg_color =  red\n"), error(1, 13, "Expected a color like #rrggbb, got: red"));
+    }
+}
@@ -1,5 +1,8 @@
-use piston_window;
-
-pub use piston_window::types::{Scalar, Vec2d};
+// The same types as piston_window's, so that they can be passed to it, but
+// without needing it.
+pub type Color = [f32; 4];
+pub type FontSize = u32;
+pub type Scalar = f64;
+pub type Vec2d = [Scalar; 2];
 
 pub type GridCoord = [usize; 2];
@@ -0,0 +1,385 @@
+// Turns snapshots of a value into the events that change one into the next,
+// so that a whole array can be traced after each step and still be shown
+// changing cell by cell.
+use crate::event::*;
+
+// Middles of arrays with more pairs of elements than this are changed element
+// by element instead of searched for the fewest events, since the search
+// takes time and memory for every pair.
+const MAX_EDIT_PAIRS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Edit {
+    Keep,
+    // Changes an element in place.
+    Change,
+    Insert,
+    Delete,
+}
+
+// The events that change the value at a location from one value to another.
+// Arrays are edited with as few sets, pushes, pops, inserts and removes as
+// possible.  Records with the same fields have just their changed fields set.
+//
+// Values that aren't defined yet are created.  Arrays are created with
+// pushes, since setting an array shows it as one value.
+pub fn diff_events(loc: &Location, from: &Value, to: &Value) -> Vec<Event> {
+    let mut events = Vec::new();
+    diff_into(loc, from, to, &mut events);
+
+    events
+}
+
+fn diff_into(loc: &Location, from: &Value, to: &Value, events: &mut Vec<Event>) {
+    if from == to {
+        return;
+    }
+    match (from, to) {
+        (Value::ArrayVal(from_elements), Value::ArrayVal(to_elements)) => {
+            diff_arrays(loc, from_elements, to_elements, events);
+        }
+        (Value::RecordVal(_, from_fields), Value::RecordVal(_, to_fields)) if is_same_shape(from, to) => {
+            for ((name, from_value), (_, to_value)) in from_fields.iter().zip(to_fields.iter()) {
+                let field_loc = Location::FieldLoc(Box::new(loc.clone()), name.clone());
+                diff_into(&field_loc, from_value, to_value, events);
+            }
+        }
+        _ => {
+            // The old elements would still be drawn, so take them out first.
+            if let Value::ArrayVal(from_elements) = from {
+                events.extend(from_elements.iter().map(|_| Event::Pop(loc.clone())));
+            }
+            events.extend(set_events(loc, to));
+        }
+    }
+}
+
+// The events that set a location to a value, pushing each array element in
+// it on its own, so that the elements get cells of their own.
+pub fn set_events(loc: &Location, value: &Value) -> Vec<Event> {
+    let mut events = vec![Event::Set(loc.clone(), shallow(value))];
+    fill_into(loc, value, &mut events);
+
+    events
+}
+
+fn diff_arrays(loc: &Location, from: &[Value], to: &[Value], events: &mut Vec<Event>) {
+    // The index of the next element in the array as it's being edited.
+    let mut index = 0;
+    let mut len = from.len();
+    let mut from_elements = from.iter();
+    let mut to_elements = to.iter();
+    let script = edit_script(from, to);
+    // Deleting everything from some element on is popping, last first.
+    let trailing_deletes = script.iter().rev().take_while(|edit| **edit == Edit::Delete).count();
+    let first_trailing_delete = script.len() - trailing_deletes;
+    for (i, edit) in script.into_iter().enumerate() {
+        match edit {
+            Edit::Keep => {
+                from_elements.next();
+                to_elements.next();
+                index += 1;
+            }
+            Edit::Change => {
+                let from_value = from_elements.next().expect("Edit script longer than array");
+                let to_value = to_elements.next().expect("Edit script longer than array");
+                let element_loc = Location::IndexLoc(Box::new(loc.clone()), index);
+                diff_into(&element_loc, from_value, to_value, events);
+                index += 1;
+            }
+            Edit::Insert => {
+                let to_value = to_elements.next().expect("Edit script longer than array");
+                if index == len {
+                    events.push(Event::Push(loc.clone(), shallow(to_value)));
+                } else {
+                    events.push(Event::Insert(loc.clone(), index, shallow(to_value)));
+                }
+                fill_into(&Location::IndexLoc(Box::new(loc.clone()), index), to_value, events);
+                index += 1;
+                len += 1;
+            }
+            Edit::Delete => {
+                from_elements.next();
+                if index + 1 == len || i >= first_trailing_delete {
+                    events.push(Event::Pop(loc.clone()));
+                } else {
+                    events.push(Event::Remove(loc.clone(), index));
+                }
+                len -= 1;
+            }
+        }
+    }
+}
+
+// The edits from one array to another, in order.  The elements that are the
+// same at the start and at the end are kept, and the ones between are edited
+// with the fewest events if there aren't too many of them.
+fn edit_script(from: &[Value], to: &[Value]) -> Vec<Edit> {
+    let prefix = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = from[prefix..].iter().rev()
+        .zip(to[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let from_middle = &from[prefix..from.len() - suffix];
+    let to_middle = &to[prefix..to.len() - suffix];
+    let mut script = vec![Edit::Keep; prefix];
+    if from_middle.len() * to_middle.len() <= MAX_EDIT_PAIRS {
+        script.extend(fewest_edits(from_middle, to_middle));
+    } else {
+        script.extend(replace_edits(from_middle, to_middle));
+    }
+    script.extend(vec![Edit::Keep; suffix]);
+
+    script
+}
+
+// Changes each element in place, then removes or adds the rest.
+fn replace_edits(from: &[Value], to: &[Value]) -> Vec<Edit> {
+    let mut script: Vec<Edit> = from.iter().zip(to.iter())
+        .map(|(a, b)| if a == b { Edit::Keep } else { Edit::Change })
+        .collect();
+    let common = script.len();
+    script.extend(vec![Edit::Delete; from.len() - common]);
+    script.extend(vec![Edit::Insert; to.len() - common]);
+
+    script
+}
+
+// The edits from one array to another that make the fewest events, in order.
+fn fewest_edits(from: &[Value], to: &[Value]) -> Vec<Edit> {
+    let (n, m) = (from.len(), to.len());
+    let insert_costs: Vec<usize> = to.iter().map(creation_cost).collect();
+    // costs[i][j] is the number of events to edit from[i..] into to[j..].
+    let mut costs = vec![vec![0; m + 1]; n + 1];
+    let mut change_costs = vec![vec![None; m]; n];
+    for i in (0..=n).rev() {
+        for j in (0..=m).rev() {
+            costs[i][j] = if i == n {
+                insert_costs[j..].iter().sum()
+            } else if j == m {
+                n - i
+            } else {
+                change_costs[i][j] = change_cost(&from[i], &to[j]);
+                let change = change_costs[i][j].map_or(usize::MAX, |cost| costs[i + 1][j + 1] + cost);
+                change.min(costs[i + 1][j] + 1).min(costs[i][j + 1] + insert_costs[j])
+            };
+        }
+    }
+
+    let mut script = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let cost = costs[i][j];
+        let change_cost = if i < n && j < m { change_costs[i][j] } else { None };
+        match change_cost {
+            Some(0) if cost == costs[i + 1][j + 1] => {
+                script.push(Edit::Keep);
+                i += 1;
+                j += 1;
+            }
+            Some(change_cost) if cost == costs[i + 1][j + 1] + change_cost => {
+                script.push(Edit::Change);
+                i += 1;
+                j += 1;
+            }
+            _ if i < n && cost == costs[i + 1][j] + 1 => {
+                script.push(Edit::Delete);
+                i += 1;
+            }
+            _ => {
+                script.push(Edit::Insert);
+                j += 1;
+            }
+        }
+    }
+
+    script
+}
+
+// About how many events it takes to change one element into another in
+// place, if it can be.
+fn change_cost(from: &Value, to: &Value) -> Option<usize> {
+    if !is_same_shape(from, to) {
+        return None;
+    }
+
+    Some(replace_cost(from, to))
+}
+
+// The number of events to change one value into another element by element,
+// without looking for elements that were inserted or removed.  Nested arrays
+// are counted this way rather than diffed, which would look at every pair of
+// their elements for every pair of elements of the outer arrays.
+fn replace_cost(from: &Value, to: &Value) -> usize {
+    if from == to {
+        return 0;
+    }
+    match (from, to) {
+        (Value::ArrayVal(from_elements), Value::ArrayVal(to_elements)) => {
+            let changes: usize = from_elements.iter().zip(to_elements.iter()).map(|(a, b)| replace_cost(a, b)).sum();
+            let deletes = from_elements.len().saturating_sub(to_elements.len());
+            let inserts: usize = to_elements.iter().skip(from_elements.len()).map(creation_cost).sum();
+
+            changes + deletes + inserts
+        }
+        (Value::RecordVal(_, from_fields), Value::RecordVal(_, to_fields)) if is_same_shape(from, to) => {
+            from_fields.iter().zip(to_fields.iter()).map(|((_, a), (_, b))| replace_cost(a, b)).sum()
+        }
+        _ => {
+            // Like diff_into, pops the old elements and sets the new value.
+            let pops = match from {
+                Value::ArrayVal(elements) => elements.len(),
+                _ => 0,
+            };
+
+            pops + creation_cost(to)
+        }
+    }
+}
+
+// The number of events to insert a value: the value itself and any array
+// elements in it.
+fn creation_cost(value: &Value) -> usize {
+    let mut events = Vec::new();
+    fill_into(&Location::VariableLoc(String::new()), value, &mut events);
+
+    1 + events.len()
+}
+
+// Whether one value can be changed into the other in place: both simple
+// values, both arrays, or records with the same type and fields.
+fn is_same_shape(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::ArrayVal(_), Value::ArrayVal(_)) => true,
+        (Value::RecordVal(a_type, a_fields), Value::RecordVal(b_type, b_fields)) => {
+            a_type == b_type
+                && a_fields.len() == b_fields.len()
+                && a_fields.iter().zip(b_fields.iter()).all(|((a_name, a_value), (b_name, b_value))| {
+                    a_name == b_name && is_same_shape(a_value, b_value)
+                })
+        }
+        _ => a.is_simple() && b.is_simple(),
+    }
+}
+
+// The value without any array elements, which are pushed separately.
+fn shallow(value: &Value) -> Value {
+    match value {
+        Value::ArrayVal(_) => Value::ArrayVal(Vec::new()),
+        Value::RecordVal(type_name, fields) => {
+            let fields = fields.iter().map(|(name, v)| (name.clone(), shallow(v))).collect();
+
+            Value::RecordVal(type_name.clone(), fields)
+        }
+        _ => value.clone(),
+    }
+}
+
+// The events that push a value onto an array at a location, pushing each
+// array element in it like set_events does.
+pub fn push_events(loc: &Location, index: usize, value: &Value) -> Vec<Event> {
+    let mut events = vec![Event::Push(loc.clone(), shallow(value))];
+    fill_into(&Location::IndexLoc(Box::new(loc.clone()), index), value, &mut events);
+
+    events
+}
+
+// Pushes the array elements that shallow left out.
+fn fill_into(loc: &Location, value: &Value, events: &mut Vec<Event>) {
+    match value {
+        Value::ArrayVal(elements) => {
+            for (i, element) in elements.iter().enumerate() {
+                events.push(Event::Push(loc.clone(), shallow(element)));
+                fill_into(&Location::IndexLoc(Box::new(loc.clone()), i), element, events);
+            }
+        }
+        Value::RecordVal(_, fields) => {
+            for (name, v) in fields.iter() {
+                fill_into(&Location::FieldLoc(Box::new(loc.clone()), name.clone()), v, events);
+            }
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ProgramGraph;
+
+    fn var() -> Location {
+        Location::VariableLoc("x".to_string())
+    }
+
+    fn array(values: &[i32]) -> Value {
+        Value::ArrayVal(values.iter().map(|n| Value::I32Val(*n)).collect())
+    }
+
+    fn point(x: i32, y: i32) -> Value {
+        let fields = vec![("x".to_string(), Value::I32Val(x)), ("y".to_string(), Value::I32Val(y))];
+
+        Value::RecordVal(Some("Point".to_string()), fields)
+    }
+
+    // The events from one value to another, after checking that playing them
+    // after the events that create the first value ends with the second.
+    fn diff_and_apply(from: &Value, to: &Value) -> Vec<Event> {
+        let events = diff_events(&var(), from, to);
+        let mut graph = ProgramGraph::new();
+        for event in diff_events(&var(), &Value::UndefinedVal, from).iter().chain(events.iter()) {
+            graph.process(&EventMeta::default(), event);
+        }
+        let id = graph.nodes_iter()
+            .find(|node| graph.node_name(node) == Some("x"))
+            .expect("Variable should be there")
+            .id();
+        assert_eq!(graph.full_value(id), *to, "after {:?}", events);
+
+        events
+    }
+
+    #[test]
+    fn pushes_elements_added_at_the_end() {
+        assert_eq!(diff_and_apply(&array(&[1, 2]), &array(&[1, 2, 3])), vec![Event::Push(var(), Value::I32Val(3))]);
+    }
+
+    #[test]
+    fn pops_elements_taken_off_the_end() {
+        assert_eq!(diff_and_apply(&array(&[1, 2, 3]), &array(&[1])), vec![Event::Pop(var()), Event::Pop(var())]);
+    }
+
+    #[test]
+    fn inserts_elements_added_in_the_middle() {
+        assert_eq!(diff_and_apply(&array(&[1, 3]), &array(&[1, 2, 3])), vec![Event::Insert(var(), 1, Value::I32Val(2))]);
+    }
+
+    #[test]
+    fn removes_elements_taken_out_of_the_middle() {
+        assert_eq!(diff_and_apply(&array(&[1, 2, 3]), &array(&[1, 3])), vec![Event::Remove(var(), 1)]);
+    }
+
+    #[test]
+    fn edits_nested_arrays_in_place() {
+        let from = Value::ArrayVal(vec![array(&[1]), array(&[2, 3])]);
+        let to = Value::ArrayVal(vec![array(&[1]), array(&[2, 4]), array(&[5])]);
+        let element = Location::IndexLoc(Box::new(var()), 1);
+        assert_eq!(diff_and_apply(&from, &to)[0], Event::Set(Location::IndexLoc(Box::new(element), 1), Value::I32Val(4)));
+    }
+
+    #[test]
+    fn sets_just_the_changed_fields_of_records() {
+        let field = Location::FieldLoc(Box::new(var()), "y".to_string());
+        assert_eq!(diff_and_apply(&point(1, 2), &point(1, 5)), vec![Event::Set(field, Value::I32Val(5))]);
+        let from = Value::ArrayVal(vec![point(1, 2), point(3, 4)]);
+        let to = Value::ArrayVal(vec![point(1, 2), point(0, 0), point(3, 4)]);
+        diff_and_apply(&from, &to);
+    }
+
+    #[test]
+    fn changes_long_arrays_element_by_element() {
+        let from: Vec<i32> = (0..1000).collect();
+        let to: Vec<i32> = (0..1000).rev().collect();
+        let events = diff_and_apply(&array(&from), &array(&to));
+        assert_eq!(events.len(), 1000);
+    }
+}
@@ -3,29 +3,40 @@
 pub type Identifier = String;
 pub type Index = usize;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     ArrayVal(Vec<Value>),
+    BoolVal(bool),
+    BytesVal(Vec<u8>),
+    CharVal(char),
     I32Val(i32),
+    I64Val(i64),
+    U64Val(u64),
     F64Val(f64),
     // MapVal(HashMap<Identifier, Value>),
     NilVal,
+    // A struct or object: an optional type name and fields in order.
+    RecordVal(Option<Identifier>, Vec<(Identifier, Value)>),
     StringVal(String),
     UndefinedVal,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Location {
     VariableLoc(Identifier),
     IndexLoc(Box<Location>, Index), // Arrays
+    FieldLoc(Box<Location>, Identifier), // Records
     // KeyLoc(Identifier, Identifier), // Maps
 }
 
-// #[derive(Clone, Debug, PartialEq)]
-// pub enum SliceLocation {
-//     SimpleLoc(Location),
-//     SliceLoc(Identifier, Index, Index),
-// }
+// A single location or the elements [lo, hi) of an array.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SliceLocation {
+    SimpleLoc(Location),
+    SliceLoc(Location, Index, Index),
+}
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Event {
@@ -33,48 +44,150 @@ pub enum Event {
     NoOp,
     // Changes the display of a named value.
     Display(Location, DisplayType),
+    // Shows an integer variable as a pointer to an element of an array.
+    IndexInto(Location, Location),
     Set(Location, Value),
-    // Forget(Location),
+    // Removes a variable, like when it goes out of scope.
+    Forget(Identifier),
+    // Starts a function call.  Variables defined until the matching exit are
+    // local to the call and are all forgotten when it exits.
+    EnterFrame(Identifier),
+    ExitFrame,
     Push(Location, Value),
     Pop(Location),
-    // Remove(Identifier, Index),
-    // Copy(SliceLocation, Location),
-    // Move(SliceLocation, Location),
-    // Swap(Location, Location),
-    // Tag(Location, Identifier),
-    // Untag(Location, Identifier),
+    // Inserts an element before the given index, or removes the element at
+    // the index, moving the following elements.
+    Insert(Location, Index, Value),
+    Remove(Location, Index),
+    // Copies the values of a location or a slice to another location.  A
+    // slice, or a copy into an element, fills consecutive elements from
+    // there, growing the array as needed.
+    Copy(SliceLocation, Location),
+    // Like Copy, but leaves the source undefined.
+    Move(SliceLocation, Location),
+    // Exchanges two array elements, including any nested values, so they can
+    // be drawn moving to each other's places.  Variables just exchange values.
+    Swap(Location, Location),
+    // Highlights cells with a named tag, like the sorted part of an array.
+    Tag(SliceLocation, Identifier),
+    Untag(SliceLocation, Identifier),
     // PushTag(Location, Identifier),
     // PopTag(Location),
 }
 
+// Where an event happened.  This doesn't change what the event does to the
+// values, only how it's shown.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EventMeta {
+    // Events without a thread act on the variables shared by all threads.
+    pub thread: Option<Identifier>,
+    // Wall-clock time in seconds, from any fixed point.
+    pub time: Option<f64>,
+    // The code that caused the event.
+    pub location: Option<SourceLocation>,
+}
+
+// A place in a source file.  Lines and columns start at 1.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum DisplayType {
     Default,
     Tree,
+    // An array of records drawn as a grid with a row per element.
+    Table,
 }
 
 impl Value {
     pub fn display_string(&self) -> String {
         match self {
             Value::ArrayVal(vec) => format!("[{}]", vec.iter().map(|v| v.display_string()).collect::<Vec<String>>().join(", ")),
+            Value::BoolVal(b) => b.to_string(),
+            // Bytes are usually hashes or binary buffers, so show them in hex.
+            Value::BytesVal(bytes) => {
+                let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                format!("0x{}", hex)
+            }
+            Value::CharVal(c) => format!("{:?}", c),
             Value::I32Val(n) => n.to_string(),
+            Value::I64Val(n) => n.to_string(),
+            Value::U64Val(n) => n.to_string(),
             Value::F64Val(x) => x.to_string(),
             // Value::MapVal(m) => panic!("map val to_string {:?}", m),
             Value::NilVal => "nil".to_string(),
+            Value::RecordVal(type_name, fields) => {
+                let fields = fields.iter().map(|(name, v)| format!("{}: {}", name, v.display_string()))
+                                   .collect::<Vec<String>>();
+                let body = if fields.is_empty() { "{}".to_string() } else { format!("{{ {} }}", fields.join(", ")) };
+                match type_name {
+                    // Like a unit struct or enum variant.
+                    Some(type_name) if fields.is_empty() => type_name.clone(),
+                    Some(type_name) => format!("{} {}", type_name, body),
+                    None => body,
+                }
+            }
             Value::StringVal(s) => format!("{:?}", s),
             Value::UndefinedVal => "<undefined>".to_string(),
         }
     }
 
+    pub fn type_name(&self) -> String {
+        match self {
+            Value::ArrayVal(elements) => format!("array of {}", elements.len()),
+            Value::BoolVal(_) => "bool".to_string(),
+            Value::BytesVal(bytes) => format!("{} bytes", bytes.len()),
+            Value::CharVal(_) => "char".to_string(),
+            Value::I32Val(_) => "i32".to_string(),
+            Value::I64Val(_) => "i64".to_string(),
+            Value::U64Val(_) => "u64".to_string(),
+            Value::F64Val(_) => "f64".to_string(),
+            Value::NilVal => "nil".to_string(),
+            Value::RecordVal(Some(type_name), _) => type_name.clone(),
+            Value::RecordVal(None, fields) => format!("record of {} fields", fields.len()),
+            Value::StringVal(_) => "string".to_string(),
+            Value::UndefinedVal => "undefined".to_string(),
+        }
+    }
+
     pub fn is_simple(&self) -> bool {
         match self {
-            Value::I32Val(_)
+            Value::BoolVal(_)
+            | Value::BytesVal(_)
+            | Value::CharVal(_)
+            | Value::I32Val(_)
+            | Value::I64Val(_)
+            | Value::U64Val(_)
             | Value::F64Val(_)
             | Value::NilVal
             | Value::StringVal(_)
             | Value::UndefinedVal => true,
 
-            Value::ArrayVal(_) => false,
+            Value::ArrayVal(_)
+            | Value::RecordVal(..) => false,
+        }
+    }
+
+    // The value as an array index, if it's an integer.
+    pub fn as_index(&self) -> Option<i64> {
+        match self {
+            Value::I32Val(n) => Some(*n as i64),
+            Value::I64Val(n) => Some(*n),
+            Value::U64Val(n) => Some((*n).min(i64::MAX as u64) as i64),
+            _ => None,
+        }
+    }
+
+    // A record's fields get nodes of their own, so the record's node only
+    // keeps its type.
+    pub fn without_fields(&self) -> Value {
+        match self {
+            Value::RecordVal(type_name, _) => Value::RecordVal(type_name.clone(), Vec::new()),
+            _ => self.clone(),
         }
     }
 }
@@ -0,0 +1,675 @@
+// Exports a trace as a single self-contained HTML file with a small player, so
+// that a run can be stepped through in a browser without the viewer.
+use std::fmt::Write;
+
+use crate::event::*;
+use crate::op::*;
+use crate::node::NodeType;
+use crate::state::ProgramGraph;
+
+const TITLE_PLACEHOLDER: &str = "{{title}}";
+const DATA_PLACEHOLDER: &str = "/*SCOPE_DATA*/null";
+
+pub fn export_html(title: &str, events: &[(EventMeta, Event)]) -> String {
+    let mut graph = ProgramGraph::new();
+    let mut labels = Vec::with_capacity(events.len());
+    let mut ops = Vec::with_capacity(events.len());
+    for (meta, event) in events.iter() {
+        if let Some(op) = graph.process(meta, event) {
+            labels.push(format!("{}{}", meta, event));
+            ops.push(op);
+        }
+    }
+
+    let data = data_json(&graph, &labels, &ops);
+    // Since the data is embedded in a script tag, make sure nothing in it can
+    // close the tag early or start a comment that keeps it open.
+    let data = data.replace("</", "<\\/").replace("<!--", "<\\!--");
+
+    // Both go in at once, so that neither is looked at for the other's
+    // placeholder.
+    let (head, rest) = PLAYER_HTML.split_once(TITLE_PLACEHOLDER).expect("No title in the player");
+    let (middle, tail) = rest.split_once(DATA_PLACEHOLDER).expect("No data in the player");
+
+    [head, &html_escape(title), middle, &data, tail].concat()
+}
+
+fn data_json(graph: &ProgramGraph, labels: &[String], ops: &[Op]) -> String {
+    let mut out = String::with_capacity(4096);
+    out.push_str("{\"nodes\":[");
+    for (i, node) in graph.nodes_iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        let name = graph.node_name(node).map(json_string).unwrap_or_else(|| "null".to_string());
+        let parent = node.parent_id().map(|id| id.to_string()).unwrap_or_else(|| "null".to_string());
+        let field = graph.field_name(node.id()).map(json_string).unwrap_or_else(|| "null".to_string());
+        let kind = match node.node_type {
+            NodeType::MemCell => "cell",
+            NodeType::NamedContainer => "variable",
+            NodeType::Frame => "frame",
+            NodeType::Thread => "thread",
+        };
+        let frame = graph.frame_id(node.id()).map(|id| id.to_string()).unwrap_or_else(|| "null".to_string());
+        let thread = graph.thread_id(node.id()).map(|id| id.to_string()).unwrap_or_else(|| "null".to_string());
+        write!(out, "{{\"id\":{},\"kind\":\"{}\",\"name\":{},\"parent\":{},\"frame\":{},\"thread\":{},\"index\":{},\"field\":{},\"complex\":{},\"display\":\"{}\"}}",
+               node.id(),
+               kind,
+               name,
+               parent,
+               frame,
+               thread,
+               node.index(),
+               field,
+               node.is_ever_complex(),
+               node.display_type()).unwrap();
+    }
+    out.push_str("],\"tags\":[");
+    for (i, name) in graph.tag_names().iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(&json_string(name));
+    }
+    out.push_str("],\"ops\":[");
+    for (i, (label, op)) in labels.iter().zip(ops.iter()).enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        write!(out, "{{\"label\":{},\"forward\":", json_string(label)).unwrap();
+        write_step_json(&mut out, &op.forward);
+        out.push_str(",\"reverse\":");
+        write_step_json(&mut out, &op.reverse);
+        out.push('}');
+    }
+    out.push_str("]}");
+
+    out
+}
+
+fn write_step_json(out: &mut String, step: &OpStep) {
+    match step {
+        OpStep::NoOp => out.push_str("{\"type\":\"noop\"}"),
+        OpStep::Atomic { steps } => {
+            out.push_str("{\"type\":\"atomic\",\"steps\":[");
+            for (i, step) in steps.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_step_json(out, step);
+            }
+            out.push_str("]}");
+        }
+        OpStep::Define { id } => write!(out, "{{\"type\":\"define\",\"id\":{}}}", id).unwrap(),
+        OpStep::Forget { id } => write!(out, "{{\"type\":\"forget\",\"id\":{}}}", id).unwrap(),
+        OpStep::Set { id, value } => {
+            write!(out, "{{\"type\":\"set\",\"id\":{},\"text\":{},\"header\":{}}}",
+                   id,
+                   json_string(&value.display_string()),
+                   header_json(value)).unwrap();
+        }
+        OpStep::Copy { id, from_id, value } => {
+            write!(out, "{{\"type\":\"copy\",\"id\":{},\"from\":{},\"text\":{},\"header\":{}}}",
+                   id,
+                   from_id,
+                   json_string(&value.display_string()),
+                   header_json(value)).unwrap();
+        }
+        OpStep::Push { id, child_id, value } => {
+            write!(out, "{{\"type\":\"push\",\"id\":{},\"child\":{},\"text\":{},\"header\":{}}}",
+                   id,
+                   child_id,
+                   json_string(&value.display_string()),
+                   header_json(value)).unwrap();
+        }
+        OpStep::Pop { id } => write!(out, "{{\"type\":\"pop\",\"id\":{}}}", id).unwrap(),
+        OpStep::Insert { id, child_id, index, value } => {
+            write!(out, "{{\"type\":\"insert\",\"id\":{},\"child\":{},\"index\":{},\"text\":{},\"header\":{}}}",
+                   id,
+                   child_id,
+                   index,
+                   json_string(&value.display_string()),
+                   header_json(value)).unwrap();
+        }
+        OpStep::Remove { id, index } => {
+            write!(out, "{{\"type\":\"remove\",\"id\":{},\"index\":{}}}", id, index).unwrap();
+        }
+        OpStep::Tag { id, tag } => write!(out, "{{\"type\":\"tag\",\"id\":{},\"tag\":{}}}", id, tag.id()).unwrap(),
+        OpStep::Untag { id, tag } => write!(out, "{{\"type\":\"untag\",\"id\":{},\"tag\":{}}}", id, tag.id()).unwrap(),
+        OpStep::Swap { a, b } => write!(out, "{{\"type\":\"swap\",\"a\":{},\"b\":{}}}", a, b).unwrap(),
+    }
+}
+
+// The type name of a record, shown above its fields.
+fn header_json(value: &Value) -> String {
+    match value {
+        Value::RecordVal(Some(type_name), _) => json_string(type_name),
+        _ => "null".to_string(),
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+
+    out
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+     .replace('<', "&lt;")
+     .replace('>', "&gt;")
+     .replace('"', "&quot;")
+}
+
+const PLAYER_HTML: &str = r#"<!doctype html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>{{title}}</title>
+  <meta name="viewport" content="width=device-width, initial-scale=1.0">
+  <style type="text/css">
+* {
+  box-sizing: border-box;
+}
+html {
+  background-color: #42423d;
+  color: #f7f7f2;
+  font-family: "Liberation Mono", courier, "courier new", monospace;
+  font-size: 12px;
+}
+body {
+  margin: 0;
+  padding: 10px;
+}
+button {
+  font-family: inherit;
+  padding: 5px 10px;
+  cursor: pointer;
+}
+#controls {
+  display: flex;
+  align-items: center;
+  gap: 10px;
+  margin-bottom: 10px;
+}
+#scrubber {
+  flex: 1;
+  max-width: 400px;
+}
+#status {
+  color: #949494;
+  margin-bottom: 15px;
+}
+#legend {
+  display: flex;
+  gap: 15px;
+  margin-bottom: 15px;
+}
+.swatch {
+  display: inline-block;
+  width: 12px;
+  height: 12px;
+  margin-right: 5px;
+  vertical-align: middle;
+}
+#scene {
+  display: flex;
+  flex-wrap: wrap;
+  align-items: flex-start;
+  gap: 30px;
+}
+.column {
+  display: flex;
+  flex-direction: column;
+  gap: 10px;
+}
+.entry {
+  display: flex;
+  align-items: flex-start;
+}
+.name {
+  color: #949494;
+  min-width: 60px;
+  padding: 3px 10px 0 0;
+}
+.array {
+  display: flex;
+  flex-direction: column;
+  gap: 10px;
+}
+.element {
+  display: flex;
+  align-items: flex-start;
+}
+.index {
+  color: #949494;
+  font-size: 10px;
+  min-width: 15px;
+  padding: 4px 4px 0 0;
+  text-align: right;
+}
+.cell {
+  background-color: #292924;
+  min-width: 120px;
+  min-height: 20px;
+  padding: 3px;
+  white-space: pre;
+  transition: background-color 0.5s ease-out;
+}
+.cell.changed {
+  background-color: #5c5c3d;
+}
+.header {
+  color: #949494;
+  padding: 3px 0;
+}
+.nested {
+  margin-left: 20px;
+}
+.table {
+  border-spacing: 20px 10px;
+  margin: -10px -20px;
+}
+.table th {
+  color: #949494;
+  font-weight: normal;
+  padding: 0 4px;
+  text-align: left;
+}
+.table td {
+  padding: 0;
+  vertical-align: top;
+}
+.frame {
+  display: flex;
+  flex-direction: column;
+  gap: 10px;
+}
+.frame-header {
+  color: #949494;
+  border-bottom: 1px solid #949494;
+  padding-bottom: 3px;
+}
+.lane {
+  display: flex;
+  flex-direction: column;
+  gap: 10px;
+  border-left: 1px solid #949494;
+  padding-left: 15px;
+}
+.lane-header {
+  color: #949494;
+}
+.frame-columns {
+  display: flex;
+  align-items: flex-start;
+  gap: 30px;
+}
+  </style>
+</head>
+<body>
+  <div id="controls">
+    <button id="back" title="Step back (Left arrow)">&larr; Back</button>
+    <button id="forward" title="Step forward (Right arrow)">Forward &rarr;</button>
+    <input id="scrubber" type="range" min="0" max="0" value="0">
+  </div>
+  <div id="status"></div>
+  <div id="legend"></div>
+  <div id="scene"></div>
+  <script>
+"use strict";
+const data = /*SCOPE_DATA*/null;
+const nodes = data.nodes.map((n) => Object.assign({ text: "<undefined>", header: null, visible: false, kids: [], tags: [] }, n));
+// These match the viewer's tag colors.
+const tagColors = ["rgb(92, 64, 115)", "rgb(56, 97, 77)", "rgb(120, 79, 46)",
+                   "rgb(51, 82, 122)", "rgb(120, 56, 69)", "rgb(102, 102, 51)"];
+let opIndex = 0;
+let changed = new Set();
+
+function apply(step) {
+  switch (step.type) {
+    case "atomic":
+      step.steps.forEach(apply);
+      break;
+    case "define":
+      nodes[step.id].visible = true;
+      changed.add(step.id);
+      break;
+    case "forget":
+      nodes[step.id].visible = false;
+      break;
+    case "set":
+    case "copy":
+      nodes[step.id].text = step.text;
+      nodes[step.id].header = step.header;
+      changed.add(step.id);
+      break;
+    case "push": {
+      const child = nodes[step.child];
+      child.text = step.text;
+      child.header = step.header;
+      child.visible = true;
+      // Swaps move nodes around, so their final places can't be used.
+      child.parent = step.id;
+      child.index = nodes[step.id].kids.length;
+      nodes[step.id].kids.push(step.child);
+      changed.add(step.child);
+      break;
+    }
+    case "pop": {
+      const childId = nodes[step.id].kids.pop();
+      if (childId !== undefined) {
+        nodes[childId].visible = false;
+      }
+      break;
+    }
+    case "tag":
+      nodes[step.id].tags.push(step.tag);
+      changed.add(step.id);
+      break;
+    case "untag":
+      nodes[step.id].tags = nodes[step.id].tags.filter((tag) => tag !== step.tag);
+      changed.add(step.id);
+      break;
+    case "insert": {
+      const child = nodes[step.child];
+      child.text = step.text;
+      child.header = step.header;
+      child.visible = true;
+      child.parent = step.id;
+      nodes[step.id].kids.splice(step.index, 0, step.child);
+      renumber(nodes[step.id]);
+      changed.add(step.child);
+      break;
+    }
+    case "remove": {
+      const [childId] = nodes[step.id].kids.splice(step.index, 1);
+      nodes[childId].visible = false;
+      renumber(nodes[step.id]);
+      break;
+    }
+    case "swap": {
+      const a = nodes[step.a];
+      const b = nodes[step.b];
+      nodes[a.parent].kids[a.index] = step.b;
+      nodes[b.parent].kids[b.index] = step.a;
+      [a.parent, b.parent] = [b.parent, a.parent];
+      [a.index, b.index] = [b.index, a.index];
+      changed.add(step.a);
+      changed.add(step.b);
+      break;
+    }
+  }
+}
+
+function renumber(node) {
+  node.kids.forEach((childId, index) => { nodes[childId].index = index; });
+}
+
+function forward() {
+  if (opIndex >= data.ops.length) return false;
+  apply(data.ops[opIndex].forward);
+  opIndex++;
+  return true;
+}
+
+function back() {
+  if (opIndex <= 0) return false;
+  opIndex--;
+  apply(data.ops[opIndex].reverse);
+  return true;
+}
+
+function seek(target) {
+  while (opIndex < target && forward()) {}
+  while (opIndex > target && back()) {}
+}
+
+function div(className, text) {
+  return el("div", className, text);
+}
+
+function el(tag, className, text) {
+  const elem = document.createElement(tag);
+  elem.className = className;
+  if (text !== undefined) elem.textContent = text;
+  return elem;
+}
+
+function isComplex(node) {
+  return node.complex || node.kids.length > 0;
+}
+
+function renderValue(node) {
+  if (node.display === "table") return renderTable(node);
+  if (isComplex(node)) {
+    const arr = div("array");
+    if (node.header && node.display !== "tree") arr.appendChild(div("header", node.header));
+    node.kids.forEach((childId, index) => {
+      const child = nodes[childId];
+      const element = div("element");
+      element.appendChild(div("index", child.field === null ? String(index) : child.field));
+      if (isComplex(child)) {
+        if (child.display === "tree") element.appendChild(div("cell", child.header || child.text));
+        const nested = renderValue(child);
+        nested.classList.add("nested");
+        element.appendChild(nested);
+      } else {
+        element.appendChild(cell(child));
+      }
+      arr.appendChild(element);
+    });
+    return arr;
+  }
+  return cell(node);
+}
+
+// One row per element and one column per field of the elements.
+function renderTable(node) {
+  const table = el("table", "table");
+  const header = el("tr");
+  header.appendChild(el("th"));
+  for (const text of columnHeaders(node)) header.appendChild(el("th", "", text));
+  table.appendChild(header);
+  node.kids.forEach((rowId, index) => {
+    const row = nodes[rowId];
+    const tr = el("tr");
+    tr.appendChild(el("td", "index", String(index)));
+    const fields = isComplex(row) ? row.kids.map((id) => nodes[id]) : [row];
+    for (const field of fields) {
+      const td = el("td");
+      td.appendChild(cell(field, changed.has(rowId)));
+      tr.appendChild(td);
+    }
+    table.appendChild(tr);
+  });
+  return table;
+}
+
+// Columns are named after the fields of records, or else numbered.
+function columnHeaders(node) {
+  const numColumns = Math.max(0, ...node.kids.map((id) => nodes[id].kids.length));
+  return Array.from({ length: numColumns }, (_, i) => {
+    for (const rowId of node.kids) {
+      const fieldId = nodes[rowId].kids[i];
+      if (fieldId !== undefined && nodes[fieldId].field !== null) return nodes[fieldId].field;
+    }
+    return String(i);
+  });
+}
+
+function cell(node, highlight) {
+  const elem = div("cell", node.text);
+  if (highlight || changed.has(node.id)) elem.classList.add("changed");
+  const tags = effectiveTags(node);
+  if (tags.length > 0) {
+    elem.style.backgroundColor = tagColors[tags[tags.length - 1] % tagColors.length];
+    elem.title = tags.map((tag) => data.tags[tag]).join(", ");
+  }
+  return elem;
+}
+
+// Tags of the arrays a node is in apply to it too.
+function effectiveTags(node) {
+  const tags = node.parent === null ? [] : effectiveTags(nodes[node.parent]);
+  return tags.concat(node.tags.filter((tag) => !tags.includes(tag)));
+}
+
+function renderLegend() {
+  const legend = document.getElementById("legend");
+  legend.textContent = "";
+  const active = new Set();
+  for (const node of nodes) {
+    if (node.visible) node.tags.forEach((tag) => active.add(tag));
+  }
+  for (const tag of Array.from(active).sort((a, b) => a - b)) {
+    const entry = el("span");
+    const swatch = el("span", "swatch");
+    swatch.style.backgroundColor = tagColors[tag % tagColors.length];
+    entry.appendChild(swatch);
+    entry.appendChild(el("span", "", data.tags[tag]));
+    legend.appendChild(entry);
+  }
+}
+
+function render() {
+  const scene = document.getElementById("scene");
+  scene.textContent = "";
+  renderTimeline(scene, null);
+  // Each thread gets a lane of its own.
+  for (const thread of nodes) {
+    if (thread.kind !== "thread" || !thread.visible) continue;
+    const lane = div("lane");
+    lane.appendChild(div("lane-header", "thread " + thread.name));
+    const columns = div("frame-columns");
+    renderTimeline(columns, thread.id);
+    lane.appendChild(columns);
+    scene.appendChild(lane);
+  }
+  renderLegend();
+  const label = opIndex > 0 ? data.ops[opIndex - 1].label : "<start>";
+  document.getElementById("status").textContent = `Op ${opIndex} / ${data.ops.length}: ${label}`;
+  document.getElementById("scrubber").value = opIndex;
+}
+
+function renderTimeline(container, threadId) {
+  renderVariables(container, nodes.filter((node) => node.frame === null && node.thread === threadId));
+  // Each function call in progress gets its variables under a header.
+  for (const frame of nodes) {
+    if (frame.kind !== "frame" || frame.thread !== threadId || !frame.visible) continue;
+    const group = div("frame");
+    group.appendChild(div("frame-header", frame.name));
+    const columns = div("frame-columns");
+    renderVariables(columns, nodes.filter((node) => node.frame === frame.id));
+    group.appendChild(columns);
+    container.appendChild(group);
+  }
+}
+
+function renderVariables(container, variables) {
+  // Simple values are stacked in the first column and arrays get their own.
+  let simpleColumn = null;
+  for (const node of variables) {
+    if (node.kind !== "variable" || !node.visible) continue;
+    if (!isComplex(node) && node.text === "<undefined>") continue;
+    const entry = div("entry");
+    entry.appendChild(div("name", node.name));
+    entry.appendChild(renderValue(node));
+    if (isComplex(node)) {
+      const column = div("column");
+      column.appendChild(entry);
+      container.appendChild(column);
+    } else {
+      if (simpleColumn === null) {
+        simpleColumn = div("column");
+        container.insertBefore(simpleColumn, container.firstChild);
+      }
+      simpleColumn.appendChild(entry);
+    }
+  }
+}
+
+function step(f) {
+  changed = new Set();
+  f();
+  render();
+}
+
+const scrubber = document.getElementById("scrubber");
+scrubber.max = data.ops.length;
+scrubber.addEventListener("input", () => step(() => seek(Number(scrubber.value))));
+document.getElementById("forward").addEventListener("click", () => step(forward));
+document.getElementById("back").addEventListener("click", () => step(back));
+document.addEventListener("keydown", (event) => {
+  if (event.key === "ArrowRight") step(forward);
+  else if (event.key === "ArrowLeft") step(back);
+});
+render();
+  </script>
+</body>
+</html>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace;
+
+    fn export(title: &str, source: &str) -> String {
+        export_html(title, &trace::parse_trace(source).expect("Invalid trace"))
+    }
+
+    // The data the player is given, as embedded in the page.
+    fn embedded_data(html: &str) -> &str {
+        let start = html.find("const data = ").expect("No data in the page") + "const data = ".len();
+        let end = start + html[start..].find(";\n").expect("Data not closed");
+
+        &html[start..end]
+    }
+
+    #[test]
+    fn embeds_an_op_for_each_event_applied() {
+        // Tagging past the end of the array is left out.
+        let html = export("run", "set x = 1\npush arr 2\ntag arr[3] done\nset x = 3\n");
+        let data = embedded_data(&html);
+        assert!(data.starts_with("{\"nodes\":[{\"id\":0,\"kind\":\"variable\",\"name\":\"x\""), "{}", data);
+        assert_eq!(data.matches("\"label\":").count(), 3);
+        assert!(data.contains("\"label\":\"set x = 1\""), "{}", data);
+        assert!(data.contains("{\"type\":\"set\",\"id\":0,\"text\":\"3\",\"header\":null}"), "{}", data);
+        assert!(data.contains("{\"type\":\"push\",\"id\":1,\"child\":2,\"text\":\"2\",\"header\":null}"), "{}", data);
+        assert!(!html.contains(DATA_PLACEHOLDER));
+    }
+
+    #[test]
+    fn text_from_the_trace_cant_close_the_script_or_title() {
+        let html = export("</title><b>", "set s = \"</script><script>alert(1)\"\n");
+        assert!(!html.contains("</script><script>alert"), "{}", html);
+        assert!(embedded_data(&html).contains("<\\/script><script>alert(1)"));
+        assert!(html.contains("<title>&lt;/title&gt;&lt;b&gt;</title>"));
+        // The page's own script is still closed.
+        assert_eq!(html.matches("</script>").count(), PLAYER_HTML.matches("</script>").count());
+    }
+
+    #[test]
+    fn placeholders_in_the_title_or_the_trace_are_left_alone() {
+        let html = export("/*SCOPE_DATA*/null {{title}}", "set s = \"{{title}} /*SCOPE_DATA*/null <!-- x\"\n");
+        assert!(html.contains("<title>/*SCOPE_DATA*/null {{title}}</title>"), "{}", html);
+        let data = embedded_data(&html);
+        assert!(data.starts_with("{\"nodes\":"), "{}", data);
+        assert!(data.contains("{{title}} /*SCOPE_DATA*/null <\\!-- x"), "{}", data);
+        assert!(!html.contains("<!--"));
+    }
+}
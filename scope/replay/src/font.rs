@@ -0,0 +1,119 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rusttype::{Font, Scale};
+
+use crate::core::*;
+
+pub const ELLIPSIS: &str = "…";
+
+// Glyph metrics used for layout, so that it doesn't need a graphics context.
+// These match what the glyph cache measures when drawing.
+#[derive(Clone)]
+pub struct FontMetrics {
+    font: Font<'static>,
+}
+
+impl FontMetrics {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<FontMetrics> {
+        let bytes = fs::read(path)?;
+        let font = Font::from_bytes(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(FontMetrics { font })
+    }
+
+    pub fn text_width(&self, font_size: FontSize, text: &str) -> Scalar {
+        let scale = Scale::uniform(font_size as f32);
+        text.chars().map(|c| {
+            self.font.glyph(c).scaled(scale).h_metrics().advance_width as Scalar
+        }).sum()
+    }
+}
+
+// Shortens text to fit in a width, ending it with an ellipsis.  Returns None
+// when the text already fits, and nothing at all when even the ellipsis
+// doesn't.
+pub fn truncate_to_width<F>(text: &str, max_width: Scalar, mut measure: F) -> Option<String>
+    where F: FnMut(&str) -> Scalar
+{
+    if measure(text) <= max_width {
+        return None;
+    }
+    let mut truncated = String::with_capacity(text.len());
+    let mut width = measure(ELLIPSIS);
+    if width > max_width {
+        return Some(truncated);
+    }
+    for c in text.chars() {
+        let mut buf = [0; 4];
+        let char_width = measure(c.encode_utf8(&mut buf));
+        if width + char_width > max_width {
+            break;
+        }
+        width += char_width;
+        truncated.push(c);
+    }
+    truncated.push_str(ELLIPSIS);
+
+    Some(truncated)
+}
+
+// Breaks text into lines that fit a width, between characters.  Past a
+// number of lines the rest is left out, ending with a line of an ellipsis.
+pub fn wrap_to_width<F>(text: &str, max_width: Scalar, max_lines: usize, mut measure: F) -> Vec<String>
+    where F: FnMut(&str) -> Scalar
+{
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut width = 0.0;
+    for c in text.chars() {
+        let mut buf = [0; 4];
+        let char_width = measure(c.encode_utf8(&mut buf));
+        if width + char_width > max_width && !line.is_empty() {
+            lines.push(std::mem::take(&mut line));
+            width = 0.0;
+            if lines.len() == max_lines {
+                lines.push(ELLIPSIS.to_string());
+                return lines;
+            }
+        }
+        width += char_width;
+        line.push(c);
+    }
+    lines.push(line);
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every character is as wide as any other, in bytes or not.
+    fn measure(text: &str) -> Scalar {
+        text.chars().count() as Scalar
+    }
+
+    #[test]
+    fn truncates_text_that_doesnt_fit() {
+        assert_eq!(truncate_to_width("", 0.0, measure), None);
+        assert_eq!(truncate_to_width("abc", 3.0, measure), None);
+        assert_eq!(truncate_to_width("abcd", 3.0, measure), Some("ab…".to_string()));
+        assert_eq!(truncate_to_width("žluťoučký", 4.0, measure), Some("žlu…".to_string()));
+        assert_eq!(truncate_to_width("abc", 1.0, measure), Some("…".to_string()));
+        assert_eq!(truncate_to_width("abc", 0.5, measure), Some(String::new()));
+    }
+
+    #[test]
+    fn wraps_text_to_fit() {
+        assert_eq!(wrap_to_width("", 3.0, 10, measure), vec![""]);
+        assert_eq!(wrap_to_width("abc", 3.0, 10, measure), vec!["abc"]);
+        assert_eq!(wrap_to_width("abcdefg", 3.0, 10, measure), vec!["abc", "def", "g"]);
+        assert_eq!(wrap_to_width("žluťoučký", 4.0, 10, measure), vec!["žluť", "oučk", "ý"]);
+        // A character wider than the lines still gets a line of its own.
+        assert_eq!(wrap_to_width("ab", 0.5, 10, measure), vec!["a", "b"]);
+        assert_eq!(wrap_to_width("abcdefg", 2.0, 2, measure), vec!["ab", "cd", ELLIPSIS]);
+        assert_eq!(wrap_to_width("abcd", 2.0, 2, measure), vec!["ab", "cd"]);
+    }
+}
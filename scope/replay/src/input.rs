@@ -1,15 +1,92 @@
 use piston_window::*;
 
+use crate::compare::Comparison;
+use crate::core::*;
+use crate::scene;
 use crate::state::*;
 
-pub fn handle<'a>(state: &mut AppState<'a>, event: &Event) {
-    if let Some(b) = event.press_args() {
-        if let Button::Keyboard(key) = b {
-            match key {
-                Key::Left => state.player_rewind(),
-                Key::Right => state.player_advance(),
-                _ => (),
-            }
+// How much one notch of the scroll wheel zooms.
+const ZOOM_STEP: Scalar = 1.1;
+
+pub fn handle(state: &mut AppState, event: &Event) {
+    if let Some(pos) = event.mouse_cursor_args() {
+        state.cursor = Some(pos);
+        if let Some(from) = state.drag_from {
+            state.camera.pan([pos[0] - from[0], pos[1] - from[1]]);
+            state.drag_from = Some(pos);
+        }
+    }
+    if let Some(false) = event.cursor_args() {
+        state.cursor = None;
+    }
+    if let Some(args) = event.resize_args() {
+        state.window_size = args.window_size;
+    }
+    if let Some(Button::Keyboard(key)) = event.press_args() {
+        match key {
+            Key::Left => state.player_rewind(),
+            Key::Right => state.player_advance(),
+            Key::L => state.toggle_lanes(),
+            Key::Space => state.toggle_playing(),
+            Key::T => state.toggle_timed_playback(),
+            Key::C => state.toggle_code_pane(),
+            Key::F if state.is_watching => state.toggle_follow_end(),
+            Key::M => state.toggle_diff_mark(),
+            Key::Escape => state.select(None),
+            Key::A => state.toggle_fit_all(),
+            Key::E => state.toggle_follow_changes(),
+            Key::D0 => state.camera.reset(),
+            _ => (),
+        }
+    }
+    if let (Some(Button::Mouse(MouseButton::Left)), Some(cursor)) = (event.press_args(), state.cursor) {
+        click(state, cursor);
+    }
+    if let Some(Button::Mouse(MouseButton::Left)) = event.release_args() {
+        state.drag_from = None;
+    }
+    if let (Some(scroll), Some(cursor)) = (event.mouse_scroll_args(), state.cursor) {
+        // Over the inspector, the wheel scrolls the value rather than zooming.
+        if scene::is_over_inspector(state, cursor) {
+            state.scroll_inspector(-scroll[1] as isize);
+        } else {
+            state.camera.zoom_at(cursor, ZOOM_STEP.powf(scroll[1]));
+        }
+    }
+}
+
+// Clicking a cell selects it for the inspector.  Clicking nothing clears the
+// selection and starts dragging the canvas.
+fn click(state: &mut AppState, pos: Vec2d) {
+    if let Some(op_index) = scene::inspector_button_at(state, pos) {
+        state.player_go_to(op_index);
+    } else if !scene::is_over_inspector(state, pos) {
+        state.select(scene::node_at(state, pos));
+        if state.selected.is_none() {
+            state.drag_from = Some(pos);
+        }
+    }
+}
+
+pub fn handle_comparison(comparison: &mut Comparison, event: &Event) {
+    if let Some(pos) = event.mouse_cursor_args() {
+        comparison.set_cursor(Some(pos));
+    }
+    if let Some(false) = event.cursor_args() {
+        comparison.set_cursor(None);
+    }
+    if let Some(args) = event.resize_args() {
+        comparison.set_window_size(args.window_size);
+    }
+    if let Some(Button::Keyboard(key)) = event.press_args() {
+        match key {
+            Key::Left => comparison.rewind(),
+            Key::Right => comparison.advance(),
+            Key::L => comparison.toggle_lanes(),
+            Key::Space => comparison.toggle_playing(),
+            Key::C => comparison.toggle_code_pane(),
+            Key::D => comparison.go_to_first_difference(),
+            _ => (),
         }
     }
 }
@@ -0,0 +1,26 @@
+// The viewer's model and drawing as a library, so that programs can record
+// events for it.  See recorder.rs.
+#[cfg(feature = "gui")]
+extern crate piston_window;
+
+pub mod camera;
+pub mod compare;
+pub mod config;
+pub mod core;
+pub mod diff;
+pub mod event;
+pub mod export;
+pub mod font;
+#[cfg(feature = "gui")]
+pub mod input;
+pub mod math;
+pub mod node;
+pub mod op;
+pub mod recorder;
+pub mod render;
+pub mod scene;
+pub mod state;
+pub mod trace;
+#[cfg(feature = "tracing")]
+pub mod tracing_layer;
+pub mod watch;
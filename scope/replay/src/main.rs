@@ -1,76 +1,379 @@
 extern crate find_folder;
 extern crate piston_window;
+extern crate replay;
 
-mod core;
-mod event;
-mod input;
-mod math;
-mod node;
-mod op;
-mod scene;
-mod state;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
 
-use piston_window::{PistonWindow, UpdateEvent, WindowSettings};
+use piston_window::{Glyphs, PistonWindow, UpdateEvent, WindowSettings};
 
-use state::*;
-use event::{DisplayType, Event as NodeEvent, Location, Value};
+use replay::{export, input, math, scene, trace};
+use replay::compare::{Alignment, Comparison};
+use replay::config::{ConfigWatcher, Theme, ViewConfig, DEFAULT_CONFIG_PATH};
+use replay::watch::{TraceChange, TraceWatcher};
+use replay::font::FontMetrics;
+use replay::render::{PistonRenderer, RecordingRenderer};
+use replay::state::*;
+use replay::event::{DisplayType, Event as NodeEvent, EventMeta, Location, Value};
 
 fn main() {
-    let mut window: PistonWindow = WindowSettings::new("Replay", [800, 600])
-        .build()
-        .unwrap_or_else(|e| { panic!("Failed to build window: {}", e) });
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    // The config is read from the current directory unless it's given.  Only
+    // the commands that draw read it.
+    let config_path = match args.as_slice() {
+        [flag, path, ..] if flag == "--config" => {
+            let path = path.clone();
+            args.drain(..2);
+            path
+        }
+        _ => DEFAULT_CONFIG_PATH.to_string(),
+    };
+    match args.as_slice() {
+        [command, trace_path, out_path] if command == "export-html" => {
+            let events = read_trace(trace_path);
+            let html = export::export_html(trace_path, &events);
+            fs::write(out_path, html).unwrap_or_else(|e| { panic!("Failed to write {}: {}", out_path, e) });
+        }
+        [command, trace_path, from, to] if command == "diff" => {
+            let (from, to) = match (from.parse(), to.parse()) {
+                (Ok(from), Ok(to)) => (from, to),
+                _ => usage(),
+            };
+            print_diff(read_trace(trace_path), from, to);
+        }
+        [command, flag, before_path, after_path] if command == "compare" && flag == "--by-index" => {
+            let (config_watcher, config) = load_config(&config_path);
+            run_comparison(before_path, after_path, Alignment::ByIndex, config_watcher, config);
+        }
+        [command, before_path, after_path] if command == "compare" => {
+            let (config_watcher, config) = load_config(&config_path);
+            run_comparison(before_path, after_path, Alignment::ByEvent, config_watcher, config);
+        }
+        [command, trace_path] if command == "dump-scene" => {
+            let (_, config) = load_config(&config_path);
+            dump_scene(read_trace(trace_path), Path::new(trace_path).parent(), &config);
+        }
+        [command, trace_path] if command == "watch" => {
+            // The file may not exist until the program starts writing it.
+            let watcher = TraceWatcher::new(trace_path);
+            let (config_watcher, config) = load_config(&config_path);
+            run_viewer(Vec::new(), Path::new(trace_path).parent(), Some(watcher), config_watcher, config);
+        }
+        [trace_path] if !trace_path.starts_with('-') => {
+            let (config_watcher, config) = load_config(&config_path);
+            run_viewer(read_trace(trace_path), Path::new(trace_path).parent(), None, config_watcher, config);
+        }
+        [] => {
+            let (config_watcher, config) = load_config(&config_path);
+            run_viewer(demo_events(), None, None, config_watcher, config);
+        }
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: replay [--config CONFIG_FILE] [TRACE_FILE]");
+    eprintln!("       replay [--config CONFIG_FILE] watch TRACE_FILE");
+    eprintln!("       replay export-html TRACE_FILE OUT_HTML_FILE");
+    eprintln!("       replay [--config CONFIG_FILE] dump-scene TRACE_FILE");
+    eprintln!("       replay diff TRACE_FILE FROM_OP TO_OP");
+    eprintln!("       replay [--config CONFIG_FILE] compare [--by-index] BEFORE_TRACE_FILE AFTER_TRACE_FILE");
+    eprintln!("The config file defaults to {} and is reread when it changes.", DEFAULT_CONFIG_PATH);
+    process::exit(2);
+}
+
+// A config with a mistake is reported, and the viewer starts with the
+// defaults until it's fixed.
+fn load_config(path: &str) -> (ConfigWatcher, ViewConfig) {
+    let mut config_watcher = ConfigWatcher::new(path);
+    let config = match config_watcher.poll() {
+        Ok(config) => config.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("Failed to read config {}: {}", path, e);
+            ViewConfig::default()
+        }
+    };
+
+    (config_watcher, config)
+}
 
+fn read_trace(path: &str) -> Vec<(EventMeta, NodeEvent)> {
+    trace::read_trace_file(path).unwrap_or_else(|e| { panic!("Failed to read trace {}: {}", path, e) })
+}
+
+// The config's font, or the bundled one.
+fn font_path(config: &ViewConfig) -> PathBuf {
+    if let Some(path) = &config.font {
+        return path.clone();
+    }
     let assets = find_folder::Search::ParentsThenKids(3, 3).for_folder("assets").expect("Couldn't find assets folder");
-    let glyphs = window.load_font(assets.join("fonts").join("liberation_mono").join("LiberationMono-Regular.ttf")).expect("Couldn't load font: liberation mono");
 
-    let mut state = AppState::new(glyphs);
-    state.add_event(NodeEvent::Set(Location::VariableLoc("num".to_string()), Value::I32Val(22)));
-    state.add_event(NodeEvent::Set(Location::VariableLoc("factor".to_string()), Value::F64Val(7.5)));
-    state.add_event(NodeEvent::Set(Location::VariableLoc("arr".to_string()), Value::ArrayVal(vec![
-        Value::StringVal("alpha".to_string()),
-        Value::StringVal("beta".to_string()),
-        Value::StringVal("charlie".to_string()),
-    ])));
-    state.add_event(NodeEvent::Set(Location::VariableLoc("display".to_string()), Value::StringVal("runtime data".to_string())));
-    let alphabet_loc = Location::VariableLoc("alphabet".to_string());
-    state.add_event(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("A".to_string())));
-    state.add_event(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("A".to_string())));
-    state.add_event(NodeEvent::Set(Location::IndexLoc(Box::new(alphabet_loc.clone()), 1), Value::StringVal("B".to_string())));
-    state.add_event(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("C".to_string())));
-    state.add_event(NodeEvent::Pop(alphabet_loc.clone()));
-    state.add_event(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("C".to_string())));
-    state.add_event(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("D".to_string())));
-    let arr_nested_loc = Location::VariableLoc("arr_nested".to_string());
-    state.add_event(NodeEvent::Display(arr_nested_loc.clone(), DisplayType::Tree));
-    state.add_event(NodeEvent::Push(arr_nested_loc.clone(), Value::StringVal("A".to_string())));
-    state.add_event(NodeEvent::Push(arr_nested_loc.clone(), Value::ArrayVal(vec![])));
-    state.add_event(NodeEvent::Push(Location::IndexLoc(Box::new(arr_nested_loc.clone()), 1), Value::StringVal("1 Nested in B".to_string())));
-    state.add_event(NodeEvent::Push(Location::IndexLoc(Box::new(arr_nested_loc.clone()), 1), Value::StringVal("2 Nested in B".to_string())));
-    state.add_event(NodeEvent::Push(arr_nested_loc.clone(), Value::StringVal("C".to_string())));
-    state.add_event(NodeEvent::Push(arr_nested_loc.clone(), Value::StringVal("D".to_string())));
-    state.add_event(NodeEvent::Set(Location::VariableLoc("num".to_string()), Value::I32Val(23)));
-    state.add_event(NodeEvent::Set(Location::VariableLoc("num".to_string()), Value::I32Val(24)));
-    state.add_event(NodeEvent::Set(Location::VariableLoc("num".to_string()), Value::I32Val(25)));
-    state.add_event(NodeEvent::Set(Location::VariableLoc("display".to_string()), Value::StringVal("CHANGED".to_string())));
+    assets.join("fonts").join("liberation_mono").join("LiberationMono-Regular.ttf")
+}
+
+fn load_font_metrics(config: &ViewConfig) -> FontMetrics {
+    let path = font_path(config);
+    FontMetrics::from_file(&path).unwrap_or_else(|e| { panic!("Couldn't load font {}: {}", path.display(), e) })
+}
+
+fn load_glyphs(window: &mut PistonWindow, config: &ViewConfig) -> Glyphs {
+    let path = font_path(config);
+    window.load_font(&path).unwrap_or_else(|e| { panic!("Couldn't load font {}: {}", path.display(), e) })
+}
+
+// Source files are looked for relative to the source dir too.
+fn load_state(events: Vec<(EventMeta, NodeEvent)>, source_dir: Option<&Path>, config: &ViewConfig) -> AppState {
+    let mut state = AppState::new(load_font_metrics(config));
+    for (meta, event) in events {
+        state.add_event(meta, event);
+    }
+    state.load_sources(source_dir);
     state.player_reset_to_start();
     state.init_draw_states();
-    state.update_layout();
+    state.apply_theme(&config.theme);
+
+    state
+}
+
+// Rereads the config file when it's changed, and the font when the config's
+// font has changed.  Returns the new theme, with the new font's metrics if
+// any.  A config or font with a mistake is reported and left unused.
+fn reload_config(window: &mut PistonWindow,
+                 watcher: &mut ConfigWatcher,
+                 config: &mut ViewConfig,
+                 glyphs: &mut Glyphs) -> Option<(Theme, Option<FontMetrics>)> {
+    let mut new_config = match watcher.poll() {
+        Ok(new_config) => new_config?,
+        Err(e) => {
+            eprintln!("Failed to read config {}: {}", watcher.path().display(), e);
+            return None;
+        }
+    };
+    let mut font = None;
+    if new_config.font != config.font {
+        let path = font_path(&new_config);
+        let loaded = window.load_font(&path).and_then(|new_glyphs| Ok((new_glyphs, FontMetrics::from_file(&path)?)));
+        match loaded {
+            Ok((new_glyphs, metrics)) => {
+                *glyphs = new_glyphs;
+                font = Some(metrics);
+            }
+            Err(e) => {
+                eprintln!("Couldn't load font {}: {}", path.display(), e);
+                new_config.font = config.font.clone();
+            }
+        }
+    }
+    *config = new_config;
+
+    Some((config.theme.clone(), font))
+}
+
+// Prints the draw commands of the final state of a trace without opening a
+// window.
+fn dump_scene(events: Vec<(EventMeta, NodeEvent)>, source_dir: Option<&Path>, config: &ViewConfig) {
+    let mut state = load_state(events, source_dir, config);
+    while state.op_index + 1 < state.ops.len() {
+        state.player_advance();
+    }
+    for ds in state.draw_states.iter_mut() {
+        ds.mix = 1.0;
+        ds.move_mix = 1.0;
+        ds.pointer_mix = 1.0;
+    }
+    let mut renderer = RecordingRenderer::new();
+    scene::draw(&state, &mut renderer);
+    for command in renderer.commands.iter() {
+        println!("{:?}", command);
+    }
+}
+
+// A watched trace is reread this often, in seconds.
+const WATCH_INTERVAL: f64 = 0.25;
+// The config file is checked for changes this often, in seconds.
+const CONFIG_INTERVAL: f64 = 0.5;
+
+// Prints what changes between two ops, where op 0 is the start.
+fn print_diff(events: Vec<(EventMeta, NodeEvent)>, from: usize, to: usize) {
+    let mut graph = ProgramGraph::new();
+    let ops = graph.process_events(&events);
+    let num_ops = ops.len();
+    if from >= num_ops || to >= num_ops {
+        eprintln!("Ops must be from 0 to {}", num_ops - 1);
+        process::exit(2);
+    }
+    for (id, cell_change) in graph.changes_between(&ops, num_ops - 1, from, to).cells {
+        let label = graph.location_label(id);
+        // Arrays, records and frames have no value of their own to show.
+        if graph.node(id).is_ever_complex() || graph.node(id).is_header() {
+            let sign = match cell_change {
+                CellChange::Added { .. } => "+",
+                CellChange::Removed { .. } => "-",
+                CellChange::Changed { .. } => continue,
+            };
+            println!("{} {}", sign, label);
+            continue;
+        }
+        match cell_change {
+            CellChange::Added { value } => println!("+ {} = {}", label, value),
+            CellChange::Removed { value } => println!("- {} = {}", label, value),
+            CellChange::Changed { from, to } => println!("~ {}: {} → {}", label, from, to),
+        }
+    }
+}
+
+fn run_viewer(events: Vec<(EventMeta, NodeEvent)>,
+              source_dir: Option<&Path>,
+              mut watcher: Option<TraceWatcher>,
+              mut config_watcher: ConfigWatcher,
+              mut config: ViewConfig) {
+    let mut window: PistonWindow = WindowSettings::new("Replay", [800, 600])
+        .build()
+        .unwrap_or_else(|e| { panic!("Failed to build window: {}", e) });
+
+    let mut glyphs = load_glyphs(&mut window, &config);
+
+    let mut state = load_state(events, source_dir, &config);
+    state.is_watching = watcher.is_some();
+    let mut watch_wait = 0.0;
+    let mut config_wait = CONFIG_INTERVAL;
 
     while let Some(event) = window.next() {
         // Handle input.
         input::handle(&mut state, &event);
 
         event.update(|args| {
-            // TODO: Change this to 0.1 to run at 1/10th the speed.
-            let transition_factor = 1.0;
-            for ds in state.draw_states.iter_mut() {
-                ds.mix = math::clamp(ds.mix + args.dt * transition_factor);
+            if let Some(watcher) = watcher.as_mut() {
+                watch_wait -= args.dt;
+                if watch_wait <= 0.0 {
+                    watch_wait = WATCH_INTERVAL;
+                    match watcher.poll() {
+                        Ok(None) => (),
+                        Ok(Some(TraceChange::Appended(events))) => state.append_events(events, source_dir),
+                        Ok(Some(TraceChange::Replaced(events))) => state.replace_events(events, source_dir),
+                        Err(e) => eprintln!("Failed to read watched trace: {}", e),
+                    }
+                }
             }
+            config_wait -= args.dt;
+            if config_wait <= 0.0 {
+                config_wait = CONFIG_INTERVAL;
+                if let Some((theme, font)) = reload_config(&mut window, &mut config_watcher, &mut config, &mut glyphs) {
+                    if let Some(font) = font {
+                        state.font = font;
+                    }
+                    state.apply_theme(&theme);
+                }
+            }
+            state.apply_queued_events();
+            state.update_playback(args.dt);
+            animate(&mut state, args.dt);
         });
 
         window.draw_2d(&event, |ctx, g, device| {
             piston_window::clear(state.bg_color, g);
-            scene::draw(&mut state, ctx, g, device);
+            let mut renderer = PistonRenderer::new(ctx, g, &mut glyphs);
+            scene::draw(&state, &mut renderer);
+            renderer.flush(device);
+        });
+    }
+}
+
+// Shows two traces side by side, played in lockstep.
+fn run_comparison(before_path: &str,
+                  after_path: &str,
+                  alignment: Alignment,
+                  mut config_watcher: ConfigWatcher,
+                  mut config: ViewConfig) {
+    let mut window: PistonWindow = WindowSettings::new("Replay", [1200, 700])
+        .build()
+        .unwrap_or_else(|e| { panic!("Failed to build window: {}", e) });
+
+    let mut glyphs = load_glyphs(&mut window, &config);
+
+    let font = load_font_metrics(&config);
+    let mut comparison = Comparison::new(font, read_trace(before_path), read_trace(after_path), alignment);
+    if comparison.alignment != alignment {
+        eprintln!("Traces are too long to align by events, so aligning by index");
+    }
+    comparison.before.load_sources(Path::new(before_path).parent());
+    comparison.after.load_sources(Path::new(after_path).parent());
+    comparison.apply_theme(&config.theme);
+    let mut config_wait = CONFIG_INTERVAL;
+
+    while let Some(event) = window.next() {
+        input::handle_comparison(&mut comparison, &event);
+
+        event.update(|args| {
+            config_wait -= args.dt;
+            if config_wait <= 0.0 {
+                config_wait = CONFIG_INTERVAL;
+                if let Some((theme, font)) = reload_config(&mut window, &mut config_watcher, &mut config, &mut glyphs) {
+                    if let Some(font) = font {
+                        comparison.before.font = font.clone();
+                        comparison.after.font = font;
+                    }
+                    comparison.apply_theme(&theme);
+                }
+            }
+            comparison.update_playback(args.dt);
+            animate(&mut comparison.before, args.dt);
+            animate(&mut comparison.after, args.dt);
         });
+
+        window.draw_2d(&event, |ctx, g, device| {
+            piston_window::clear(comparison.before.bg_color, g);
+            let mut renderer = PistonRenderer::new(ctx, g, &mut glyphs);
+            scene::draw_comparison(&comparison, &mut renderer);
+            renderer.flush(device);
+        });
+    }
+}
+
+// Moves the transitions of the last ops played and the camera along.
+fn animate(state: &mut AppState, dt: f64) {
+    // TODO: Change this to 0.1 to run at 1/10th the speed.
+    let transition_factor = 1.0;
+    for ds in state.draw_states.iter_mut() {
+        ds.mix = math::clamp(ds.mix + dt * transition_factor);
+        ds.move_mix = math::clamp(ds.move_mix + dt * transition_factor);
+        ds.pointer_mix = math::clamp(ds.pointer_mix + dt * transition_factor);
     }
+    state.update_camera(dt);
+}
+
+fn demo_events() -> Vec<(EventMeta, NodeEvent)> {
+    let mut events = Vec::with_capacity(32);
+    events.push(NodeEvent::Set(Location::VariableLoc("num".to_string()), Value::I32Val(22)));
+    events.push(NodeEvent::Set(Location::VariableLoc("factor".to_string()), Value::F64Val(7.5)));
+    events.push(NodeEvent::Set(Location::VariableLoc("arr".to_string()), Value::ArrayVal(vec![
+        Value::StringVal("alpha".to_string()),
+        Value::StringVal("beta".to_string()),
+        Value::StringVal("charlie".to_string()),
+    ])));
+    events.push(NodeEvent::Set(Location::VariableLoc("display".to_string()), Value::StringVal("runtime data".to_string())));
+    let alphabet_loc = Location::VariableLoc("alphabet".to_string());
+    events.push(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("A".to_string())));
+    events.push(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("A".to_string())));
+    events.push(NodeEvent::Set(Location::IndexLoc(Box::new(alphabet_loc.clone()), 1), Value::StringVal("B".to_string())));
+    events.push(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("C".to_string())));
+    events.push(NodeEvent::Pop(alphabet_loc.clone()));
+    events.push(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("C".to_string())));
+    events.push(NodeEvent::Push(alphabet_loc.clone(), Value::StringVal("D".to_string())));
+    let arr_nested_loc = Location::VariableLoc("arr_nested".to_string());
+    events.push(NodeEvent::Display(arr_nested_loc.clone(), DisplayType::Tree));
+    events.push(NodeEvent::Push(arr_nested_loc.clone(), Value::StringVal("A".to_string())));
+    events.push(NodeEvent::Push(arr_nested_loc.clone(), Value::ArrayVal(vec![])));
+    events.push(NodeEvent::Push(Location::IndexLoc(Box::new(arr_nested_loc.clone()), 1), Value::StringVal("1 Nested in B".to_string())));
+    events.push(NodeEvent::Push(Location::IndexLoc(Box::new(arr_nested_loc.clone()), 1), Value::StringVal("2 Nested in B".to_string())));
+    events.push(NodeEvent::Push(arr_nested_loc.clone(), Value::StringVal("C".to_string())));
+    events.push(NodeEvent::Push(arr_nested_loc.clone(), Value::StringVal("D".to_string())));
+    events.push(NodeEvent::Set(Location::VariableLoc("num".to_string()), Value::I32Val(23)));
+    events.push(NodeEvent::Set(Location::VariableLoc("num".to_string()), Value::I32Val(24)));
+    events.push(NodeEvent::Set(Location::VariableLoc("num".to_string()), Value::I32Val(25)));
+    events.push(NodeEvent::Set(Location::VariableLoc("display".to_string()), Value::StringVal("CHANGED".to_string())));
+
+    events.into_iter().map(|event| (EventMeta::default(), event)).collect()
 }
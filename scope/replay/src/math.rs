@@ -1,7 +1,7 @@
 use crate::core::*;
 
 pub fn clamp(s: Scalar) -> Scalar {
-    s.max(0.0).min(1.0)
+    s.clamp(0.0, 1.0)
 }
 
 pub fn mix_scalar(s1: Scalar, s2: Scalar, percent: Scalar) -> Scalar {
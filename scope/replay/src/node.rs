@@ -4,12 +4,12 @@ use crate::event::*;
 
 pub type NId = usize;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct NodeIdGenerator {
     next: NId,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub struct Tag {
     id: usize,
 }
@@ -33,6 +33,18 @@ pub struct Node {
 pub enum NodeType {
     MemCell,
     NamedContainer,
+    // The header of a function call's variables.
+    Frame,
+    // The header of the lane of a thread's variables.
+    Thread,
+}
+
+// Where a node falls in an array displayed as a table.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum TableRole {
+    Table,
+    Row,
+    Field,
 }
 
 impl Node {
@@ -80,6 +92,29 @@ impl Node {
         self.set_value(Value::UndefinedVal);
     }
 
+    pub fn new_frame(id: NId) -> Node {
+        Node::new_header(id, NodeType::Frame)
+    }
+
+    pub fn new_thread(id: NId) -> Node {
+        Node::new_header(id, NodeType::Thread)
+    }
+
+    fn new_header(id: NId, node_type: NodeType) -> Node {
+        Node {
+            id,
+            node_type,
+            parent_id: None,
+            index: 0,
+            children: Vec::new(),
+            num_child_values: 0,
+            tags: Vec::new(),
+            value: Value::UndefinedVal,
+            is_complex: false,
+            display_type: DisplayType::Default,
+        }
+    }
+
     pub fn id(&self) -> NId {
         self.id
     }
@@ -140,7 +175,10 @@ impl Node {
 
     pub fn should_show_value(&self) -> bool {
         match self.display_type {
-            DisplayType::Default => self.is_always_simple() && self.value != Value::UndefinedVal,
+            DisplayType::Default
+            | DisplayType::Table => {
+                (self.is_always_simple() || self.has_simple_value()) && self.value != Value::UndefinedVal
+            }
             DisplayType::Tree => true,
         }
     }
@@ -153,14 +191,48 @@ impl Node {
         self.is_complex
     }
 
+    // An array or record set to a simple value, like a record set to a
+    // number, has no elements left and shows the value instead.
+    pub fn has_simple_value(&self) -> bool {
+        self.num_children() == 0 && self.value.is_simple() && self.value != Value::UndefinedVal
+    }
+
     pub fn set_complex(&mut self) {
         self.is_complex = true;
     }
 
+    // Frames and threads only show a header above their variables.
+    pub fn is_header(&self) -> bool {
+        matches!(self.node_type, NodeType::Frame | NodeType::Thread)
+    }
+
     pub fn has_index_label(&self) -> bool {
         self.parent_id.is_some()
     }
 
+    // Tags in the order they were added.
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    // Returns false when the node already has the tag.
+    pub fn add_tag(&mut self, tag: Tag) -> bool {
+        if self.tags.contains(&tag) {
+            return false;
+        }
+        self.tags.push(tag);
+
+        true
+    }
+
+    // Returns false when the node doesn't have the tag.
+    pub fn remove_tag(&mut self, tag: Tag) -> bool {
+        let len = self.tags.len();
+        self.tags.retain(|t| *t != tag);
+
+        self.tags.len() != len
+    }
+
     pub fn display_type(&self) -> DisplayType {
         self.display_type
     }
@@ -170,11 +242,22 @@ impl Node {
     }
 }
 
+impl Tag {
+    pub fn new(id: usize) -> Tag {
+        Tag { id }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+}
+
 impl NodeIdGenerator {
     pub fn new() -> NodeIdGenerator {
         NodeIdGenerator { next: 0 }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> NId {
         let cur = self.next;
         let n = self.next.checked_add(1).expect("NodeIdGenerator::next: integer overflow");
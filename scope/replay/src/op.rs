@@ -1,5 +1,5 @@
 use crate::event::Value;
-use crate::node::NId;
+use crate::node::{NId, Tag};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub enum StepDirection {
@@ -14,8 +14,16 @@ pub enum OpStep {
     Define { id: NId },
     Forget { id: NId },
     Set { id: NId, value: Value },
+    // Like Set, but the value comes from another node.
+    Copy { id: NId, from_id: NId, value: Value },
     Push { id: NId, child_id: NId, value: Value },
     Pop { id: NId },
+    Insert { id: NId, child_id: NId, index: usize, value: Value },
+    Remove { id: NId, index: usize },
+    // Exchanges the places of two array elements.
+    Swap { a: NId, b: NId },
+    Tag { id: NId, tag: Tag },
+    Untag { id: NId, tag: Tag },
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -34,6 +42,13 @@ impl Op {
         }
     }
     
+    // Whether the op changes a node's value, its elements, or whether it's
+    // there.  Pops and removes don't say which node they take out, but
+    // undoing them puts it back, so both ways are looked at.
+    pub fn modifies(&self, node_id: NId) -> bool {
+        self.forward.modifies(node_id) || self.reverse.modifies(node_id)
+    }
+
     pub fn step_in(&self, direction: StepDirection) -> &OpStep {
         match direction {
             StepDirection::Forward => &self.forward,
@@ -70,9 +85,15 @@ impl OpStep {
             }
             OpStep::Define { id } => OpStep::Forget { id: *id },
             OpStep::Forget { id } => OpStep::Define { id: *id },
-            OpStep::Set { .. } => panic!("OpStep is not reversible without more information: {:?}", self),
+            OpStep::Set { .. }
+            | OpStep::Copy { .. } => panic!("OpStep is not reversible without more information: {:?}", self),
             OpStep::Push { id, .. } => OpStep::Pop { id: *id },
             OpStep::Pop { .. } => panic!("OpStep is not reversible without more information: {:?}", self),
+            OpStep::Insert { id, index, .. } => OpStep::Remove { id: *id, index: *index },
+            OpStep::Remove { .. } => panic!("OpStep is not reversible without more information: {:?}", self),
+            OpStep::Swap { a, b } => OpStep::Swap { a: *a, b: *b },
+            OpStep::Tag { id, tag } => OpStep::Untag { id: *id, tag: *tag },
+            OpStep::Untag { id, tag } => OpStep::Tag { id: *id, tag: *tag },
         }
     }
 
@@ -91,8 +112,14 @@ impl OpStep {
             OpStep::Define { .. }
             | OpStep::Forget { .. }
             | OpStep::Set { .. }
+            | OpStep::Copy { .. }
             | OpStep::Push { .. }
-            | OpStep::Pop { .. } => {
+            | OpStep::Pop { .. }
+            | OpStep::Insert { .. }
+            | OpStep::Remove { .. }
+            | OpStep::Swap { .. }
+            | OpStep::Tag { .. }
+            | OpStep::Untag { .. } => {
                 match step {
                     OpStep::Atomic { mut steps } => {
                         steps.insert(0, self);
@@ -105,6 +132,24 @@ impl OpStep {
         }
     }
 
+    pub fn modifies(&self, node_id: NId) -> bool {
+        match self {
+            OpStep::Atomic { steps } => steps.iter().any(|step| step.modifies(node_id)),
+            OpStep::Define { id }
+            | OpStep::Forget { id }
+            | OpStep::Set { id, .. }
+            | OpStep::Copy { id, .. } => *id == node_id,
+            OpStep::Push { id, child_id, .. }
+            | OpStep::Insert { id, child_id, .. } => *id == node_id || *child_id == node_id,
+            OpStep::Pop { id }
+            | OpStep::Remove { id, .. } => *id == node_id,
+            OpStep::Swap { a, b } => *a == node_id || *b == node_id,
+            OpStep::NoOp
+            | OpStep::Tag { .. }
+            | OpStep::Untag { .. } => false,
+        }
+    }
+
     pub fn is_no_op(&self) -> bool {
         *self == OpStep::NoOp
     }
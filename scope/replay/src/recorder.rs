@@ -0,0 +1,601 @@
+// Records events from a running Rust program, for replaying them later.
+//
+//     replay::recorder::record_to_file("run.trace")?;
+//     let mut stack = Vec::new();
+//     replay::scope_push!(stack, 3);
+//     let mut top = replay::scope_pop!(stack);
+//     replay::scope_set!(top = Some(4));
+//
+// Values changed by other code can be recorded whole after each change with
+// scope_snapshot!, which records just what changed since the last snapshot.
+//
+// Structs and enums can be recorded by deriving ToValue, which is re-exported
+// here from replay-derive.
+//
+// The macros only record when the crate's `record` feature is enabled.
+// Otherwise they do just what the code they stand for does, so recording can
+// be left in without costing anything.  Programs that only record can depend
+// on the crate with `default-features = false` to leave out the viewer's
+// window and its graphics dependencies.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{self, BufWriter, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::diff::{diff_events, push_events, set_events};
+use crate::event::*;
+use crate::state::AppState;
+
+pub use replay_derive::ToValue;
+
+// The derive's display types are checked when it's compiled.  Its other
+// uses are tested in tests/derive.rs.
+/// ```compile_fail
+/// #[derive(replay::recorder::ToValue)]
+/// struct Tree {
+///     #[scope(display = "graph")]
+///     children: Vec<i32>,
+/// }
+/// ```
+///
+/// ```
+/// #[derive(replay::recorder::ToValue)]
+/// struct Tree {
+///     #[scope(display = "tree")]
+///     children: Vec<i32>,
+/// }
+/// ```
+#[cfg(doctest)]
+pub struct UnknownDisplayTypes;
+
+// Where recorded events go.
+pub trait Sink: Send {
+    fn record(&mut self, meta: &EventMeta, event: &Event);
+}
+
+// Writes events in the trace format, one per line.
+pub struct WriterSink<W: Write + Send> {
+    writer: W,
+}
+
+// Adds events to a viewer's state in the same process, the way watch mode adds
+// the events appended to a trace file.  They're added when the frame loop next
+// applies the state's queued events, once a frame, so that it's laid out once
+// for all of them.
+pub struct AppStateSink {
+    state: Arc<Mutex<AppState>>,
+}
+
+// What's being recorded to, and the last snapshot of each variable by thread
+// and name, so that the next one can be recorded as just what changed.
+struct Recorder {
+    sink: Box<dyn Sink>,
+    snapshots: HashMap<(Identifier, Identifier), Value>,
+}
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+// Converts host values to the values that events carry.
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+
+    // How to show the fields of the record that to_value makes, for fields
+    // that shouldn't be shown the default way.
+    fn field_display_types(&self) -> Vec<(Identifier, DisplayType)> {
+        Vec::new()
+    }
+}
+
+impl<W: Write + Send> WriterSink<W> {
+    pub fn new(writer: W) -> WriterSink<W> {
+        WriterSink { writer }
+    }
+}
+
+impl<W: Write + Send> Sink for WriterSink<W> {
+    fn record(&mut self, meta: &EventMeta, event: &Event) {
+        // Flush every line so that a trace being watched is always complete.
+        writeln!(self.writer, "{}{}", meta, event)
+            .and_then(|_| self.writer.flush())
+            .unwrap_or_else(|e| eprintln!("Failed to record event: {}", e));
+    }
+}
+
+impl AppStateSink {
+    pub fn new(state: Arc<Mutex<AppState>>) -> AppStateSink {
+        AppStateSink { state }
+    }
+}
+
+impl Sink for AppStateSink {
+    fn record(&mut self, meta: &EventMeta, event: &Event) {
+        let mut state = self.state.lock().expect("AppState lock poisoned");
+        state.queue_event(meta.clone(), event.clone());
+    }
+}
+
+// Replaces where events are recorded to.
+pub fn record_to<S: Sink + 'static>(sink: S) {
+    *RECORDER.lock().expect("Recorder lock poisoned") = Some(Recorder {
+        sink: Box::new(sink),
+        snapshots: HashMap::new(),
+    });
+}
+
+pub fn record_to_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let file = File::create(path)?;
+    record_to(WriterSink::new(BufWriter::new(file)));
+
+    Ok(())
+}
+
+pub fn record_to_socket<A: ToSocketAddrs>(addr: A) -> io::Result<()> {
+    let stream = TcpStream::connect(addr)?;
+    record_to(WriterSink::new(stream));
+
+    Ok(())
+}
+
+pub fn record_to_state(state: Arc<Mutex<AppState>>) {
+    record_to(AppStateSink::new(state));
+}
+
+pub fn stop_recording() {
+    *RECORDER.lock().expect("Recorder lock poisoned") = None;
+}
+
+// Records an event from a place in the code, on the current thread and at the
+// current time.  Events are dropped when nothing is being recorded to.
+pub fn record(event: Event, file: &str, line: u32, column: u32) {
+    record_at(event, Some(source_location(file, line, column)));
+}
+
+// Like record, for events that may not come from a known place in the code.
+pub fn record_at(event: Event, location: Option<SourceLocation>) {
+    let mut recorder = RECORDER.lock().expect("Recorder lock poisoned");
+    if let Some(recorder) = recorder.as_mut() {
+        recorder.sink.record(&meta_now(location), &event);
+    }
+}
+
+// Like record_at, for an event of another thread than the one recording it.
+pub fn record_on(thread: Identifier, event: Event, location: Option<SourceLocation>) {
+    let mut recorder = RECORDER.lock().expect("Recorder lock poisoned");
+    if let Some(recorder) = recorder.as_mut() {
+        let meta = EventMeta { thread: Some(thread), ..meta_now(location) };
+        recorder.sink.record(&meta, &event);
+    }
+}
+
+// Records a variable's whole value as the events that change its last
+// snapshot into it, so that arrays are shown changing element by element.
+pub fn record_snapshot<T: ToValue + ?Sized>(name: &str, value: &T, file: &str, line: u32, column: u32) {
+    let key = (thread_name(), name.to_string());
+    // Snapshots are by thread, so the last one can be taken out while the new
+    // one is diffed, which leaves other threads free to record meanwhile.
+    let from = match RECORDER.lock().expect("Recorder lock poisoned").as_mut() {
+        None => return,
+        Some(recorder) => recorder.snapshots.remove(&key).unwrap_or(Value::UndefinedVal),
+    };
+    let meta = meta_now(Some(source_location(file, line, column)));
+    let loc = Location::VariableLoc(name.to_string());
+    let host_value = value;
+    let value = value.to_value();
+    let mut events = diff_events(&loc, &from, &value);
+    // Fields set anew, like a variable's first snapshot, are shown the way
+    // the type asks.  Fields that only change keep how they're shown.
+    if events.iter().any(|event| matches!(event, Event::Set(set_loc, _) if *set_loc == loc)) {
+        events.extend(field_display_events(&loc, host_value));
+    }
+    let mut recorder = RECORDER.lock().expect("Recorder lock poisoned");
+    if let Some(recorder) = recorder.as_mut() {
+        for event in events.iter() {
+            recorder.sink.record(&meta, event);
+        }
+        recorder.snapshots.insert(key, value);
+    }
+}
+
+fn meta_now(location: Option<SourceLocation>) -> EventMeta {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).ok();
+
+    EventMeta { thread: Some(thread_name()), time, location }
+}
+
+fn source_location(file: &str, line: u32, column: u32) -> SourceLocation {
+    SourceLocation {
+        file: file.to_string(),
+        line: line as usize,
+        column: Some(column as usize),
+    }
+}
+
+// Records setting a variable, and how to show its fields.  Arrays in the
+// value are recorded element by element, so that each element gets a cell.
+pub fn record_set<T: ToValue + ?Sized>(name: &str, value: &T, file: &str, line: u32, column: u32) {
+    let loc = Location::VariableLoc(name.to_string());
+    for event in set_events(&loc, &value.to_value()) {
+        record(event, file, line, column);
+    }
+    record_field_display_types(&loc, value, file, line, column);
+}
+
+// Records pushing a value onto an array variable of the given length.
+pub fn record_push<T: ToValue + ?Sized>(name: &str, len: usize, value: &T, file: &str, line: u32, column: u32) {
+    let loc = Location::VariableLoc(name.to_string());
+    for event in push_events(&loc, len, &value.to_value()) {
+        record(event, file, line, column);
+    }
+    let element_loc = Location::IndexLoc(Box::new(loc), len);
+    record_field_display_types(&element_loc, value, file, line, column);
+}
+
+fn record_field_display_types<T: ToValue + ?Sized>(loc: &Location, value: &T, file: &str, line: u32, column: u32) {
+    for event in field_display_events(loc, value) {
+        record(event, file, line, column);
+    }
+}
+
+fn field_display_events<T: ToValue + ?Sized>(loc: &Location, value: &T) -> Vec<Event> {
+    value.field_display_types().into_iter().map(|(field, display_type)| {
+        Event::Display(Location::FieldLoc(Box::new(loc.clone()), field), display_type)
+    }).collect()
+}
+
+// The thread's name, or its id when it has none, made safe for the trace
+// format.
+pub(crate) fn thread_name() -> String {
+    let current = thread::current();
+    let name = match current.name() {
+        Some(name) => name.to_string(),
+        None => format!("{:?}", current.id()),
+    };
+
+    name.chars().filter(|c| *c != ')').map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+// Records a variable's value, after assigning it when given a new one.
+#[cfg(feature = "record")]
+#[macro_export]
+macro_rules! scope_set {
+    ($var:ident) => {
+        $crate::recorder::record_set(stringify!($var), &$var, file!(), line!(), column!())
+    };
+    ($var:ident = $value:expr) => {{
+        $var = $value;
+        $crate::scope_set!($var)
+    }};
+}
+
+#[cfg(not(feature = "record"))]
+#[macro_export]
+macro_rules! scope_set {
+    ($var:ident) => {{
+        let _ = &$var;
+    }};
+    ($var:ident = $value:expr) => {{
+        $var = $value;
+        let _ = &$var;
+    }};
+}
+
+// Pushes a value onto a Vec and records it.
+#[cfg(feature = "record")]
+#[macro_export]
+macro_rules! scope_push {
+    ($vec:ident, $value:expr) => {{
+        let value = $value;
+        $crate::recorder::record_push(stringify!($vec), $vec.len(), &value, file!(), line!(), column!());
+        $vec.push(value)
+    }};
+}
+
+#[cfg(not(feature = "record"))]
+#[macro_export]
+macro_rules! scope_push {
+    ($vec:ident, $value:expr) => {
+        $vec.push($value)
+    };
+}
+
+// Pops a value off of a Vec and records it.  Evaluates to the value popped.
+#[cfg(feature = "record")]
+#[macro_export]
+macro_rules! scope_pop {
+    ($vec:ident) => {{
+        $crate::recorder::record(
+            $crate::event::Event::Pop($crate::event::Location::VariableLoc(stringify!($vec).to_string())),
+            file!(), line!(), column!());
+        $vec.pop()
+    }};
+}
+
+#[cfg(not(feature = "record"))]
+#[macro_export]
+macro_rules! scope_pop {
+    ($vec:ident) => {
+        $vec.pop()
+    };
+}
+
+// Records a variable's value as just what changed since the last snapshot of
+// it.  Variables recorded this way shouldn't also be changed with the other
+// macros, since the snapshot wouldn't know about it.
+#[cfg(feature = "record")]
+#[macro_export]
+macro_rules! scope_snapshot {
+    ($var:ident) => {
+        $crate::recorder::record_snapshot(stringify!($var), &$var, file!(), line!(), column!())
+    };
+}
+
+#[cfg(not(feature = "record"))]
+#[macro_export]
+macro_rules! scope_snapshot {
+    ($var:ident) => {{
+        let _ = &$var;
+    }};
+}
+
+macro_rules! impl_to_value {
+    ($variant:ident as $as_type:ty: $($host_type:ty),*) => {
+        $(
+            impl ToValue for $host_type {
+                fn to_value(&self) -> Value {
+                    Value::$variant(*self as $as_type)
+                }
+            }
+        )*
+    };
+}
+
+impl_to_value!(I32Val as i32: i8, i16, i32, u8, u16);
+impl_to_value!(I64Val as i64: i64, u32, isize);
+impl_to_value!(U64Val as u64: u64, usize);
+impl_to_value!(F64Val as f64: f32, f64);
+
+impl ToValue for bool {
+    fn to_value(&self) -> Value {
+        Value::BoolVal(*self)
+    }
+}
+
+impl ToValue for char {
+    fn to_value(&self) -> Value {
+        Value::CharVal(*self)
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Value {
+        Value::StringVal(self.to_string())
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::StringVal(self.clone())
+    }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Value {
+        (**self).to_value()
+    }
+
+    fn field_display_types(&self) -> Vec<(Identifier, DisplayType)> {
+        (**self).field_display_types()
+    }
+}
+
+impl<T: ToValue> ToValue for [T] {
+    fn to_value(&self) -> Value {
+        Value::ArrayVal(self.iter().map(ToValue::to_value).collect())
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        self.as_slice().to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Value {
+        match self {
+            None => Value::NilVal,
+            Some(value) => value.to_value(),
+        }
+    }
+
+    fn field_display_types(&self) -> Vec<(Identifier, DisplayType)> {
+        match self {
+            None => Vec::new(),
+            Some(value) => value.field_display_types(),
+        }
+    }
+}
+
+// Maps become arrays of entries, sorted so that the order doesn't change from
+// run to run.
+impl<K: ToValue, V: ToValue, S: BuildHasher> ToValue for HashMap<K, V, S> {
+    fn to_value(&self) -> Value {
+        let mut entries: Vec<Value> = self.iter().map(|(key, value)| {
+            Value::RecordVal(Some("Entry".to_string()), vec![
+                ("key".to_string(), key.to_value()),
+                ("value".to_string(), value.to_value()),
+            ])
+        }).collect();
+        entries.sort_by(|a, b| match (a, b) {
+            (Value::RecordVal(_, a_fields), Value::RecordVal(_, b_fields)) => compare_keys(&a_fields[0].1, &b_fields[0].1),
+            _ => Ordering::Equal,
+        });
+
+        Value::ArrayVal(entries)
+    }
+}
+
+// Orders map keys by their values, so that numbers are in numeric order.
+// The keys of a map are all of one type, so keys of different kinds are
+// only ordered by how they're shown.
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::BoolVal(a), Value::BoolVal(b)) => a.cmp(b),
+        (Value::BytesVal(a), Value::BytesVal(b)) => a.cmp(b),
+        (Value::CharVal(a), Value::CharVal(b)) => a.cmp(b),
+        (Value::I32Val(a), Value::I32Val(b)) => a.cmp(b),
+        (Value::I64Val(a), Value::I64Val(b)) => a.cmp(b),
+        (Value::U64Val(a), Value::U64Val(b)) => a.cmp(b),
+        (Value::F64Val(a), Value::F64Val(b)) => a.total_cmp(b),
+        (Value::StringVal(a), Value::StringVal(b)) => a.cmp(b),
+        (Value::ArrayVal(a), Value::ArrayVal(b)) => {
+            a.iter().zip(b.iter()).map(|(a, b)| compare_keys(a, b)).find(|order| order.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len()))
+        }
+        (Value::RecordVal(_, a), Value::RecordVal(_, b)) => {
+            a.iter().zip(b.iter()).map(|((_, a), (_, b))| compare_keys(a, b)).find(|order| order.is_ne())
+                .unwrap_or_else(|| a.len().cmp(&b.len()))
+        }
+        _ => a.display_string().cmp(&b.display_string()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::state::tests::state_for_trace;
+
+    // Recording goes to one place for the whole process, so the tests that
+    // record take turns.
+    pub(crate) static RECORDING: Mutex<()> = Mutex::new(());
+
+    struct EventsSink {
+        events: Arc<Mutex<Vec<Event>>>,
+    }
+
+    impl Sink for EventsSink {
+        fn record(&mut self, _meta: &EventMeta, event: &Event) {
+            self.events.lock().expect("Events lock poisoned").push(event.clone());
+        }
+    }
+
+    // The events recorded while running a function.
+    pub(crate) fn record_events<F: FnOnce()>(f: F) -> Vec<Event> {
+        let _turn = RECORDING.lock().unwrap_or_else(|e| e.into_inner());
+        let events = Arc::new(Mutex::new(Vec::new()));
+        record_to(EventsSink { events: events.clone() });
+        f();
+        stop_recording();
+        let events = events.lock().expect("Events lock poisoned").clone();
+
+        events
+    }
+
+    fn new_state() -> Arc<Mutex<AppState>> {
+        Arc::new(Mutex::new(state_for_trace("")))
+    }
+
+    #[test]
+    fn events_recorded_to_a_state_are_added_when_the_queue_is_applied() {
+        let state = new_state();
+        let mut sink = AppStateSink::new(state.clone());
+        let arr = Location::VariableLoc("arr".to_string());
+        sink.record(&EventMeta::default(), &Event::Push(arr.clone(), Value::I32Val(1)));
+        sink.record(&EventMeta::default(), &Event::Push(arr, Value::I32Val(2)));
+
+        let mut state = state.lock().expect("AppState lock poisoned");
+        assert_eq!(state.ops.len(), 1);
+        state.update_playback(0.0);
+        assert_eq!(state.ops.len(), 1);
+        state.apply_queued_events();
+        assert_eq!(state.ops.len(), 3);
+        assert_eq!(state.draw_states.len(), state.graph.nodes_iter().count());
+        assert_eq!(state.positions.len(), state.draw_states.len());
+    }
+
+    #[test]
+    fn arrays_are_recorded_element_by_element() {
+        let events = record_events(|| {
+            record_set("v", &vec![vec![1], vec![]], "main.rs", 1, 1);
+            record_push("v", 2, &vec![2], "main.rs", 2, 1);
+        });
+        let v = Location::VariableLoc("v".to_string());
+        let v_at = |i| Location::IndexLoc(Box::new(v.clone()), i);
+        assert_eq!(events, vec![
+            Event::Set(v.clone(), Value::ArrayVal(Vec::new())),
+            Event::Push(v.clone(), Value::ArrayVal(Vec::new())),
+            Event::Push(v_at(0), Value::I32Val(1)),
+            Event::Push(v.clone(), Value::ArrayVal(Vec::new())),
+            Event::Push(v.clone(), Value::ArrayVal(Vec::new())),
+            Event::Push(v_at(2), Value::I32Val(2)),
+        ]);
+
+        // Each element is a cell that later events can use.
+        let state = new_state();
+        let mut state = state.lock().expect("AppState lock poisoned");
+        let mut events: Vec<(EventMeta, Event)> = events.into_iter().map(|event| (EventMeta::default(), event)).collect();
+        events.push((EventMeta::default(), Event::Set(v_at(1), Value::I32Val(3))));
+        events.push((EventMeta::default(), Event::Set(v.clone(), Value::ArrayVal(Vec::new()))));
+        events.push((EventMeta::default(), Event::Push(v, Value::I32Val(4))));
+        state.append_events(events, None);
+        let v_id = state.graph.nodes_iter().find(|node| state.graph.node_name(node) == Some("v")).expect("No v").id();
+        let num_ops = state.ops.len();
+        state.player_go_to(num_ops - 3);
+        assert_eq!(state.graph.full_value(v_id).display_string(), "[[1], 3, [2]]");
+        state.player_go_to(num_ops - 1);
+        assert_eq!(state.graph.full_value(v_id), Value::ArrayVal(vec![Value::I32Val(4)]));
+        state.player_go_to(num_ops - 3);
+        assert_eq!(state.graph.full_value(v_id).display_string(), "[[1], 3, [2]]");
+    }
+
+    struct Tree {
+        key: i32,
+        children: Vec<i32>,
+    }
+
+    impl ToValue for Tree {
+        fn to_value(&self) -> Value {
+            Value::RecordVal(Some("Tree".to_string()), vec![
+                ("key".to_string(), self.key.to_value()),
+                ("children".to_string(), self.children.to_value()),
+            ])
+        }
+
+        fn field_display_types(&self) -> Vec<(Identifier, DisplayType)> {
+            vec![("children".to_string(), DisplayType::Tree)]
+        }
+    }
+
+    #[test]
+    fn snapshots_show_fields_the_way_the_type_asks() {
+        let events = record_events(|| {
+            record_snapshot("t", &Tree { key: 1, children: vec![2] }, "main.rs", 1, 1);
+            record_snapshot("t", &Tree { key: 3, children: vec![2] }, "main.rs", 2, 1);
+        });
+        let t = Location::VariableLoc("t".to_string());
+        let children = Location::FieldLoc(Box::new(t.clone()), "children".to_string());
+        let displays: Vec<&Event> = events.iter().filter(|event| matches!(event, Event::Display(..))).collect();
+        assert_eq!(displays, vec![&Event::Display(children, DisplayType::Tree)]);
+        assert_eq!(events.last(), Some(&Event::Set(Location::FieldLoc(Box::new(t), "key".to_string()), Value::I32Val(3))));
+    }
+
+    #[test]
+    fn map_entries_are_in_the_order_of_their_keys() {
+        let map: HashMap<i32, &str> = [(10, "ten"), (9, "nine"), (-1, "minus one"), (100, "hundred")].iter().copied().collect();
+        let keys: Vec<Value> = match map.to_value() {
+            Value::ArrayVal(entries) => entries.into_iter().map(|entry| match entry {
+                Value::RecordVal(_, mut fields) => fields.remove(0).1,
+                entry => panic!("Entry isn't a record: {:?}", entry),
+            }).collect(),
+            value => panic!("Map isn't an array: {:?}", value),
+        };
+        assert_eq!(keys, [-1, 9, 10, 100].iter().map(|n| Value::I32Val(*n)).collect::<Vec<_>>());
+        assert_eq!(compare_keys(&Value::StringVal("b".to_string()), &Value::StringVal("ab".to_string())), Ordering::Greater);
+    }
+}
@@ -0,0 +1,300 @@
+#[cfg(feature = "gui")]
+extern crate gfx_device_gl;
+
+#[cfg(feature = "gui")]
+use gfx_device_gl::Device;
+#[cfg(feature = "gui")]
+use piston_window::*;
+#[cfg(feature = "gui")]
+use piston_window::character::CharacterCache;
+#[cfg(feature = "gui")]
+use piston_window::glyph_cache::rusttype::GlyphCache;
+
+use crate::core::*;
+
+#[cfg(feature = "gui")]
+pub type Glyphs<'font> = GlyphCache<'font, G2dTextureContext, G2dTexture>;
+
+// Rectangles are [x, y, width, height].
+pub type Rect = [Scalar; 4];
+
+// Everything the scene needs from a graphics backend.  Text positions are the
+// left end of the baseline.
+pub trait Renderer {
+    fn draw_rect(&mut self, color: Color, rect: Rect);
+    fn draw_text(&mut self, color: Color, font_size: FontSize, text: &str, pos: Vec2d);
+    fn measure_text(&mut self, font_size: FontSize, text: &str) -> Scalar;
+    fn draw_line(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d);
+    fn draw_arrow(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d);
+    // Restricts drawing to a rectangle, or removes the restriction when None.
+    fn set_clip(&mut self, clip: Option<Rect>);
+}
+
+#[cfg(feature = "gui")]
+pub struct PistonRenderer<'a, 'g, 'font> {
+    ctx: Context,
+    g: &'a mut G2d<'g>,
+    glyphs: &'a mut Glyphs<'font>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DrawCommand {
+    Rect { color: Color, rect: Rect },
+    Text { color: Color, font_size: FontSize, text: String, pos: Vec2d },
+    Line { color: Color, width: Scalar, from: Vec2d, to: Vec2d },
+    Arrow { color: Color, width: Scalar, from: Vec2d, to: Vec2d },
+    Clip { clip: Option<Rect> },
+}
+
+// A headless backend that records what would have been drawn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordingRenderer {
+    pub commands: Vec<DrawCommand>,
+    // Width of a character relative to the font size.  The default matches
+    // the advance width of Liberation Mono at the scale the glyph cache uses.
+    pub char_width_factor: Scalar,
+}
+
+// Draws into a part of another renderer's window as though it were the whole
+// window, like one side of a split view.  Drawing is clipped to the part.
+pub struct ViewportRenderer<'a, R: Renderer> {
+    inner: &'a mut R,
+    viewport: Rect,
+}
+
+// Draws through another renderer, scaled and then moved, like through a
+// camera.  Sizes are measured as they'd be before scaling.
+pub struct TransformRenderer<'a, R: Renderer> {
+    inner: &'a mut R,
+    offset: Vec2d,
+    scale: Scalar,
+}
+
+#[cfg(feature = "gui")]
+const ARROW_HEAD_LENGTH: Scalar = 6.0;
+
+#[cfg(feature = "gui")]
+impl<'a, 'g, 'font> PistonRenderer<'a, 'g, 'font> {
+    pub fn new(ctx: Context, g: &'a mut G2d<'g>, glyphs: &'a mut Glyphs<'font>) -> PistonRenderer<'a, 'g, 'font> {
+        PistonRenderer { ctx, g, glyphs }
+    }
+
+    // Update glyphs before rendering.
+    pub fn flush(self, device: &mut Device) {
+        self.glyphs.factory.encoder.flush(device);
+    }
+}
+
+#[cfg(feature = "gui")]
+impl<'a, 'g, 'font> Renderer for PistonRenderer<'a, 'g, 'font> {
+    fn draw_rect(&mut self, color: Color, rect: Rect) {
+        piston_window::rectangle(color, rect, self.ctx.transform, self.g);
+    }
+
+    fn draw_text(&mut self, color: Color, font_size: FontSize, text: &str, pos: Vec2d) {
+        let transform = self.ctx.transform.trans(pos[0], pos[1]);
+        Text::new_color(color, font_size).draw(text,
+                                               self.glyphs,
+                                               &self.ctx.draw_state,
+                                               transform,
+                                               self.g).expect("Draw text failed");
+    }
+
+    fn measure_text(&mut self, font_size: FontSize, text: &str) -> Scalar {
+        self.glyphs.width(font_size, text).expect("Measure text failed")
+    }
+
+    fn draw_line(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d) {
+        piston_window::line_from_to(color, width / 2.0, from, to, self.ctx.transform, self.g);
+    }
+
+    fn draw_arrow(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d) {
+        self.draw_line(color, width, from, to);
+        for head in arrow_head(from, to).iter() {
+            self.draw_line(color, width, to, *head);
+        }
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.ctx.draw_state = match clip {
+            None => DrawState::default(),
+            Some(rect) => {
+                let to_pixels = |s: Scalar| s.max(0.0).round() as u32;
+                DrawState::default().scissor([to_pixels(rect[0]),
+                                              to_pixels(rect[1]),
+                                              to_pixels(rect[2]),
+                                              to_pixels(rect[3])])
+            }
+        };
+    }
+}
+
+impl RecordingRenderer {
+    pub fn new() -> RecordingRenderer {
+        RecordingRenderer {
+            commands: Vec::new(),
+            char_width_factor: 0.5297,
+        }
+    }
+}
+
+impl Default for RecordingRenderer {
+    fn default() -> RecordingRenderer {
+        RecordingRenderer::new()
+    }
+}
+
+impl Renderer for RecordingRenderer {
+    fn draw_rect(&mut self, color: Color, rect: Rect) {
+        self.commands.push(DrawCommand::Rect { color, rect });
+    }
+
+    fn draw_text(&mut self, color: Color, font_size: FontSize, text: &str, pos: Vec2d) {
+        self.commands.push(DrawCommand::Text { color, font_size, text: text.to_string(), pos });
+    }
+
+    fn measure_text(&mut self, font_size: FontSize, text: &str) -> Scalar {
+        text.chars().count() as Scalar * font_size as Scalar * self.char_width_factor
+    }
+
+    fn draw_line(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d) {
+        self.commands.push(DrawCommand::Line { color, width, from, to });
+    }
+
+    fn draw_arrow(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d) {
+        self.commands.push(DrawCommand::Arrow { color, width, from, to });
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        self.commands.push(DrawCommand::Clip { clip });
+    }
+}
+
+impl<'a, R: Renderer> ViewportRenderer<'a, R> {
+    pub fn new(inner: &'a mut R, viewport: Rect) -> ViewportRenderer<'a, R> {
+        inner.set_clip(Some(viewport));
+
+        ViewportRenderer { inner, viewport }
+    }
+
+    // Stops clipping to the viewport.
+    pub fn finish(self) {
+        self.inner.set_clip(None);
+    }
+
+    fn to_window(&self, pos: Vec2d) -> Vec2d {
+        [pos[0] + self.viewport[0], pos[1] + self.viewport[1]]
+    }
+}
+
+impl<'a, R: Renderer> Renderer for ViewportRenderer<'a, R> {
+    fn draw_rect(&mut self, color: Color, rect: Rect) {
+        let pos = self.to_window([rect[0], rect[1]]);
+        self.inner.draw_rect(color, [pos[0], pos[1], rect[2], rect[3]]);
+    }
+
+    fn draw_text(&mut self, color: Color, font_size: FontSize, text: &str, pos: Vec2d) {
+        let pos = self.to_window(pos);
+        self.inner.draw_text(color, font_size, text, pos);
+    }
+
+    fn measure_text(&mut self, font_size: FontSize, text: &str) -> Scalar {
+        self.inner.measure_text(font_size, text)
+    }
+
+    fn draw_line(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d) {
+        let (from, to) = (self.to_window(from), self.to_window(to));
+        self.inner.draw_line(color, width, from, to);
+    }
+
+    fn draw_arrow(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d) {
+        let (from, to) = (self.to_window(from), self.to_window(to));
+        self.inner.draw_arrow(color, width, from, to);
+    }
+
+    // Clips to the part of the rectangle inside the viewport.
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        let clip = match clip {
+            None => self.viewport,
+            Some(rect) => {
+                let [vx, vy, vw, vh] = self.viewport;
+                let pos = self.to_window([rect[0], rect[1]]);
+                let left = pos[0].max(vx);
+                let top = pos[1].max(vy);
+                let right = (pos[0] + rect[2]).min(vx + vw);
+                let bottom = (pos[1] + rect[3]).min(vy + vh);
+                [left, top, (right - left).max(0.0), (bottom - top).max(0.0)]
+            }
+        };
+        self.inner.set_clip(Some(clip));
+    }
+}
+
+impl<'a, R: Renderer> TransformRenderer<'a, R> {
+    pub fn new(inner: &'a mut R, offset: Vec2d, scale: Scalar) -> TransformRenderer<'a, R> {
+        TransformRenderer { inner, offset, scale }
+    }
+
+    fn transform(&self, pos: Vec2d) -> Vec2d {
+        [pos[0] * self.scale + self.offset[0], pos[1] * self.scale + self.offset[1]]
+    }
+
+    fn transform_rect(&self, rect: Rect) -> Rect {
+        let pos = self.transform([rect[0], rect[1]]);
+
+        [pos[0], pos[1], rect[2] * self.scale, rect[3] * self.scale]
+    }
+
+    fn scale_font_size(&self, font_size: FontSize) -> FontSize {
+        (font_size as Scalar * self.scale).round().max(1.0) as FontSize
+    }
+}
+
+impl<'a, R: Renderer> Renderer for TransformRenderer<'a, R> {
+    fn draw_rect(&mut self, color: Color, rect: Rect) {
+        let rect = self.transform_rect(rect);
+        self.inner.draw_rect(color, rect);
+    }
+
+    fn draw_text(&mut self, color: Color, font_size: FontSize, text: &str, pos: Vec2d) {
+        let (font_size, pos) = (self.scale_font_size(font_size), self.transform(pos));
+        self.inner.draw_text(color, font_size, text, pos);
+    }
+
+    fn measure_text(&mut self, font_size: FontSize, text: &str) -> Scalar {
+        let font_size = self.scale_font_size(font_size);
+
+        self.inner.measure_text(font_size, text) / self.scale
+    }
+
+    fn draw_line(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d) {
+        let (from, to) = (self.transform(from), self.transform(to));
+        self.inner.draw_line(color, width * self.scale, from, to);
+    }
+
+    fn draw_arrow(&mut self, color: Color, width: Scalar, from: Vec2d, to: Vec2d) {
+        let (from, to) = (self.transform(from), self.transform(to));
+        self.inner.draw_arrow(color, width * self.scale, from, to);
+    }
+
+    fn set_clip(&mut self, clip: Option<Rect>) {
+        let clip = clip.map(|rect| self.transform_rect(rect));
+        self.inner.set_clip(clip);
+    }
+}
+
+// The two end points of the lines that make up an arrow head at `to`.
+#[cfg(feature = "gui")]
+fn arrow_head(from: Vec2d, to: Vec2d) -> [Vec2d; 2] {
+    let dx = to[0] - from[0];
+    let dy = to[1] - from[1];
+    let len = (dx * dx + dy * dy).sqrt();
+    if len == 0.0 {
+        return [to, to];
+    }
+    let (ux, uy) = (dx / len, dy / len);
+    let back = [to[0] - ux * ARROW_HEAD_LENGTH, to[1] - uy * ARROW_HEAD_LENGTH];
+    let (px, py) = (-uy * ARROW_HEAD_LENGTH / 2.0, ux * ARROW_HEAD_LENGTH / 2.0);
+
+    [[back[0] + px, back[1] + py], [back[0] - px, back[1] - py]]
+}
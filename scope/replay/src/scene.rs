@@ -1,130 +1,707 @@
-extern crate gfx_device_gl;
-
-use gfx_device_gl::Device;
-use piston_window::*;
-
+use crate::camera::CameraMode;
+use crate::compare::*;
 use crate::core::*;
+use crate::font;
+use crate::event::{DisplayType, Value};
 use crate::math;
-use crate::node::{NId, NodeType};
+use crate::node::{NId, TableRole};
+use crate::render::{Rect, Renderer, TransformRenderer, ViewportRenderer};
 use crate::state::*;
 
-const WINDOW_MARGIN_Y: Scalar = 5.0;
-const CELL_MARGIN_X: Scalar = 20.0;
+const TOOLTIP_OFFSET: Scalar = 12.0;
+const TREE_STEM_LENGTH: Scalar = 8.0;
+const TREE_LINE_WIDTH: Scalar = 1.0;
+const POINTER_LINE_WIDTH: Scalar = 1.0;
+const TAG_STRIPE_WIDTH: Scalar = 3.0;
+const FRAME_LINE_WIDTH: Scalar = 1.0;
+const CODE_PANE_PADDING: Scalar = 8.0;
+const CODE_LINE_HEIGHT: Scalar = 16.0;
+const CODE_GUTTER_WIDTH: Scalar = 36.0;
+const CODE_TAB_WIDTH: usize = 4;
+const CHANGE_OUTLINE_WIDTH: Scalar = 1.5;
+const REMOVED_CELL_OPACITY: f32 = 0.5;
+const SELECTION_OUTLINE_WIDTH: Scalar = 2.0;
+const INSPECTOR_LINE_HEIGHT: Scalar = 16.0;
+const INSPECTOR_BUTTON_WIDTH: Scalar = 60.0;
+
+// What the inspector shows, line by line, and where its buttons to go to
+// ops are.
+struct InspectorLayout {
+    panel: Rect,
+    lines: Vec<String>,
+    buttons: Vec<(usize, Rect)>,
+}
+
+// The canvas as the camera shows it, with the status line below it and the
+// code pane and inspector over it.
+pub fn draw<R: Renderer>(state: &AppState, r: &mut R) {
+    let camera = &state.camera;
+    let mut canvas_r = TransformRenderer::new(r, camera.offset, camera.zoom);
+    let hover_text = draw_canvas(state, &mut canvas_r);
+    let legend_bottom = draw_tag_legend(state, &mut canvas_r);
+    // The status line stays in view.
+    let status_y = camera.to_screen([0.0, legend_bottom])[1].min(state.window_size[1] - state.status_line_height);
+    draw_status(state, r, status_y);
+    if state.show_code_pane && state.has_source_locations() {
+        draw_code_pane(state, r);
+    }
+    if let Some(layout) = inspector_layout(state) {
+        draw_inspector(state, r, &layout);
+    }
 
-pub fn draw<'font>(state: &mut AppState<'font>,
-                   ctx: Context,
-                   g: &mut G2d,
-                   device: &mut Device) {
+    if let (Some(text), Some(cursor)) = (hover_text, state.cursor) {
+        draw_tooltip(state, r, &text, cursor);
+    }
+}
 
-    let cell_width = state.cell_width;
+// The cells, headers and pointers.  Returns the full text of the truncated
+// value under the mouse, if any.
+fn draw_canvas<R: Renderer>(state: &AppState, r: &mut R) -> Option<String> {
     let cell_height = state.cell_height;
-    for ((node, position), draw_state) in state.graph.nodes_iter().zip(&state.positions).zip(&state.draw_states) {
-        let opacity_mix_val = math::mix_scalar(draw_state.from_opacity, draw_state.to_opacity, math::quadratic_out(draw_state.mix));
-        if node.should_show_name() {
+    let cursor = world_cursor(state);
+    // Full text of the truncated value under the mouse, if any.
+    let mut hover_text: Option<String> = None;
+    for (node, draw_state) in state.graph.nodes_iter().zip(&state.draw_states) {
+        let opacity_mix_val = draw_state.opacity();
+        let position = state.draw_position(node.id());
+        if node.is_header() {
+            // Thread headers are only placed when showing lanes.
+            if state.grid_coords[node.id()].is_some() {
+                draw_header(state, r, node.id());
+            }
+            continue;
+        }
+        let name = state.graph.node_name(node);
+        if let (true, Some(label_str)) = (node.should_show_name(), name) {
             // Named cell label.
             let x = position[0];
-            let y = position[1] + WINDOW_MARGIN_Y;
-            let label_transform = ctx.transform.trans(x, y + 15.0);
-            let label_str = state.graph.node_name(node).unwrap_or("");
-            let mut name_color = state.cell_label_color.clone();
+            let y = position[1] + state.window_margin_y;
+            let mut name_color = state.cell_label_color;
             name_color[3] = opacity_mix_val as f32;
-            let name_text = Text::new_color(name_color, 12);
-            name_text.draw(label_str,
-                &mut state.glyphs,
-                &ctx.draw_state,
-                label_transform,
-                g).expect("Draw text failed");
-        }
-        if !node.should_show_value() {
-            continue;
+            r.draw_text(name_color, LABEL_FONT_SIZE, label_str, [x, y + 15.0]);
         }
 
-        let mut pos = value_abs_position(&state, node.id());
+        let mut pos = state.value_position(node.id());
+        pos[1] += state.window_margin_y;
 
-        if node.has_index_label() {
-            // Array index.
-            let index_label_width = state.index_label_width;
-
-            let mut label_color = state.cell_label_color.clone();
-            let index_str = node.index().to_string();
+        let table_role = state.graph.table_role(node.id());
+        if table_role == Some(TableRole::Table) && node.num_children() > 0 {
+            draw_table_headers(state, r, node.id());
+        }
+        if let Some(type_name) = state.graph.record_header(node.id()) {
+            let origin = state.children_origin(node.id(), position);
+            let mut header_color = state.cell_label_color;
+            header_color[3] = opacity_mix_val as f32;
+            r.draw_text(header_color, LABEL_FONT_SIZE, type_name, [origin[0], origin[1] + state.window_margin_y + 15.0]);
+        }
+        if node.has_index_label() && table_role != Some(TableRole::Field) {
+            // Array index, right-aligned against the cell.
+            let mut label_color = state.cell_label_color;
+            let index_str = state.graph.index_label(node.id());
             label_color[3] = opacity_mix_val as f32;
-            let text = Text::new_color(label_color, 10);
-            // TODO: This should be right-aligned.
-            let text_offset_x = if index_str.len() <= 1 {
-                0.0
-            } else {
-                -14.0
-            };
-            let transform = ctx.transform.trans(pos[0] + text_offset_x, pos[1] + 14.0);
-            text.draw(&index_str,
-                      &mut state.glyphs,
-                      &ctx.draw_state,
-                      transform,
-                      g).expect("Draw text failed");
-
-            pos[0] += index_label_width;
+            let text_width = r.measure_text(INDEX_FONT_SIZE, &index_str);
+            let x = pos[0] - INDEX_LABEL_MARGIN_X - text_width;
+            r.draw_text(label_color, INDEX_FONT_SIZE, &index_str, [x, pos[1] + 14.0]);
         }
-        // Cell background.
-        let mut bg_color = state.cell_bg_color.clone();
+        if !node.should_show_value() {
+            continue;
+        }
+
+        // Cell background, in the color of its latest tag.  Earlier tags show
+        // as stripes along the left edge.
+        let cell_width = draw_state.cell_width;
+        let tags = state.graph.effective_tags(node.id());
+        let mut bg_color = tags.last().map(|tag| state.tag_color(*tag)).unwrap_or(state.cell_bg_color);
         bg_color[3] = opacity_mix_val as f32;
-        piston_window::rectangle(bg_color, [pos[0], pos[1], cell_width, cell_height], ctx.transform, g);
+        r.draw_rect(bg_color, [pos[0], pos[1], cell_width, cell_height]);
+        for (i, tag) in tags.iter().rev().skip(1).enumerate() {
+            let mut stripe_color = state.tag_color(*tag);
+            stripe_color[3] = opacity_mix_val as f32;
+            let x = pos[0] + i as Scalar * TAG_STRIPE_WIDTH;
+            r.draw_rect(stripe_color, [x, pos[1], TAG_STRIPE_WIDTH, cell_height]);
+        }
+        if node.display_type() == DisplayType::Tree && node.is_ever_complex() {
+            draw_tree_connectors(state, r, node.id(), pos);
+        }
         // Cell value.
-        let mut cell_value_color = state.cell_value_color.clone();
-        let to_str = &draw_state.to_text;
+        let mut cell_value_color = state.cell_value_color;
+        let change_text = match state.cell_change(node.id()) {
+            Some(CellChange::Changed { from, to }) => Some(format!("{} → {}", from, to)),
+            _ => None,
+        };
+        let tree_text;
+        let to_str = if node.display_type() == DisplayType::Tree && node.is_ever_complex() {
+            // The value of a node in a tree is the array or record hanging off
+            // of it.
+            tree_text = match node.value() {
+                Value::RecordVal(Some(type_name), _) => type_name.clone(),
+                Value::RecordVal(None, _) => format!("Record({})", node.num_children()),
+                _ => format!("Array({})", node.num_children()),
+            };
+            &tree_text
+        } else if let Some(change_text) = &change_text {
+            change_text
+        } else {
+            &draw_state.to_text
+        };
         let text_mix_val = if draw_state.from_opacity <= draw_state.to_opacity {
             math::mix_scalar(0.0, draw_state.to_opacity, math::quadratic_out(draw_state.mix))
         } else {
             opacity_mix_val
         };
         cell_value_color[3] = text_mix_val as f32;
-        let text = Text::new_color(cell_value_color, 12);
-        let transform = ctx.transform.trans(pos[0], pos[1] + 15.0);
-        text.draw(&to_str,
-                  &mut state.glyphs,
-                  &ctx.draw_state,
-                  transform,
-                  g).expect("Draw text failed");
-    }
-    // Update glyphs before rendering.
-    state.glyphs.factory.encoder.flush(device);
-}
-
-fn value_abs_position<'a>(state: &AppState<'a>, mut node_id: NId) -> Vec2d {
-    let mut pos: Vec2d = [0.0, WINDOW_MARGIN_Y];
-    // We don't want to include the width of the given node, only of its
-    // parents.
-    let rel_pos = state.positions[node_id];
-    pos[0] += rel_pos[0];
-    pos[1] += rel_pos[1];
-    let label_width = state.draw_states[node_id].label_width;
-    pos[0] += label_width; // Should only be non-zero for NodeType::NamedContainer.
-    let node = &state.graph.node(node_id);
-    match node.parent_id() {
-        None => return pos,
-        Some(parent_id) => {
-            node_id = *parent_id;
-        }
-    }
-    loop {
-        let rel_pos = &state.positions[node_id];
-        pos[0] += rel_pos[0];
-        pos[1] += rel_pos[1];
-        let label_width = state.draw_states[node_id].label_width;
-        pos[0] += label_width; // Should only be non-zero for NodeType::NamedContainer.
-        let node = &state.graph.node(node_id);
-        match node.node_type {
-            NodeType::MemCell => {
-                pos[0] += state.cell_width + CELL_MARGIN_X;
-            },
-            NodeType::NamedContainer => (),
-        }
-        match node.parent_id() {
-            None => break,
-            Some(parent_id) => {
-                node_id = *parent_id;
+        let max_text_width = cell_width - 2.0 * state.cell_padding_x;
+        let text_pos = [pos[0] + state.cell_padding_x, pos[1] + 15.0];
+        match font::truncate_to_width(to_str, max_text_width, |s| r.measure_text(VALUE_FONT_SIZE, s)) {
+            None => r.draw_text(cell_value_color, VALUE_FONT_SIZE, to_str, text_pos),
+            Some(truncated) => {
+                r.draw_text(cell_value_color, VALUE_FONT_SIZE, &truncated, text_pos);
+                if is_inside(cursor, [pos[0], pos[1], cell_width, cell_height]) {
+                    hover_text = Some(change_text.unwrap_or_else(|| draw_state.to_text.clone()));
+                }
+            }
+        }
+        let rect = [pos[0], pos[1], cell_width, cell_height];
+        if state.selected == Some(node.id()) {
+            draw_selection(state, r, rect);
+        } else if let Some(cell_change) = state.cell_change(node.id()) {
+            draw_cell_change(state, r, cell_change, rect);
+        } else if state.mismatches.contains(&node.id()) {
+            draw_outline(r, state.mismatch_color, CHANGE_OUTLINE_WIDTH, rect);
+        }
+    }
+
+    for (var_id, array_id) in state.graph.index_pointers() {
+        draw_index_pointer(state, r, var_id, array_id);
+    }
+    if let Some(selected) = state.selected {
+        // Arrays and records have no cell of their own, so their name is
+        // outlined instead.
+        if !state.graph.node(selected).should_show_value() {
+            if let Some(rect) = name_rect(state, selected) {
+                draw_selection(state, r, rect);
+            }
+        }
+    }
+
+    hover_text
+}
+
+// Where the mouse is on the canvas.
+fn world_cursor(state: &AppState) -> Option<Vec2d> {
+    state.cursor.map(|cursor| state.camera.to_world(cursor))
+}
+
+// Both traces of a comparison side by side, with what op each is at below
+// it, and which step the traces first differ at.
+pub fn draw_comparison<R: Renderer>(comparison: &Comparison, r: &mut R) {
+    let viewports = comparison.viewports();
+    let sides = [("before", &comparison.before), ("after", &comparison.after)];
+    for ((title, state), viewport) in sides.iter().zip(viewports.iter()) {
+        let mut viewport_r = ViewportRenderer::new(r, *viewport);
+        draw(state, &mut viewport_r);
+        viewport_r.finish();
+        let text = format!("{}: op {} of {}, {} differing", title, state.op_index, state.ops.len() - 1,
+                           state.mismatches.len());
+        let y = viewport[1] + viewport[3] + 16.0;
+        r.draw_text(state.cell_label_color, LABEL_FONT_SIZE, &text, [viewport[0] + 5.0, y]);
+    }
+    let state = &comparison.before;
+    let divider_x = viewports[1][0];
+    r.draw_line(state.cell_label_color, FRAME_LINE_WIDTH, [divider_x, 0.0], [divider_x, comparison.window_size[1]]);
+
+    let mut text = format!("step {} of {}", comparison.step_index, comparison.steps.len() - 1);
+    let mut color = state.cell_label_color;
+    match comparison.first_difference {
+        None => text.push_str(", no differences"),
+        Some(step_index) => {
+            let (before_op, after_op) = comparison.steps[step_index];
+            text.push_str(&format!(", first difference at step {} (ops {} and {})", step_index, before_op, after_op));
+            if step_index == comparison.step_index {
+                color = state.mismatch_color;
+            }
+        }
+    }
+    if comparison.is_playing {
+        text.push_str(", playing");
+    }
+    r.draw_text(color, LABEL_FONT_SIZE, &text, [5.0, comparison.window_size[1] - 6.0]);
+}
+
+// The cell or name drawn at a point in the window, if any.  Cells drawn later
+// are on top.
+pub fn node_at(state: &AppState, point: Vec2d) -> Option<NId> {
+    let point = state.camera.to_world(point);
+    (0..state.draw_states.len()).rev()
+        .map(|id| state.graph.node(id))
+        .filter(|node| !node.is_header() && state.draw_states[node.id()].to_opacity > 0.0)
+        .find(|node| {
+            let rects = [value_rect(state, node.id()), name_rect(state, node.id())];
+            rects.iter().any(|rect| is_inside(Some(point), rect.unwrap_or_default()))
+        })
+        .map(|node| node.id())
+}
+
+fn value_rect(state: &AppState, node_id: NId) -> Option<Rect> {
+    if !state.graph.node(node_id).should_show_value() {
+        return None;
+    }
+    let pos = state.value_position(node_id);
+
+    Some([pos[0], pos[1] + state.window_margin_y, state.draw_states[node_id].cell_width, state.cell_height])
+}
+
+fn name_rect(state: &AppState, node_id: NId) -> Option<Rect> {
+    let node = state.graph.node(node_id);
+    let name = state.graph.node_name(node)?;
+    if !node.should_show_name() {
+        return None;
+    }
+    let pos = state.draw_position(node_id);
+    let width = state.font.text_width(LABEL_FONT_SIZE, name);
+
+    Some([pos[0], pos[1] + state.window_margin_y, width, state.cell_height])
+}
+
+// The op that the inspector button at a point goes to, if any.
+pub fn inspector_button_at(state: &AppState, point: Vec2d) -> Option<usize> {
+    let layout = inspector_layout(state)?;
+
+    layout.buttons.iter().find(|(_, rect)| is_inside(Some(point), *rect)).map(|(op_index, _)| *op_index)
+}
+
+pub fn is_over_inspector(state: &AppState, point: Vec2d) -> bool {
+    inspector_layout(state).is_some_and(|layout| is_inside(Some(point), layout.panel))
+}
+
+// The name of a function or thread above its variables, with a rule across
+// all of its columns.  The thread of the last op played stands out.
+fn draw_header<R: Renderer>(state: &AppState, r: &mut R, header_id: NId) {
+    let draw_state = &state.draw_states[header_id];
+    let position = state.draw_position(header_id);
+    let label = state.graph.header_label(header_id);
+    let mut color = if state.current_thread_id() == Some(header_id) {
+        state.cell_value_color
+    } else {
+        state.cell_label_color
+    };
+    color[3] = draw_state.opacity() as f32;
+    let y = position[1] + state.window_margin_y;
+    r.draw_text(color, LABEL_FONT_SIZE, &label, [position[0], y + 15.0]);
+    let line_y = y + state.named_cell_height;
+    r.draw_line(color, FRAME_LINE_WIDTH, [position[0], line_y], [position[0] + draw_state.cell_width, line_y]);
+}
+
+// Lines from a cell to each element of the array hanging off of it: a stem
+// out of the cell, a spine down the elements, and a branch to each one.
+fn draw_tree_connectors<R: Renderer>(state: &AppState, r: &mut R, node_id: NId, cell_pos: Vec2d) {
+    let node = state.graph.node(node_id);
+    let children = &node.children()[..node.num_children()];
+    if children.is_empty() {
+        return;
+    }
+    let half_height = state.cell_height / 2.0;
+    let stem_from = [cell_pos[0] + state.draw_states[node_id].cell_width, cell_pos[1] + half_height];
+    let spine_x = stem_from[0] + TREE_STEM_LENGTH;
+    let mut color = state.cell_label_color;
+    color[3] = state.draw_states[node_id].opacity() as f32;
+    r.draw_line(color, TREE_LINE_WIDTH, stem_from, [spine_x, stem_from[1]]);
+    for child_id in children.iter() {
+        let child_pos = state.draw_position(*child_id);
+        let branch_y = child_pos[1] + state.window_margin_y + half_height;
+        let mut color = state.cell_label_color;
+        color[3] = state.draw_states[*child_id].opacity() as f32;
+        r.draw_line(color, TREE_LINE_WIDTH, [spine_x, stem_from[1]], [spine_x, branch_y]);
+        r.draw_line(color, TREE_LINE_WIDTH, [spine_x, branch_y], [child_pos[0], branch_y]);
+    }
+}
+
+// Column headers above the first row of a table.
+fn draw_table_headers<R: Renderer>(state: &AppState, r: &mut R, table_id: NId) {
+    let origin = state.children_origin(table_id, state.draw_position(table_id));
+    let mut color = state.cell_label_color;
+    color[3] = state.draw_states[table_id].opacity() as f32;
+    let headers = state.graph.table_headers(table_id);
+    let columns = state.table_column_offsets(table_id).into_iter().zip(state.table_widths(table_id));
+    for (header, (offset, width)) in headers.iter().zip(columns) {
+        let max_text_width = width - 2.0 * state.cell_padding_x;
+        let text = font::truncate_to_width(header, max_text_width, |s| r.measure_text(LABEL_FONT_SIZE, s));
+        let pos = [origin[0] + offset + state.cell_padding_x, origin[1] + state.window_margin_y + 15.0];
+        r.draw_text(color, LABEL_FONT_SIZE, text.as_deref().unwrap_or(header), pos);
+    }
+}
+
+// An arrow beside the element of an array that a variable indexes, labeled
+// like `i = 3`.
+fn draw_index_pointer<R: Renderer>(state: &AppState, r: &mut R, var_id: NId, array_id: NId) {
+    let var = state.graph.node(var_id);
+    if var.value().as_index().is_none() {
+        return;
+    }
+    let draw_state = &state.draw_states[var_id];
+    let mut color = state.cell_label_color;
+    color[3] = draw_state.opacity() as f32;
+    let x = state.pointer_x(array_id);
+    let y = state.pointer_y(var_id) + state.window_margin_y;
+    let mid_y = y + state.cell_height / 2.0;
+    r.draw_arrow(color, POINTER_LINE_WIDTH, [x + POINTER_ARROW_LENGTH, mid_y], [x, mid_y]);
+    let label = match state.graph.node_name(var) {
+        Some(name) => format!("{} = {}", name, draw_state.to_text),
+        None => draw_state.to_text.clone(),
+    };
+    r.draw_text(color, LABEL_FONT_SIZE, &label, [x + POINTER_ARROW_LENGTH + state.cell_padding_x, y + 15.0]);
+}
+
+// The color and name of each tag that's on a visible cell, below everything
+// else.
+// Returns where to draw below the legend.
+fn draw_tag_legend<R: Renderer>(state: &AppState, r: &mut R) -> Scalar {
+    let mut tags = Vec::new();
+    let mut bottom: Scalar = 0.0;
+    for (node, draw_state) in state.graph.nodes_iter().zip(&state.draw_states) {
+        if draw_state.opacity() <= 0.0 {
+            continue;
+        }
+        bottom = bottom.max(state.draw_position(node.id())[1] + state.cell_height);
+        for tag in node.tags().iter() {
+            if !tags.contains(tag) {
+                tags.push(*tag);
+            }
+        }
+    }
+    tags.sort_by_key(|tag| tag.id());
+    let mut x = 5.0;
+    let y = bottom + state.window_margin_y + state.legend_margin_y;
+    for tag in tags.iter() {
+        let name = state.graph.tag_name(*tag);
+        r.draw_rect(state.tag_color(*tag), [x, y, state.legend_swatch_size, state.legend_swatch_size]);
+        x += state.legend_swatch_size + state.cell_padding_x;
+        r.draw_text(state.cell_label_color, LABEL_FONT_SIZE, name, [x, y + 11.0]);
+        x += r.measure_text(LABEL_FONT_SIZE, name) + state.legend_spacing_x;
+    }
+    if tags.is_empty() {
+        return y;
+    }
+
+    y + state.legend_swatch_size + state.legend_margin_y
+}
+
+// The time of the last op played and whether it's playing.
+fn draw_status<R: Renderer>(state: &AppState, r: &mut R, y: Scalar) {
+    let mut parts = Vec::new();
+    if let Some(time) = state.current_time() {
+        parts.push(format!("t = {}", format_duration(time)));
+    }
+    match (state.is_playing, state.is_timed_playback) {
+        (false, _) => (),
+        (true, false) => parts.push("playing".to_string()),
+        (true, true) => parts.push("playing in real time".to_string()),
+    }
+    if let (Some(mark), Some(diff)) = (state.diff_mark, &state.diff) {
+        let count = |is_kind: fn(&CellChange) -> bool| diff.cells.values().filter(|c| is_kind(c)).count();
+        parts.push(format!(
+            "changes from op {} to op {}: {} added, {} removed, {} changed",
+            mark,
+            state.op_index,
+            count(|c| matches!(c, CellChange::Added { .. })),
+            count(|c| matches!(c, CellChange::Removed { .. })),
+            count(|c| matches!(c, CellChange::Changed { .. })),
+        ));
+    }
+    match (state.is_watching, state.follow_end) {
+        (false, _) => (),
+        (true, false) => parts.push("watching".to_string()),
+        (true, true) => parts.push("watching, following the end".to_string()),
+    }
+    match state.camera.mode {
+        CameraMode::Free => (),
+        CameraMode::FitAll => parts.push("fitting all".to_string()),
+        CameraMode::FollowChanges => parts.push("following changes".to_string()),
+    }
+    if (state.camera.target_zoom - 1.0).abs() > 0.005 {
+        parts.push(format!("zoom {:.0}%", state.camera.target_zoom * 100.0));
+    }
+    if parts.is_empty() {
+        return;
+    }
+    r.draw_text(state.cell_label_color, LABEL_FONT_SIZE, &parts.join("  "), [5.0, y + 11.0]);
+}
+
+// Outlines a cell that changed since the marked op.  A removed cell is
+// drawn faded with the value it had.
+fn draw_cell_change<R: Renderer>(state: &AppState, r: &mut R, cell_change: &CellChange, rect: Rect) {
+    let color = match cell_change {
+        CellChange::Added { .. } => state.added_color,
+        CellChange::Changed { .. } => state.changed_color,
+        CellChange::Removed { value } => {
+            let mut bg_color = state.cell_bg_color;
+            bg_color[3] = REMOVED_CELL_OPACITY;
+            r.draw_rect(bg_color, rect);
+            let mut text_color = state.cell_value_color;
+            text_color[3] = REMOVED_CELL_OPACITY;
+            let max_text_width = rect[2] - 2.0 * state.cell_padding_x;
+            let text = font::truncate_to_width(value, max_text_width, |s| r.measure_text(VALUE_FONT_SIZE, s));
+            let text = text.as_deref().unwrap_or(value);
+            r.draw_text(text_color, VALUE_FONT_SIZE, text, [rect[0] + state.cell_padding_x, rect[1] + 15.0]);
+
+            state.removed_color
+        }
+    };
+    draw_outline(r, color, CHANGE_OUTLINE_WIDTH, rect);
+}
+
+fn draw_selection<R: Renderer>(state: &AppState, r: &mut R, rect: Rect) {
+    draw_outline(r, state.selection_color, SELECTION_OUTLINE_WIDTH, rect);
+}
+
+// The inspector of the selected cell sits in the bottom right corner, left
+// of the code pane if it's shown.  A value too long for it scrolls, with a
+// line telling which part of it is shown.
+fn inspector_layout(state: &AppState) -> Option<InspectorLayout> {
+    let inspector = state.inspector.as_ref()?;
+    let info = &inspector.info;
+    let mut lines = vec![info.label.clone(), format!("type: {}", info.type_name)];
+    if let Some(parent) = &info.parent {
+        lines.push(format!("parent: {}", parent));
+    }
+    if let Some(index) = info.index {
+        lines.push(format!("index: {}", index));
+    }
+    lines.push(format!("display: {}", info.display_type));
+    let num_value_lines = inspector.value_lines.len();
+    let shown = inspector.scroll..(inspector.scroll + INSPECTOR_VALUE_LINES).min(num_value_lines);
+    lines.extend(inspector.value_lines[shown.clone()].iter().cloned());
+    if num_value_lines > INSPECTOR_VALUE_LINES {
+        lines.push(format!("lines {}-{} of {}, scroll for more", shown.start + 1, shown.end, num_value_lines));
+    }
+    let changes = [("last changed", info.last_modified), ("next change", info.next_modified)];
+    let first_change_line = lines.len();
+    for (what, op_index) in changes.iter() {
+        lines.push(match op_index {
+            None => format!("{}: none", what),
+            Some(op_index) => format!("{}: op {}", what, op_index),
+        });
+    }
+
+    let height = lines.len() as Scalar * INSPECTOR_LINE_HEIGHT + 2.0 * INSPECTOR_PADDING;
+    let canvas_rect = state.canvas_rect();
+    let x = (canvas_rect[0] + canvas_rect[2] - INSPECTOR_WIDTH - INSPECTOR_PADDING).max(0.0);
+    let y = (state.window_size[1] - height - INSPECTOR_PADDING).max(0.0);
+    let panel = [x, y, INSPECTOR_WIDTH, height];
+    let buttons = changes.iter().enumerate()
+        .filter_map(|(i, (_, op_index))| {
+            let op_index = (*op_index)?;
+            if op_index == state.op_index {
+                return None;
             }
+            let line_y = y + INSPECTOR_PADDING + (first_change_line + i) as Scalar * INSPECTOR_LINE_HEIGHT;
+            let button_x = x + INSPECTOR_WIDTH - INSPECTOR_PADDING - INSPECTOR_BUTTON_WIDTH;
+
+            Some((op_index, [button_x, line_y, INSPECTOR_BUTTON_WIDTH, INSPECTOR_LINE_HEIGHT - 1.0]))
+        })
+        .collect();
+
+    Some(InspectorLayout { panel, lines, buttons })
+}
+
+fn draw_inspector<R: Renderer>(state: &AppState, r: &mut R, layout: &InspectorLayout) {
+    let [x, y, w, h] = layout.panel;
+    r.draw_rect(state.cell_label_color, [x - 1.0, y - 1.0, w + 2.0, h + 2.0]);
+    r.draw_rect(state.cell_bg_color, layout.panel);
+    for (i, line) in layout.lines.iter().enumerate() {
+        let color = if i == 0 { state.cell_value_color } else { state.cell_label_color };
+        let line_y = y + INSPECTOR_PADDING + i as Scalar * INSPECTOR_LINE_HEIGHT;
+        r.draw_text(color, VALUE_FONT_SIZE, line, [x + INSPECTOR_PADDING, line_y + 12.0]);
+    }
+    for (_, rect) in layout.buttons.iter() {
+        r.draw_rect(state.cell_label_color, *rect);
+        let text = "go to";
+        let text_x = rect[0] + (rect[2] - r.measure_text(LABEL_FONT_SIZE, text)) / 2.0;
+        r.draw_text(state.cell_bg_color, LABEL_FONT_SIZE, text, [text_x, rect[1] + 12.0]);
+    }
+}
+
+fn draw_outline<R: Renderer>(r: &mut R, color: Color, width: Scalar, rect: Rect) {
+    let [x, y, w, h] = rect;
+    let corners = [[x, y], [x + w, y], [x + w, y + h], [x, y + h]];
+    for i in 0..corners.len() {
+        r.draw_line(color, width, corners[i], corners[(i + 1) % corners.len()]);
+    }
+}
+
+// The code around the location of the last op played, along the right side of
+// the window, with the line highlighted and the column underlined.
+fn draw_code_pane<R: Renderer>(state: &AppState, r: &mut R) {
+    let width = CODE_PANE_WIDTH.min(state.window_size[0]);
+    let pane = [state.window_size[0] - width, 0.0, width, state.window_size[1]];
+    r.draw_rect(state.cell_bg_color, pane);
+    r.set_clip(Some(pane));
+    draw_code(state, r, pane);
+    r.set_clip(None);
+}
+
+fn draw_code<R: Renderer>(state: &AppState, r: &mut R, pane: Rect) {
+    let x = pane[0] + CODE_PANE_PADDING;
+    let mut y = CODE_PANE_PADDING;
+    let location = state.current_location();
+    let title = match location {
+        None => "No source location yet".to_string(),
+        Some(location) => location.to_string(),
+    };
+    r.draw_text(state.cell_label_color, LABEL_FONT_SIZE, &title, [x, y + 12.0]);
+    y += CODE_LINE_HEIGHT + CODE_PANE_PADDING;
+    let location = match location {
+        None => return,
+        Some(location) => location,
+    };
+    let lines = match state.sources.get(&location.file).and_then(Option::as_ref) {
+        None => {
+            let message = format!("Couldn't read {}", location.file);
+            r.draw_text(state.cell_label_color, LABEL_FONT_SIZE, &message, [x, y + 12.0]);
+            return;
+        }
+        Some(lines) => lines,
+    };
+    // Keep the line in the middle of the pane.
+    let num_visible = ((pane[3] - y) / CODE_LINE_HEIGHT).max(1.0) as usize;
+    let first = location.line.saturating_sub(num_visible / 2).max(1);
+    let code_x = x + CODE_GUTTER_WIDTH;
+    for (i, line) in lines.iter().enumerate().skip(first - 1).take(num_visible) {
+        let line_number = i + 1;
+        if line_number == location.line {
+            r.draw_rect(state.code_highlight_color, [pane[0], y, pane[2], CODE_LINE_HEIGHT]);
+        }
+        let number = line_number.to_string();
+        let number_x = code_x - CODE_PANE_PADDING - r.measure_text(INDEX_FONT_SIZE, &number);
+        r.draw_text(state.cell_label_color, INDEX_FONT_SIZE, &number, [number_x, y + 12.0]);
+        r.draw_text(state.cell_value_color, VALUE_FONT_SIZE, &expand_tabs(line), [code_x, y + 12.0]);
+        if let (true, Some(column)) = (line_number == location.line, location.column) {
+            let before: String = line.chars().take(column.saturating_sub(1)).collect();
+            let at: String = line.chars().skip(column.saturating_sub(1)).take(1).collect();
+            let from_x = code_x + r.measure_text(VALUE_FONT_SIZE, &expand_tabs(&before));
+            let to_x = from_x + r.measure_text(VALUE_FONT_SIZE, if at.is_empty() { " " } else { &at });
+            let underline_y = y + CODE_LINE_HEIGHT - 1.0;
+            r.draw_line(state.cell_value_color, 1.0, [from_x, underline_y], [to_x, underline_y]);
+        }
+        y += CODE_LINE_HEIGHT;
+    }
+}
+
+fn expand_tabs(line: &str) -> String {
+    line.replace('\t', &" ".repeat(CODE_TAB_WIDTH))
+}
+
+fn format_duration(seconds: f64) -> String {
+    let abs = seconds.abs();
+    if abs > 0.0 && abs < 1e-3 {
+        format!("{:.1} us", seconds * 1e6)
+    } else if abs > 0.0 && abs < 1.0 {
+        format!("{:.3} ms", seconds * 1e3)
+    } else {
+        format!("{:.3} s", seconds)
+    }
+}
+
+fn draw_tooltip<R: Renderer>(state: &AppState, r: &mut R, text: &str, cursor: Vec2d) {
+    let width = r.measure_text(VALUE_FONT_SIZE, text) + 2.0 * state.cell_padding_x;
+    let pos = [cursor[0] + TOOLTIP_OFFSET, cursor[1] + TOOLTIP_OFFSET];
+    r.draw_rect(state.cell_label_color, [pos[0] - 1.0, pos[1] - 1.0, width + 2.0, state.cell_height + 2.0]);
+    r.draw_rect(state.cell_bg_color, [pos[0], pos[1], width, state.cell_height]);
+    r.draw_text(state.cell_value_color, VALUE_FONT_SIZE, text, [pos[0] + state.cell_padding_x, pos[1] + 15.0]);
+}
+
+fn is_inside(point: Option<Vec2d>, rect: Rect) -> bool {
+    match point {
+        None => false,
+        Some(p) => p[0] >= rect[0] && p[0] < rect[0] + rect[2] && p[1] >= rect[1] && p[1] < rect[1] + rect[3],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{DrawCommand, RecordingRenderer};
+    use crate::state::tests::state_for_trace;
+
+    // What's drawn at the end of a trace once its transitions have finished.
+    fn draw_trace(source: &str) -> (AppState, Vec<DrawCommand>) {
+        let mut state = state_for_trace(source);
+        state.player_go_to(state.ops.len() - 1);
+        for ds in state.draw_states.iter_mut() {
+            ds.mix = 1.0;
+            ds.move_mix = 1.0;
+            ds.pointer_mix = 1.0;
         }
+        let mut r = RecordingRenderer::new();
+        draw(&state, &mut r);
+
+        (state, r.commands)
     }
 
-    pos
+    fn find_text<'a>(commands: &'a [DrawCommand], text: &str) -> (&'a Color, FontSize, Vec2d) {
+        commands.iter().find_map(|command| match command {
+            DrawCommand::Text { color, font_size, text: t, pos } if t == text => Some((color, *font_size, *pos)),
+            _ => None,
+        }).unwrap_or_else(|| panic!("Nothing drew {:?}", text))
+    }
+
+    // The color of the cell drawn behind a point.
+    fn rect_color_at(commands: &[DrawCommand], pos: Vec2d) -> Color {
+        commands.iter().rev().find_map(|command| match command {
+            DrawCommand::Rect { color, rect } if is_inside(Some([pos[0], pos[1] - 1.0]), *rect) => Some(*color),
+            _ => None,
+        }).unwrap_or_else(|| panic!("No cell at {:?}", pos))
+    }
+
+    #[test]
+    fn draws_a_variable() {
+        let (state, commands) = draw_trace("set num = 22\n");
+        let (label_color, _, label_pos) = find_text(&commands, "num");
+        let (value_color, font_size, value_pos) = find_text(&commands, "22");
+        assert_eq!(*label_color, state.cell_label_color);
+        assert_eq!(*value_color, state.cell_value_color);
+        assert_eq!(font_size, VALUE_FONT_SIZE);
+        assert!(label_pos[0] < value_pos[0]);
+        assert_eq!(rect_color_at(&commands, value_pos), state.cell_bg_color);
+    }
+
+    #[test]
+    fn draws_an_array_with_indexes() {
+        let (_, commands) = draw_trace("push arr 7\npush arr 8\npush arr 9\n");
+        let values: Vec<Vec2d> = ["7", "8", "9"].iter().map(|text| find_text(&commands, text).2).collect();
+        // Elements go down a column.
+        assert!(values[0][1] < values[1][1] && values[1][1] < values[2][1]);
+        for (i, value_pos) in values.iter().enumerate() {
+            let (_, font_size, index_pos) = find_text(&commands, &i.to_string());
+            assert_eq!(font_size, INDEX_FONT_SIZE);
+            assert!(index_pos[0] < value_pos[0]);
+            assert!((index_pos[1] - value_pos[1]).abs() < 2.0);
+        }
+    }
+
+    #[test]
+    fn draws_tagged_cells_in_the_tag_color_with_a_legend() {
+        let (state, commands) = draw_trace("push arr 7\npush arr 8\ntag arr[0] sorted\n");
+        let tag_color = state.tag_colors[0];
+        assert_eq!(rect_color_at(&commands, find_text(&commands, "7").2), tag_color);
+        assert_eq!(rect_color_at(&commands, find_text(&commands, "8").2), state.cell_bg_color);
+        let (legend_color, _, legend_pos) = find_text(&commands, "sorted");
+        assert_eq!(*legend_color, state.cell_label_color);
+        assert_eq!(rect_color_at(&commands, [legend_pos[0] - state.cell_padding_x - 1.0, legend_pos[1]]), tag_color);
+    }
+
+    #[test]
+    fn truncates_a_value_too_wide_for_its_cell() {
+        let value = "y".repeat(200);
+        let (state, commands) = draw_trace(&format!("set s = \"{}\"\n", value));
+        let texts: Vec<&str> = commands.iter().filter_map(|command| match command {
+            DrawCommand::Text { text, .. } if text.starts_with("\"yy") => Some(text.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(texts.len(), 1);
+        assert!(texts[0].ends_with(font::ELLIPSIS));
+        let s = state.graph.nodes_iter().find(|node| state.graph.node_name(node) == Some("s")).expect("No variable s");
+        let max_text_width = state.draw_states[s.id()].cell_width - 2.0 * state.cell_padding_x;
+        assert!(state.font.text_width(VALUE_FONT_SIZE, texts[0]) <= max_text_width);
+    }
 }
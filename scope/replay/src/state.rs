@@ -1,34 +1,126 @@
-use std::collections::HashMap;
-
-use piston_window::{G2dTexture, G2dTextureContext, Text};
-use piston_window::glyph_cache::rusttype::GlyphCache;
-use piston_window::types::Color;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
 
+use crate::camera::*;
+use crate::config::Theme;
 use crate::core::*;
 use crate::event::*;
+use crate::font::{self, FontMetrics};
+use crate::math;
 use crate::node::*;
 use crate::op::*;
-
-const NAMED_CELL_MARGIN_X: Scalar = 30.0;
-const NAMED_CELL_MARGIN_Y: Scalar = 10.0;
-const CELL_MARGIN_Y: Scalar = 10.0;
+use crate::render::Rect;
+
+// Space between a name and its value.
+const NAMED_LABEL_MARGIN_X: Scalar = 10.0;
+// Length of the arrow from an index pointer to its element.
+pub const POINTER_ARROW_LENGTH: Scalar = 20.0;
+// Seconds between ops when playing, or on average when playing in real time.
+pub const PLAYBACK_DELAY: Scalar = 0.5;
+// Bounds on the delay between ops when playing in real time, so that long
+// waits don't stall playback and bursts can still be followed.
+const MIN_TIMED_PLAYBACK_DELAY: Scalar = 0.05;
+const MAX_TIMED_PLAYBACK_DELAY: Scalar = 3.0;
+// Space between an index and its cell.
+pub const INDEX_LABEL_MARGIN_X: Scalar = 4.0;
+pub const LABEL_FONT_SIZE: FontSize = 12;
+pub const INDEX_FONT_SIZE: FontSize = 10;
+pub const VALUE_FONT_SIZE: FontSize = 12;
+pub const CODE_PANE_WIDTH: Scalar = 420.0;
+pub const INSPECTOR_WIDTH: Scalar = 320.0;
+pub const INSPECTOR_PADDING: Scalar = 8.0;
+// The value lines the inspector shows at once, and how many it wraps a value
+// to before leaving the rest out.
+pub const INSPECTOR_VALUE_LINES: usize = 8;
+const INSPECTOR_MAX_VALUE_LINES: usize = 10_000;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct ProgramGraph {
     gensym: NodeIdGenerator,
     nodes: Vec<Node>,
-    ids_by_name: HashMap::<String, NId>,
     names_by_id: HashMap::<NId, String>,
+    // Names of the nodes that are fields of records.
+    field_names_by_id: HashMap::<NId, Identifier>,
+    // Arrays that integer variables are shown pointing into, by variable so
+    // that pointers are always drawn in the same order.
+    index_into_by_id: BTreeMap::<NId, NId>,
+    tags: HashMap::<String, Tag>,
+    // Indexed by tag id.
+    tag_names: Vec<String>,
+    // Variables shared by all threads, and the calls made outside of any
+    // thread.
+    shared: Timeline,
+    threads: HashMap::<Identifier, Timeline>,
+    // The thread of the event being processed.
+    thread: Option<Identifier>,
+    // The frame each local variable was defined in.
+    frame_ids_by_id: HashMap::<NId, NId>,
+    // How many calls of its thread were in progress when each frame was
+    // entered.
+    frame_depths_by_id: HashMap::<NId, usize>,
+    // The header of the thread each variable and frame belongs to.
+    thread_ids_by_id: HashMap::<NId, NId>,
+}
+
+// The variables of a thread and its function calls in progress, innermost
+// last.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Timeline {
+    // The node of the thread's header.  The shared timeline has none.
+    id: Option<NId>,
+    ids_by_name: HashMap::<String, NId>,
+    frames: Vec<Frame>,
+}
+
+// The local variables of a function call.  While a call is in progress, its
+// own variables hide the ones of the calls that made it, so that a recursive
+// call gets new ones.  The variables defined outside of any call are still
+// visible, like globals.
+#[derive(Clone, Debug, PartialEq)]
+struct Frame {
+    id: NId,
+    ids_by_name: HashMap::<String, NId>,
+}
+
+impl Timeline {
+    // A variable of the innermost call, or else of the thread itself.
+    fn get(&self, name: &str) -> Option<NId> {
+        self.frames.last()
+            .and_then(|frame| frame.ids_by_name.get(name))
+            .or_else(|| self.ids_by_name.get(name))
+            .copied()
+    }
+
+    fn remove(&mut self, name: &str) -> Option<NId> {
+        self.frames.last_mut()
+            .and_then(|frame| frame.ids_by_name.remove(name))
+            .or_else(|| self.ids_by_name.remove(name))
+    }
+
+    // The variables that new ones go in: the innermost call's, or else the
+    // thread's own.
+    fn scope_mut(&mut self) -> &mut HashMap<String, NId> {
+        match self.frames.last_mut() {
+            None => &mut self.ids_by_name,
+            Some(frame) => &mut frame.ids_by_name,
+        }
+    }
 }
 
-pub struct AppState<'font> {
+pub struct AppState {
     pub graph: ProgramGraph,
 
     // Components.
 
     // Reversible operations.
     pub ops: Vec<Op>,
+    // Where the event of each op happened.
+    pub op_metas: Vec<EventMeta>,
+    op_times: OpTimes,
     pub op_index: usize,
+    // Events recorded in the same process that haven't been added yet.
+    queued_events: Vec<(EventMeta, Event)>,
     // Coordinates of each entity, relative to its parent.
     pub positions: Vec<Vec2d>,
     // State used only for drawing.
@@ -39,17 +131,95 @@ pub struct AppState<'font> {
     // View state.
 
     pub bg_color: Color,
-    pub named_cell_label_width: Scalar,
     pub named_cell_height: Scalar,
     pub index_label_width: Scalar,
     pub cell_bg_color: Color,
     pub cell_label_color: Color,
     pub cell_value_color: Color,
-    pub cell_label_text: Text,
-    pub cell_value_text: Text,
+    // Cell backgrounds for tags, used in turn.
+    pub tag_colors: Vec<Color>,
+    // Cells grow from cell_width to fit their values, up to max_cell_width.
     pub cell_width: Scalar,
+    pub max_cell_width: Scalar,
     pub cell_height: Scalar,
-    pub glyphs: GlyphCache<'font, G2dTextureContext, G2dTexture>,
+    // Space between the columns and rows of variables, and between the cells
+    // of arrays and records.
+    pub named_cell_margin: Vec2d,
+    pub cell_margin: Vec2d,
+    // Space above each cell in its slot, and either side of the text in it.
+    pub window_margin_y: Scalar,
+    pub cell_padding_x: Scalar,
+    // Space above and below the tag legend, the size of its color swatches
+    // and the space between its tags.
+    pub legend_margin_y: Scalar,
+    pub legend_swatch_size: Scalar,
+    pub legend_spacing_x: Scalar,
+    pub status_line_height: Scalar,
+    pub font: FontMetrics,
+    // Mouse position in window coordinates.
+    pub cursor: Option<Vec2d>,
+    // Width of each column of each table, by the id of the table's node.
+    pub table_column_widths: HashMap<NId, Vec<Scalar>>,
+    // Whether each thread's variables are shown in a lane of their own.
+    pub show_lanes: bool,
+    pub is_playing: bool,
+    // Whether playback waits in proportion to the time between events.
+    pub is_timed_playback: bool,
+    // Seconds until the next op is played.
+    pub playback_wait: Scalar,
+    // Lines of the source files that events happened in, by path as given in
+    // the trace, or None for files that couldn't be read.
+    pub sources: HashMap<String, Option<Vec<String>>>,
+    // How many ops have had their source files read.
+    num_ops_with_sources: usize,
+    pub show_code_pane: bool,
+    // Background of the line of code of the last op played.
+    pub code_highlight_color: Color,
+    // Whether events are being added as a trace file grows, and whether the
+    // last op is played as they are, rather than staying where the user is.
+    pub is_watching: bool,
+    pub follow_end: bool,
+    // The op that changes are shown since, and what changed from there to
+    // the last op played.
+    pub diff_mark: Option<usize>,
+    pub diff: Option<ChangeSummary>,
+    // What the ops from the mark did to each cell, up to the op the diff was
+    // last updated at, so that updating it only looks at the ops since.
+    diff_histories: BTreeMap<NId, CellHistory>,
+    diff_op_index: usize,
+    pub added_color: Color,
+    pub removed_color: Color,
+    pub changed_color: Color,
+    // Where the layout is shared with another trace's, how, and the width of
+    // each column as last laid out.
+    pub shared_layout: Option<SharedLayout>,
+    pub column_widths: Vec<Scalar>,
+    // Cells whose values differ from the other trace's when comparing.
+    pub mismatches: HashSet<NId>,
+    pub mismatch_color: Color,
+    // The cell clicked on, which the inspector shows.
+    pub selected: Option<NId>,
+    pub inspector: Option<Inspector>,
+    pub selection_color: Color,
+    pub camera: Camera,
+    // The cells that the last op played changed, for the camera to follow.
+    pub camera_focus: Vec<NId>,
+    // Where the mouse was when the canvas was last dragged, while it's being
+    // dragged.
+    pub drag_from: Option<Vec2d>,
+    pub window_size: Vec2d,
+}
+
+// A layout shared with another trace's, so that variables of the same name
+// are drawn in the same places in both.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SharedLayout {
+    // The names of the variables laid out first, in order, and whether each
+    // is large.  A variable that this trace doesn't have still takes up its
+    // place.
+    pub slots: Vec<(Identifier, bool)>,
+    // The least width of each column.
+    pub column_widths: Vec<Scalar>,
 }
 
 // This is used for drawing transitions.  The state on the model may change, but
@@ -57,11 +227,22 @@ pub struct AppState<'font> {
 #[derive(Clone, Debug, PartialEq)]
 pub struct AppDrawState {
     pub label_width: Scalar,
+    pub cell_width: Scalar,
+    // Width of the index labels of this node's children.
+    pub index_label_width: Scalar,
     pub from_opacity: Scalar,
     pub to_opacity: Scalar,
     pub from_text: String,
     pub to_text: String,
     pub mix: Scalar,
+    // Where the node is moving from when its position changes.
+    pub from_position: Vec2d,
+    pub move_mix: Scalar,
+    // Where the pointer of a variable shown as an index into an array is
+    // moving from and to.
+    pub pointer_from_y: Scalar,
+    pub pointer_to_y: Scalar,
+    pub pointer_mix: Scalar,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -70,45 +251,188 @@ pub enum ChangeType {
     Parallel { changes: Vec<ChangeType> },
     // Something changed, but it doesn't affect the layout.
     ValueChange { id: NId, from: String, to: String },
+    // The value came from another cell, so it's drawn moving from there.
+    CopyCell { id: NId, from_id: NId, from: String, to: String },
     AddCell { id: NId },
     RemoveCell { id: NId },
+    // A tag was added to or removed from the cell.
+    TagChange { id: NId },
+    // The cell moved to another place, like another index in its array.
+    MoveCell { id: NId },
     // The layout and possibly eveything else changed.
     #[allow(dead_code)]
     LayoutChange,
 }
 
-impl<'font> AppState<'font> {
-    pub fn new(glyphs: GlyphCache<'font, G2dTextureContext, G2dTexture>) -> AppState {
+// What changed over a range of ops, by cell.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChangeSummary {
+    pub cells: BTreeMap<NId, CellChange>,
+}
+
+// The first and last timestamps of the ops, and how many ops have one, so
+// that playback in real time doesn't go through all the ops for each one.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct OpTimes {
+    first: Option<f64>,
+    last: f64,
+    count: usize,
+}
+
+// A cell's value with its live elements or fields, taken before a copy or a
+// swap changes them.
+struct CellSnapshot {
+    value: Value,
+    field_name: Option<Identifier>,
+    children: Vec<CellSnapshot>,
+}
+
+// Whether a cell was there before the first op in a range that changed it,
+// with its value then, and whether it's there after the last.
+struct CellHistory {
+    was_live: bool,
+    from: String,
+    is_live: bool,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CellChange {
+    Added { value: String },
+    Removed { value: String },
+    Changed { from: String, to: String },
+}
+
+// What the inspector shows about a cell.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CellInfo {
+    // Where the cell is, like `arr_nested[1][0]`.
+    pub label: String,
+    pub value: String,
+    pub type_name: String,
+    pub parent: Option<String>,
+    pub index: Option<usize>,
+    pub display_type: DisplayType,
+    // The last op up to the one played that changed the cell, and the next
+    // one after it.
+    pub last_modified: Option<usize>,
+    pub next_modified: Option<usize>,
+}
+
+// The inspector's view of the selected cell as of the last op played, with
+// the value wrapped to the inspector's width.  It's made again only when the
+// selection or the op changes, since a value can be long.
+pub struct Inspector {
+    pub info: CellInfo,
+    pub value_lines: Vec<String>,
+    // The first value line shown.
+    pub scroll: usize,
+}
+
+impl AppState {
+    pub fn new(font: FontMetrics) -> AppState {
         let mut ops = Vec::with_capacity(256);
         // Since our index can't point between ops in the vector, we always keep
         // a no-op at the beginning so we can point to the real beginning.
         ops.push(Op { forward: OpStep::NoOp, reverse: OpStep::NoOp });
+        let mut op_metas = Vec::with_capacity(256);
+        op_metas.push(EventMeta::default());
 
-        let cell_label_color = [0.58, 0.58, 0.58, 1.0];
-        let cell_value_color = [0.97, 0.97, 0.95, 1.0];
+        let theme = Theme::dark();
 
         AppState {
             graph: ProgramGraph::new(),
             ops,
+            op_metas,
+            op_times: OpTimes::default(),
             op_index: 0,
+            queued_events: Vec::new(),
             positions: Vec::new(),
             draw_states: Vec::new(),
             grid_coords: Vec::new(),
-            bg_color: [0.26, 0.26, 0.24, 1.0],
-            named_cell_label_width: 60.0,
-            named_cell_height: 20.0,
-            index_label_width: 15.0,
-            cell_bg_color: [0.16, 0.16, 0.14, 1.0],
-            cell_label_color,
-            cell_value_color,
-            cell_label_text: Text::new_color(cell_label_color, 12),
-            cell_value_text: Text::new_color(cell_value_color, 12),
-            cell_width: 120.0,
-            cell_height: 20.0,
-            glyphs,
+            bg_color: theme.bg_color,
+            named_cell_height: theme.named_cell_height,
+            index_label_width: theme.index_label_width,
+            cell_bg_color: theme.cell_bg_color,
+            cell_label_color: theme.cell_label_color,
+            cell_value_color: theme.cell_value_color,
+            tag_colors: theme.tag_colors,
+            cell_width: theme.cell_width,
+            max_cell_width: theme.max_cell_width,
+            cell_height: theme.cell_height,
+            named_cell_margin: [theme.named_cell_margin_x, theme.named_cell_margin_y],
+            cell_margin: [theme.cell_margin_x, theme.cell_margin_y],
+            window_margin_y: theme.window_margin_y,
+            cell_padding_x: theme.cell_padding_x,
+            legend_margin_y: theme.legend_margin_y,
+            legend_swatch_size: theme.legend_swatch_size,
+            legend_spacing_x: theme.legend_spacing_x,
+            status_line_height: theme.status_line_height,
+            font,
+            cursor: None,
+            table_column_widths: HashMap::new(),
+            show_lanes: false,
+            is_playing: false,
+            is_timed_playback: false,
+            playback_wait: 0.0,
+            sources: HashMap::new(),
+            num_ops_with_sources: 0,
+            show_code_pane: false,
+            code_highlight_color: theme.code_highlight_color,
+            is_watching: false,
+            follow_end: true,
+            diff_mark: None,
+            diff: None,
+            diff_histories: BTreeMap::new(),
+            diff_op_index: 0,
+            added_color: theme.added_color,
+            removed_color: theme.removed_color,
+            changed_color: theme.changed_color,
+            shared_layout: None,
+            column_widths: Vec::new(),
+            mismatches: HashSet::new(),
+            mismatch_color: theme.mismatch_color,
+            selected: None,
+            inspector: None,
+            selection_color: theme.selection_color,
+            camera: Camera::new(),
+            camera_focus: Vec::new(),
+            drag_from: None,
+            window_size: [800.0, 600.0],
         }
     }
 
+    // Takes the colors, sizes and margins of a theme, and lays the cells out
+    // again to fit them.
+    pub fn apply_theme(&mut self, theme: &Theme) {
+        self.bg_color = theme.bg_color;
+        self.cell_bg_color = theme.cell_bg_color;
+        self.cell_label_color = theme.cell_label_color;
+        self.cell_value_color = theme.cell_value_color;
+        self.tag_colors = theme.tag_colors.clone();
+        self.code_highlight_color = theme.code_highlight_color;
+        self.added_color = theme.added_color;
+        self.removed_color = theme.removed_color;
+        self.changed_color = theme.changed_color;
+        self.mismatch_color = theme.mismatch_color;
+        self.selection_color = theme.selection_color;
+        self.cell_width = theme.cell_width;
+        self.max_cell_width = theme.max_cell_width;
+        self.cell_height = theme.cell_height;
+        self.named_cell_height = theme.named_cell_height;
+        self.index_label_width = theme.index_label_width;
+        self.named_cell_margin = [theme.named_cell_margin_x, theme.named_cell_margin_y];
+        self.cell_margin = [theme.cell_margin_x, theme.cell_margin_y];
+        self.window_margin_y = theme.window_margin_y;
+        self.cell_padding_x = theme.cell_padding_x;
+        self.legend_margin_y = theme.legend_margin_y;
+        self.legend_swatch_size = theme.legend_swatch_size;
+        self.legend_spacing_x = theme.legend_spacing_x;
+        self.status_line_height = theme.status_line_height;
+        self.update_layout();
+        // The font may have changed with the theme.
+        self.update_inspector();
+    }
+
     pub fn init_layout(&mut self) {
         self.positions.resize(self.graph.nodes.len(), [0.0, 0.0]);
         self.grid_coords.resize(self.graph.nodes.len(), None);
@@ -116,340 +440,1921 @@ impl<'font> AppState<'font> {
 
     pub fn update_layout(&mut self) {
         self.init_layout();
-        // For each column, store how many elements we've placed there.
-        let mut cols: Vec<usize> = Vec::new();
+        let num_cols = self.update_grid_coords();
         let mut col_label_widths = HashMap::<usize, Scalar>::new();
+        let mut col_cell_widths = HashMap::<usize, Scalar>::new();
         let mut col_widths = HashMap::<usize, Scalar>::new();
+        let value_widths = self.measure_value_widths();
+        self.update_index_label_widths();
+        self.table_column_widths = self.measure_table_column_widths(&value_widths);
         for (i, node) in self.graph.nodes.iter().enumerate() {
-            match node.node_type {
-                NodeType::MemCell => continue,
-                NodeType::NamedContainer => (),
-            }
-            let grid_coord = if !node.is_ever_complex() && (cols.is_empty() || cols[0] < 10) {
-                // Stack multiple primitives on top of each other in the first
-                // column, up to a point.
-                if cols.is_empty() { cols.push(0); }
-                let row = cols[0];
-                cols[0] += 1;
-
-                [0, row]
-            } else {
-                let index = cols.len();
-                // Since this is a large object like an array, it could take up
-                // arbitrary vertical space in the column.
-                cols.push(std::usize::MAX);
-
-                [index, 0]
+            let col = match self.grid_coords[i] {
+                None => continue,
+                Some(grid_coord) => grid_coord[0],
             };
-            self.grid_coords[i] = Some(grid_coord);
-            let col = grid_coord[0];
+            if node.is_header() {
+                // A header may be its column's only entry.
+                col_label_widths.entry(col).or_insert(0.0);
+                col_cell_widths.entry(col).or_insert(0.0);
+                continue;
+            }
             // Calculate the width of this node's label to get the label width
             // of each column.
             let label_width = self.graph.node_name(node).map(|label_str| {
-                // TODO: actually measure based on the font.
-                10.0 * label_str.len() as Scalar
+                self.font.text_width(LABEL_FONT_SIZE, label_str) + NAMED_LABEL_MARGIN_X
             }).unwrap_or(0.0);
             let max_label_width = *col_label_widths.entry(col).or_insert(0.0);
             if label_width > max_label_width {
                 col_label_widths.insert(col, label_width);
             }
-            // Calculate the width of this node to get the width of each column.
-            let width = self.measure_width(&node, label_width);
-            let max_width = *col_widths.entry(col).or_insert(0.0);
-            if width > max_width {
-                col_widths.insert(col, width);
+            // Size the cells of each column to fit the widest value that any of
+            // them will ever hold.
+            let cell_width = self.graph.subtree_ids(node.id()).iter()
+                .map(|id| value_widths[*id])
+                .fold(self.cell_width, Scalar::max)
+                .min(self.max_cell_width);
+            let max_cell_width = *col_cell_widths.entry(col).or_insert(0.0);
+            if cell_width > max_cell_width {
+                col_cell_widths.insert(col, cell_width);
+            }
+        }
+        // Now that the cell sizes are known, calculate the width of each
+        // column.
+        for i in 0..self.graph.nodes.len() {
+            if let Some(grid_coord) = self.grid_coords[i] {
+                let col = grid_coord[0];
+                let width = if self.graph.nodes[i].is_header() {
+                    self.font.text_width(LABEL_FONT_SIZE, &self.graph.header_label(i))
+                } else {
+                    self.draw_states[i].label_width = *col_label_widths.get(&col).expect("col label width not present");
+                    let cell_width = *col_cell_widths.get(&col).expect("col cell width not present");
+                    for id in self.graph.subtree_ids(i) {
+                        self.draw_states[id].cell_width = self.table_column_width(id).unwrap_or(cell_width);
+                    }
+                    self.measure_width(i) + self.measure_pointers_width(i, &value_widths)
+                };
+                let max_width = *col_widths.entry(col).or_insert(0.0);
+                if width > max_width {
+                    col_widths.insert(col, width);
+                }
+            }
+        }
+        if let Some(shared_layout) = &self.shared_layout {
+            // Columns reserved for variables this trace doesn't have are
+            // empty, but still as wide as the other trace's.
+            for (col, min_width) in shared_layout.column_widths.iter().enumerate().take(num_cols) {
+                let width = col_widths.entry(col).or_insert(0.0);
+                *width = width.max(*min_width);
             }
         }
         // Calculate absolute x of each column.
-        let mut abs_x_for_col = Vec::with_capacity(cols.len());
+        let mut abs_x_for_col = Vec::with_capacity(num_cols);
         let mut abs_x = 5.0;
-        for col in 0..cols.len() {
+        for col in 0..num_cols {
             abs_x_for_col.push(abs_x);
-            let col_width = *col_widths.get(&col).expect("col width not present");
-            abs_x += col_width + NAMED_CELL_MARGIN_X;
+            let col_width = col_widths.get(&col).copied().unwrap_or(0.0);
+            abs_x += col_width + self.named_cell_margin[0];
         }
+        self.column_widths = (0..num_cols).map(|col| col_widths.get(&col).copied().unwrap_or(0.0)).collect();
         // Set the absolute position of each container node.
-        for (i, node) in self.graph.nodes.iter().enumerate() {
-            match node.node_type {
-                NodeType::MemCell => continue,
-                NodeType::NamedContainer => (),
-            }
+        for i in 0..self.graph.nodes.len() {
             if let Some(grid_coord) = self.grid_coords[i] {
-                let col = grid_coord[0];
-                self.draw_states[node.id()].label_width = *col_label_widths.get(&col).expect("col label width not present");
-                let x = abs_x_for_col[col];
-                self.positions[i] = [x, 0.0];
+                let x = abs_x_for_col[grid_coord[0]];
+                let y = self.world_y_coord_from_grid_cell(grid_coord);
+                self.set_target_position(i, [x, y], false);
             }
         }
+        self.update_header_widths(&abs_x_for_col, &col_widths);
+        self.update_positions(false);
     }
 
-    fn measure_width(&self, node: &Node, label_width: Scalar) -> Scalar {
-        let mut w = self.cell_width;
-        w += label_width;
-        if node.is_ever_complex() {
-            w += self.index_label_width;
+    // Places the variables on the grid.  The shared variables come first,
+    // then, in lanes, the variables of each thread below the thread's header.
+    // Returns the number of columns.
+    fn update_grid_coords(&mut self) -> usize {
+        for grid_coord in self.grid_coords.iter_mut() {
+            *grid_coord = None;
+        }
+        let mut num_cols = 0;
+        self.place_timeline(None, 0, &mut num_cols);
+        if self.show_lanes {
+            let thread_ids: Vec<NId> = self.graph.nodes_iter()
+                .filter(|node| node.node_type == NodeType::Thread)
+                .map(|node| node.id())
+                .collect();
+            for thread_id in thread_ids {
+                let first_col = num_cols;
+                self.grid_coords[thread_id] = Some([first_col, 0]);
+                self.place_timeline(Some(thread_id), 1, &mut num_cols);
+                num_cols = num_cols.max(first_col + 1);
+            }
         }
 
-        w
+        num_cols
     }
 
-    fn world_y_coord_from_grid_cell(&self, coord: GridCoord) -> Scalar {
-        let cell_height = self.named_cell_height;
-        let y = coord[1] as Scalar * (cell_height + NAMED_CELL_MARGIN_Y);
-
-        y
+    // Places the variables of a thread, then the variables of each of its
+    // frames in columns of their own, below the frame's header.  Calls at the
+    // same depth are never in progress together, so they share columns, the
+    // way they'd share a call stack.  Without lanes, every thread goes in
+    // with the shared variables.
+    fn place_timeline(&mut self, thread_id: Option<NId>, first_row: usize, num_cols: &mut usize) {
+        let globals: Vec<NId> = self.graph.nodes_iter()
+            .filter(|node| node.node_type == NodeType::NamedContainer)
+            .filter(|node| self.graph.frame_id(node.id()).is_none() && self.lane_id(node.id()) == thread_id)
+            .map(|node| node.id())
+            .collect();
+        let globals = match (&self.shared_layout, thread_id) {
+            (Some(shared_layout), None) => self.fill_slots(&shared_layout.slots, globals),
+            _ => self.unslotted(globals),
+        };
+        self.place_variables(&globals, first_row, num_cols);
+        let mut frame_ids_by_depth = BTreeMap::<usize, Vec<NId>>::new();
+        for node in self.graph.nodes_iter() {
+            if node.node_type == NodeType::Frame && self.lane_id(node.id()) == thread_id {
+                frame_ids_by_depth.entry(self.graph.frame_depth(node.id())).or_default().push(node.id());
+            }
+        }
+        for frame_ids in frame_ids_by_depth.values() {
+            let first_col = *num_cols;
+            let mut end_col = first_col + 1;
+            for frame_id in frame_ids.iter().copied() {
+                self.grid_coords[frame_id] = Some([first_col, first_row]);
+                let locals: Vec<NId> = self.graph.nodes_iter()
+                    .filter(|node| self.graph.frame_id(node.id()) == Some(frame_id))
+                    .map(|node| node.id())
+                    .collect();
+                let locals = self.unslotted(locals);
+                let mut col = first_col;
+                self.place_variables(&locals, first_row + 1, &mut col);
+                end_col = end_col.max(col);
+            }
+            *num_cols = end_col;
+        }
     }
 
-    pub fn player_reset_to_start(&mut self) {
-        for node in self.graph.nodes.iter_mut() {
-            node.reset();
+    // The lane that a variable or frame is shown in, if any.
+    fn lane_id(&self, node_id: NId) -> Option<NId> {
+        if !self.show_lanes {
+            return None;
         }
-        self.op_index = 0;
+
+        self.graph.thread_id(node_id)
     }
 
-    pub fn init_draw_states(&mut self) {
-        self.draw_states = self.graph.nodes.iter().map(|node| {
-            AppDrawState {
-                label_width: 0.0,
-                from_opacity: 0.0,
-                to_opacity: 0.0,
-                from_text: "".to_string(),
-                to_text: node.value().display_string(),
-                mix: 1.0,
-            }
-        }).collect();
+    // The variables laid out first, by name, and whether each is large.
+    pub fn layout_slots(&self) -> Vec<(Identifier, bool)> {
+        self.graph.nodes_iter()
+            .filter(|node| node.node_type == NodeType::NamedContainer)
+            .filter(|node| self.graph.frame_id(node.id()).is_none() && self.lane_id(node.id()).is_none())
+            .filter_map(|node| {
+                self.graph.node_name(node).map(|name| (name.to_string(), node.is_ever_complex()))
+            })
+            .collect()
     }
 
-    pub fn add_event(&mut self, event: Event) {
-        if let Some(op) = self.graph.process(&event) {
-            self.ops.push(op);
-            self.op_index += 1;
+    // Puts each variable in the first free slot of its name.  Slots without
+    // one are left empty, and variables without one go after the slots.
+    fn fill_slots(&self, slots: &[(Identifier, bool)], mut ids: Vec<NId>) -> Vec<(Option<NId>, bool)> {
+        let mut entries = Vec::with_capacity(slots.len());
+        for (name, is_complex) in slots.iter() {
+            let i = ids.iter().position(|id| self.graph.node_name(&self.graph.nodes[*id]) == Some(name.as_str()));
+            entries.push((i.map(|i| ids.remove(i)), *is_complex));
         }
-    }
+        entries.extend(self.unslotted(ids));
 
-    pub fn player_advance(&mut self) {
-        self.player_step(StepDirection::Forward);
-        eprintln!();
+        entries
     }
 
-    pub fn player_rewind(&mut self) {
-        self.player_step(StepDirection::Reverse);
-        eprintln!();
+    fn unslotted(&self, ids: Vec<NId>) -> Vec<(Option<NId>, bool)> {
+        ids.into_iter().map(|id| (Some(id), self.graph.nodes[id].is_ever_complex())).collect()
     }
 
-    fn player_step(&mut self, direction: StepDirection) {
-        if let Some((op_index, new_op_index)) = self.find_step_ops(direction) {
-            let op = &self.ops[op_index];
-            let op_step = op.step_in(direction);
-            let changes = self.graph.step(op_step);
-            self.op_index = new_op_index;
-            self.process_changes(&changes, false);
+    // Stacks primitives on top of each other in one column, up to a point, and
+    // gives each large variable a column of its own.  Entries without a
+    // variable keep their places empty.
+    fn place_variables(&mut self, entries: &[(Option<NId>, bool)], first_row: usize, num_cols: &mut usize) {
+        let mut primitives_col = None;
+        let mut num_primitives = 0;
+        for &(id, is_complex) in entries {
+            let grid_coord = if !is_complex && num_primitives < 10 {
+                let col = *primitives_col.get_or_insert_with(|| {
+                    *num_cols += 1;
+                    *num_cols - 1
+                });
+                num_primitives += 1;
+
+                [col, first_row + num_primitives - 1]
+            } else {
+                // Since this is a large object like an array, it could take up
+                // arbitrary vertical space in the column.
+                *num_cols += 1;
+
+                [*num_cols - 1, first_row]
+            };
+            if let Some(id) = id {
+                self.grid_coords[id] = Some(grid_coord);
+            }
         }
     }
 
-    fn find_step_ops(&mut self, direction: StepDirection) -> Option<(usize, usize)> {
-        match direction {
-            StepDirection::Forward => {
-                let new_index = self.op_index.saturating_add(1);
-                if new_index == self.ops.len() {
-                    return None;
-                }
-
-                Some((new_index, new_index))
-            }
-            StepDirection::Reverse => {
-                let new_index = self.op_index.saturating_sub(1);
-                if self.op_index == 0 {
-                    return None;
-                }
+    // A header spans the columns up to the next header that isn't nested in
+    // it, like the next frame or the next thread's lane.
+    fn update_header_widths(&mut self, abs_x_for_col: &[Scalar], col_widths: &HashMap<usize, Scalar>) {
+        let header_coords: Vec<(NId, GridCoord)> = self.graph.nodes_iter()
+            .filter(|node| node.is_header())
+            .filter_map(|node| self.grid_coords[node.id()].map(|coord| (node.id(), coord)))
+            .collect();
+        for (header_id, coord) in header_coords.iter() {
+            let end_col = header_coords.iter()
+                .filter(|(_, other)| other[0] > coord[0] && other[1] <= coord[1])
+                .map(|(_, other)| other[0])
+                .min()
+                .unwrap_or(abs_x_for_col.len());
+            let last_col = end_col - 1;
+            let right = abs_x_for_col[last_col] + col_widths.get(&last_col).copied().unwrap_or(0.0);
+            self.draw_states[*header_id].cell_width = right - abs_x_for_col[coord[0]];
+        }
+    }
 
-                Some((self.op_index, new_index))
+    // Lays out the elements of every array.  This depends on how many
+    // elements each array currently has, so it's done after every step.
+    pub fn update_positions(&mut self, animate: bool) {
+        for i in 0..self.graph.nodes.len() {
+            if self.grid_coords[i].is_some() {
+                let origin = self.children_origin(i, self.positions[i]);
+                self.layout_children(i, origin, animate);
             }
         }
+        self.update_pointers(animate);
     }
 
-    fn process_changes(&mut self, change: &ChangeType, parallel: bool) {
-        eprintln!("process_changes {:?}", change);
-        match change {
-            ChangeType::NoChange => (),
-            ChangeType::Parallel { changes } => {
-                for change in changes.iter() {
-                    self.process_changes(change, true);
-                }
-            }
-            ChangeType::AddCell { id } => {
-                let id = *id;
-                let node = &self.graph.nodes[id];
-                eprintln!("  position before = {:?}", self.positions[id]);
-                let y = if let Some(grid_coord) = &self.grid_coords[id] {
-                    self.world_y_coord_from_grid_cell(*grid_coord)
-                } else {
-                    0.0
-                };
-                self.positions[id][1] = y + node.index() as Scalar * (self.cell_height + CELL_MARGIN_Y);
-                eprintln!("  position after  = {:?}", self.positions[id]);
-                let text = node.value().display_string();
-                eprintln!("  id={}, parent_id={:?}, index={}, text={:?}", id, node.parent_id(), node.index(), &text);
-                self.draw_states[id].from_opacity = 0.0;
-                self.draw_states[id].to_opacity = 1.0;
-                self.draw_states[id].from_text = text.clone();
-                self.draw_states[id].to_text = text;
-                self.draw_states[id].mix = 0.0;
-            }
-            ChangeType::RemoveCell { id } => {
-                let id = *id;
-                self.draw_states[id].from_opacity = 1.0;
-                self.draw_states[id].to_opacity = 0.0;
-                self.draw_states[id].mix = 0.0;
+    // Moves each index pointer next to the element it points to.  Pointers
+    // that are out of range go just above or below the array.
+    fn update_pointers(&mut self, animate: bool) {
+        let pointers: Vec<(NId, NId)> = self.graph.index_pointers().collect();
+        for (var_id, array_id) in pointers {
+            let y = match self.graph.nodes[var_id].value().as_index() {
+                None => continue,
+                Some(index) => self.pointer_target_y(array_id, index),
+            };
+            let is_visible = self.draw_states[var_id].opacity() > 0.0;
+            let from_y = self.pointer_y(var_id);
+            let ds = &mut self.draw_states[var_id];
+            if ds.pointer_to_y == y {
+                continue;
             }
-            ChangeType::ValueChange { id, from, to } => {
-                let id = *id;
-                let node = &self.graph.nodes[id];
-                eprintln!("  id={}, parent_id={:?}, index={}, text={:?}", id, node.parent_id(), node.index(), &to);
-                if !parallel {
-                    self.draw_states[id].from_opacity = 1.0;
-                    self.draw_states[id].to_opacity = 1.0;
-                }
-                self.draw_states[id].from_text = from.to_string();
-                self.draw_states[id].to_text = to.to_string();
-                self.draw_states[id].mix = 0.0;
+            if animate && is_visible {
+                ds.pointer_from_y = from_y;
+                ds.pointer_mix = 0.0;
+            } else {
+                ds.pointer_from_y = y;
+                ds.pointer_mix = 1.0;
             }
-            ChangeType::LayoutChange => self.update_layout(),
+            ds.pointer_to_y = y;
         }
     }
-}
 
-impl ProgramGraph {
-    pub fn new() -> ProgramGraph {
-        ProgramGraph {
-            gensym: NodeIdGenerator::new(),
-            // tags: HashMap::<String, Tag>::with_capacity(32),
-            ids_by_name: HashMap::<String, NId>::with_capacity(128),
-            names_by_id: HashMap::<NId, String>::with_capacity(128),
-            nodes: Vec::with_capacity(128),
+    fn pointer_target_y(&self, array_id: NId, index: i64) -> Scalar {
+        let row_height = self.cell_height + self.cell_margin[1];
+        let array = &self.graph.nodes[array_id];
+        let elements = &array.children()[..array.num_children()];
+        let first_y = match elements.first() {
+            Some(id) => self.positions[*id][1],
+            None => self.children_origin(array_id, self.positions[array_id])[1],
+        };
+        if index < 0 {
+            return first_y - row_height;
+        }
+        match elements.get(index as usize) {
+            Some(id) => self.positions[*id][1],
+            None => match elements.last() {
+                Some(id) => self.positions[*id][1] + row_height,
+                None => first_y,
+            },
         }
     }
 
-    pub fn nodes_iter(&self) -> impl Iterator<Item = &Node> {
-        self.nodes.iter()
-    }
+    // The current y of a variable's index pointer, including any transition
+    // in progress.
+    pub fn pointer_y(&self, var_id: NId) -> Scalar {
+        let ds = &self.draw_states[var_id];
 
-    pub fn node(&self, node_id: NId) -> &Node {
-        &self.nodes[node_id]
+        math::mix_scalar(ds.pointer_from_y, ds.pointer_to_y, math::quadratic_out(ds.pointer_mix))
     }
 
-    fn next_id(&mut self) -> NId {
-        self.gensym.next()
-    }
+    // Pointers go to the right of the array's widest element.
+    pub fn pointer_x(&self, array_id: NId) -> Scalar {
+        let origin = self.children_origin(array_id, self.draw_position(array_id));
+        let width = if self.graph.table_role(array_id) == Some(TableRole::Table) {
+            self.table_width(array_id)
+        } else {
+            self.measure_children_width(array_id)
+        };
 
-    fn add_unnamed_node(&mut self, node: Node) {
-        self.nodes.push(node);
+        origin[0] + width + self.cell_padding_x
     }
 
-    fn add_named_node(&mut self, name: String, node: Node) {
-        self.ids_by_name.insert(name.clone(), node.id());
-        self.names_by_id.insert(node.id(), name);
-        self.nodes.push(node);
+    // Room for the pointers into the arrays in a named container, with their
+    // labels.
+    fn measure_pointers_width(&self, node_id: NId, value_widths: &[Scalar]) -> Scalar {
+        let subtree_ids = self.graph.subtree_ids(node_id);
+        self.graph.index_pointers().filter(|(_, array_id)| subtree_ids.contains(array_id)).map(|(var_id, _)| {
+            let name = self.graph.node_name(&self.graph.nodes[var_id]).unwrap_or("");
+            let label_width = self.font.text_width(LABEL_FONT_SIZE, &format!("{} = ", name));
+
+            self.cell_padding_x + POINTER_ARROW_LENGTH + label_width + value_widths[var_id]
+        }).fold(0.0, Scalar::max)
     }
 
-    fn add_named_value_node(&mut self, name: String, value: Value) -> NId {
-        let id = self.next_id();
-        let node = Node::new_named_container(id, value);
-        self.add_named_node(name, node);
+    // Places each element of an array below the previous one, leaving enough
+    // room for any nested arrays to the right of it.  Returns the height used.
+    fn layout_children(&mut self, node_id: NId, origin: Vec2d, animate: bool) -> Scalar {
+        if self.graph.table_role(node_id) == Some(TableRole::Table) {
+            return self.layout_table(node_id, origin, animate);
+        }
+        let num_children = self.graph.nodes[node_id].num_children();
+        let mut y = origin[1];
+        if self.graph.record_header(node_id).is_some() {
+            // Leave a row for the type name.
+            y += self.cell_height + self.cell_margin[1];
+        }
+        for index in 0..num_children {
+            let child_id = self.graph.nodes[node_id].children()[index];
+            let pos = [origin[0], y];
+            self.set_target_position(child_id, pos, animate);
+            let mut height = self.cell_height;
+            if self.graph.nodes[child_id].num_children() > 0 {
+                let child_origin = self.children_origin(child_id, pos);
+                height = height.max(self.layout_children(child_id, child_origin, animate));
+            }
+            y += height + self.cell_margin[1];
+        }
 
-        id
+        (y - origin[1] - self.cell_margin[1]).max(0.0)
     }
 
-    fn add_value_node(&mut self, value: Value, parent_id: Option<NId>, index: usize) -> NId {
-        let display_type = parent_id.map(|id| self.nodes[id].display_type())
-                                    .unwrap_or(DisplayType::Default);
-        let id = self.next_id();
-        let node = Node::new(id, value, parent_id, index, display_type);
-        self.add_unnamed_node(node);
+    // Places each row of a table below a row of column headers, with the
+    // fields of every row lined up in columns.  Returns the height used.
+    fn layout_table(&mut self, table_id: NId, origin: Vec2d, animate: bool) -> Scalar {
+        let offsets = self.table_column_offsets(table_id);
+        let row_height = self.cell_height + self.cell_margin[1];
+        let num_rows = self.graph.nodes[table_id].num_children();
+        for index in 0..num_rows {
+            let row_id = self.graph.nodes[table_id].children()[index];
+            let pos = [origin[0], origin[1] + (index + 1) as Scalar * row_height];
+            self.set_target_position(row_id, pos, animate);
+            // Popped fields move along with their row too, so that they're in
+            // place if they come back.
+            let field_ids = self.graph.nodes[row_id].children().clone();
+            for (field_id, offset) in field_ids.iter().zip(offsets.iter()) {
+                self.set_target_position(*field_id, [origin[0] + offset, pos[1]], animate);
+            }
+        }
 
-        id
+        (num_rows + 1) as Scalar * row_height - self.cell_margin[1]
     }
 
-    pub fn node_name(&self, node: &Node) -> Option<&str> {
-        self.names_by_id.get(&node.id()).map(|s| s.as_str())
-    }
+    // Offset of each column of a table from where its rows start.
+    pub fn table_column_offsets(&self, table_id: NId) -> Vec<Scalar> {
+        let mut x = self.draw_states[table_id].index_label_width;
+        self.table_widths(table_id).iter().map(|width| {
+            let offset = x;
+            x += width + self.cell_margin[0];
 
-    fn node_id_by_name_implicit_declare(&mut self, name: &str, value: Value) -> (NId, OpStep) {
-        match self.ids_by_name.get(name) {
-            None => {
-                eprintln!("Implicit node create: name={} value={:?}", name, &value);
+            offset
+        }).collect()
+    }
 
-                let new_id = self.add_named_value_node(name.to_string(), value);
+    // Width of a field's column when the node is a field of a table.
+    fn table_column_width(&self, node_id: NId) -> Option<Scalar> {
+        match self.graph.table_role(node_id) {
+            Some(TableRole::Field) => {
+                let row_id = *self.graph.nodes[node_id].parent_id()?;
+                let table_id = *self.graph.nodes[row_id].parent_id()?;
 
-                (new_id, OpStep::Define { id: new_id })
+                self.table_column_widths.get(&table_id)?.get(self.graph.nodes[node_id].index()).copied()
             }
-            Some(id) => (*id, OpStep::NoOp),
+            _ => None,
         }
     }
 
-    fn node_id_by_loc_implicit_declare(&mut self, loc: &Location, value: Value) -> (NId, OpStep) {
-        match loc {
-            Location::VariableLoc(name) => {
-                self.node_id_by_name_implicit_declare(name, value)
-            }
-            Location::IndexLoc(loc, index) => {
-                let (array_node_id, step) = self.node_id_by_loc_implicit_declare_array(loc);
-                eprintln!("IndexLoc(loc={:?}, index={}) => {:?}", loc, index, &self.nodes[array_node_id]);
+    pub fn table_widths(&self, table_id: NId) -> &[Scalar] {
+        self.table_column_widths.get(&table_id).map(Vec::as_slice).unwrap_or(&[])
+    }
 
-                let array_node = &mut self.nodes[array_node_id];
-                let child_id = array_node.children()[*index];
+    fn table_width(&self, table_id: NId) -> Scalar {
+        let widths = self.table_widths(table_id);
+        let columns_width = widths.iter().sum::<Scalar>() + self.cell_margin[0] * widths.len().saturating_sub(1) as Scalar;
 
-                (child_id, step)
+        self.draw_states[table_id].index_label_width + columns_width.max(self.cell_width)
+    }
+
+    // Columns fit their header and the widest value any field in them will
+    // ever hold.
+    fn measure_table_column_widths(&self, value_widths: &[Scalar]) -> HashMap<NId, Vec<Scalar>> {
+        let mut tables = HashMap::new();
+        for node in self.graph.nodes.iter() {
+            if self.graph.table_role(node.id()) != Some(TableRole::Table) {
+                continue;
             }
+            let mut widths: Vec<Scalar> = self.graph.table_headers(node.id()).iter().map(|header| {
+                self.font.text_width(LABEL_FONT_SIZE, header) + 2.0 * self.cell_padding_x
+            }).collect();
+            for row_id in node.children().iter() {
+                for (col, field_id) in self.graph.nodes[*row_id].children().iter().enumerate() {
+                    widths[col] = widths[col].max(value_widths[*field_id]);
+                }
+            }
+            for width in widths.iter_mut() {
+                *width = width.max(self.cell_width).min(self.max_cell_width);
+            }
+            tables.insert(node.id(), widths);
         }
+
+        tables
     }
 
-    fn node_id_by_loc_implicit_declare_array(&mut self, loc: &Location) -> (NId, OpStep) {
-        self.node_id_by_loc_implicit_declare(loc, Value::UndefinedVal)
+    // Where the first element of a node's array goes, given the position of
+    // the node itself.  Elements replace the value of a named container unless
+    // it's shown as a tree, in which case they hang off to the right like
+    // nested arrays.
+    pub fn children_origin(&self, node_id: NId, pos: Vec2d) -> Vec2d {
+        let node = &self.graph.nodes[node_id];
+        let value_x = pos[0] + self.value_offset_x(node_id);
+        match (node.node_type, node.display_type()) {
+            (NodeType::NamedContainer, DisplayType::Default)
+            | (NodeType::NamedContainer, DisplayType::Table) => [value_x, pos[1]],
+            _ => [value_x + self.draw_states[node_id].cell_width + self.cell_margin[0], pos[1]],
+        }
     }
 
-    fn node_push_implicit_create(&mut self, node_id: NId, value: Value) -> (NId, OpStep) {
-        let node = &mut self.nodes[node_id];
-        let capacity = node.children().len();
-        let index = node.num_children();
-        if index < capacity {
-            // The node is already there.  Reuse it.
-            node.increment_num_children();
-            let child_id = node.children()[index];
-            let child_node = &mut self.nodes[child_id];
-            child_node.set_value(value.clone());
+    // Offset from a node's position to its value cell, past its name or index.
+    pub fn value_offset_x(&self, node_id: NId) -> Scalar {
+        // Fields of a table are placed in their column directly.
+        if self.graph.table_role(node_id) == Some(TableRole::Field) {
+            return 0.0;
+        }
+        match self.graph.nodes[node_id].parent_id() {
+            None => self.draw_states[node_id].label_width,
+            Some(parent_id) => self.draw_states[*parent_id].index_label_width,
+        }
+    }
 
-            return (child_id, OpStep::NoOp);
+    fn set_target_position(&mut self, node_id: NId, pos: Vec2d, animate: bool) {
+        if self.positions[node_id] == pos {
+            return;
         }
+        // Cells that aren't visible yet appear in place instead of sliding.
+        let ds = &self.draw_states[node_id];
+        let is_visible = ds.opacity() > 0.0;
+        let from_position = if animate && is_visible {
+            self.draw_position(node_id)
+        } else {
+            pos
+        };
+        let ds = &mut self.draw_states[node_id];
+        ds.from_position = from_position;
+        ds.move_mix = if animate && is_visible { 0.0 } else { 1.0 };
+        self.positions[node_id] = pos;
+    }
 
-        // Create a new node.
-        let child_id = self.add_value_node(value.clone(), Some(node_id), index);
-        let node = &mut self.nodes[node_id];
-        node.children_mut().push(child_id);
-        node.set_complex();
-        node.increment_num_children();
+    // The current position of a node, including any transition in progress.
+    pub fn draw_position(&self, node_id: NId) -> Vec2d {
+        let ds = &self.draw_states[node_id];
+        let to = self.positions[node_id];
+        let t = math::quadratic_out(ds.move_mix);
 
-        (child_id, OpStep::Define { id: child_id })
+        [math::mix_scalar(ds.from_position[0], to[0], t),
+         math::mix_scalar(ds.from_position[1], to[1], t)]
     }
 
-    fn node_push_id(&mut self, node_id: NId, child_id: NId) {
-        let node = &self.nodes[node_id];
-        let index = node.num_children();
-        let child_node = &mut self.nodes[child_id];
-        child_node.set_parent_id(Some(node_id));
-        child_node.set_index(index);
-        let node = &mut self.nodes[node_id];
-        node.children_mut().push(child_id);
-        node.increment_num_children();
+    pub fn value_position(&self, node_id: NId) -> Vec2d {
+        let pos = self.draw_position(node_id);
+
+        [pos[0] + self.value_offset_x(node_id), pos[1]]
     }
 
-    fn node_pop(&mut self, node_id: NId) -> Option<NId> {
-        let node = &mut self.nodes[node_id];
-        let len = node.num_children();
-        if len == 0 {
-            return None;
+    // Total width of a named container, including all nested arrays.
+    fn measure_width(&self, node_id: NId) -> Scalar {
+        let node = &self.graph.nodes[node_id];
+        let mut w = self.draw_states[node_id].label_width;
+        match (node.is_ever_complex(), node.display_type()) {
+            (true, DisplayType::Default) => w += self.measure_children_width(node_id),
+            (true, DisplayType::Tree) => {
+                w += self.draw_states[node_id].cell_width + self.cell_margin[0] + self.measure_children_width(node_id);
+            }
+            (true, DisplayType::Table) => w += self.table_width(node_id),
+            (false, _) => w += self.draw_states[node_id].cell_width,
+        }
+
+        w
+    }
+
+    // Width of the elements of an array.  This includes elements that have
+    // been popped so that columns don't shift while playing.
+    fn measure_children_width(&self, node_id: NId) -> Scalar {
+        let index_label_width = self.draw_states[node_id].index_label_width;
+        self.graph.nodes[node_id].children().iter().map(|child_id| {
+            let mut w = index_label_width + self.draw_states[*child_id].cell_width;
+            if self.graph.table_role(*child_id) == Some(TableRole::Table) {
+                w += self.cell_margin[0] + self.table_width(*child_id);
+            } else if !self.graph.nodes[*child_id].children().is_empty() {
+                w += self.cell_margin[0] + self.measure_children_width(*child_id);
+            }
+
+            w
+        }).fold(0.0, Scalar::max)
+    }
+
+    // The width each node needs to show every value it takes on over the
+    // whole run, so that the layout doesn't jump around while playing.
+    fn measure_value_widths(&self) -> Vec<Scalar> {
+        let mut widths = vec![0.0; self.graph.nodes.len()];
+        for node in self.graph.nodes.iter() {
+            self.measure_value_width(node.id(), node.value(), &mut widths);
+        }
+        for op in self.ops.iter() {
+            self.measure_step_value_widths(&op.forward, &mut widths);
+        }
+
+        widths
+    }
+
+    fn measure_step_value_widths(&self, step: &OpStep, widths: &mut [Scalar]) {
+        match step {
+            OpStep::Atomic { steps } => {
+                for step in steps.iter() {
+                    self.measure_step_value_widths(step, widths);
+                }
+            }
+            OpStep::Set { id, value }
+            | OpStep::Copy { id, value, .. } => self.measure_value_width(*id, value, widths),
+            OpStep::Push { child_id, value, .. }
+            | OpStep::Insert { child_id, value, .. } => self.measure_value_width(*child_id, value, widths),
+            OpStep::NoOp
+            | OpStep::Define { .. }
+            | OpStep::Forget { .. }
+            | OpStep::Pop { .. }
+            | OpStep::Remove { .. }
+            | OpStep::Swap { .. }
+            | OpStep::Tag { .. }
+            | OpStep::Untag { .. } => (),
+        }
+    }
+
+    fn measure_value_width(&self, id: NId, value: &Value, widths: &mut [Scalar]) {
+        let width = self.font.text_width(VALUE_FONT_SIZE, &value.display_string()) + 2.0 * self.cell_padding_x;
+        if width > widths[id] {
+            widths[id] = width;
+        }
+    }
+
+    // Index labels are right-aligned, so each array needs room for its
+    // largest index, and each record for its longest field name.
+    fn update_index_label_widths(&mut self) {
+        for node in self.graph.nodes.iter() {
+            let width = node.children().iter().map(|child_id| {
+                self.font.text_width(INDEX_FONT_SIZE, &self.graph.index_label(*child_id)) + INDEX_LABEL_MARGIN_X
+            }).fold(self.index_label_width, Scalar::max);
+            self.draw_states[node.id()].index_label_width = width;
+        }
+    }
+
+    pub fn tag_color(&self, tag: Tag) -> Color {
+        self.tag_colors[tag.id() % self.tag_colors.len()]
+    }
+
+    fn world_y_coord_from_grid_cell(&self, coord: GridCoord) -> Scalar {
+        let cell_height = self.named_cell_height;
+        coord[1] as Scalar * (cell_height + self.named_cell_margin[1])
+    }
+
+    pub fn player_reset_to_start(&mut self) {
+        // Undo every op instead of only clearing the values, since ops like
+        // swaps also rearrange the nodes.
+        while self.op_index > 0 {
+            self.graph.step(&self.ops[self.op_index].reverse);
+            self.op_index -= 1;
+        }
+        for node in self.graph.nodes.iter_mut() {
+            node.reset();
+        }
+        self.op_index = 0;
+    }
+
+    pub fn init_draw_states(&mut self) {
+        self.draw_states = self.graph.nodes.iter().map(|node| self.hidden_draw_state(node)).collect();
+    }
+
+    // The draw state of a node that hasn't been defined yet.
+    fn hidden_draw_state(&self, node: &Node) -> AppDrawState {
+        AppDrawState {
+            label_width: 0.0,
+            cell_width: self.cell_width,
+            index_label_width: self.index_label_width,
+            from_opacity: 0.0,
+            to_opacity: 0.0,
+            from_text: "".to_string(),
+            to_text: node.value().display_string(),
+            mix: 1.0,
+            from_position: [0.0, 0.0],
+            move_mix: 1.0,
+            pointer_from_y: 0.0,
+            pointer_to_y: 0.0,
+            pointer_mix: 1.0,
+        }
+    }
+
+    pub fn add_event(&mut self, meta: EventMeta, event: Event) {
+        if let Some(op) = self.graph.process(&meta, &event) {
+            self.ops.push(op);
+            if let Some(time) = meta.time {
+                self.op_times.add(time);
+            }
+            self.op_metas.push(meta);
+            self.op_index += 1;
+        }
+    }
+
+    // Keeps an event recorded in the same process to add with the others
+    // recorded before apply_queued_events adds them.
+    pub fn queue_event(&mut self, meta: EventMeta, event: Event) {
+        self.queued_events.push((meta, event));
+    }
+
+    // Adds events to a trace that's already being shown.  The user stays at
+    // the op they're at unless following the end.
+    pub fn append_events(&mut self, events: Vec<(EventMeta, Event)>, source_dir: Option<&Path>) {
+        // Events are processed against the values at the end.
+        let op_index = self.op_index;
+        self.graph_seek(self.ops.len() - 1);
+        let num_nodes = self.graph.nodes.len();
+        for (meta, event) in events {
+            self.add_event(meta, event);
+        }
+        self.graph_seek(op_index);
+        // Like player_reset_to_start, for the nodes that didn't exist yet.
+        for node in self.graph.nodes[num_nodes..].iter_mut() {
+            node.reset();
+        }
+        let hidden: Vec<AppDrawState> = self.graph.nodes[num_nodes..].iter()
+            .map(|node| self.hidden_draw_state(node))
+            .collect();
+        self.draw_states.extend(hidden);
+        self.load_sources(source_dir);
+        self.update_layout();
+        if self.follow_end {
+            self.player_advance_to_end();
+        }
+        self.update_diff();
+        // The selected cell may change in the new ops.
+        self.update_inspector();
+    }
+
+    // Shows a new trace in place of the current one, like when a program is
+    // run again.  The user goes to the same op in it unless following the
+    // end.
+    pub fn replace_events(&mut self, events: Vec<(EventMeta, Event)>, source_dir: Option<&Path>) {
+        let op_index = self.op_index;
+        self.graph = ProgramGraph::new();
+        self.ops.truncate(1);
+        self.op_metas.truncate(1);
+        self.op_times = OpTimes::default();
+        self.op_index = 0;
+        self.positions.clear();
+        self.grid_coords.clear();
+        self.table_column_widths.clear();
+        self.diff_mark = None;
+        self.diff = None;
+        self.diff_histories.clear();
+        self.selected = None;
+        self.inspector = None;
+        self.camera_focus.clear();
+        // The source may have changed too.
+        self.sources.clear();
+        self.num_ops_with_sources = 0;
+        for (meta, event) in events {
+            self.add_event(meta, event);
+        }
+        self.load_sources(source_dir);
+        self.player_reset_to_start();
+        self.init_draw_states();
+        self.update_layout();
+        if self.follow_end {
+            self.player_advance_to_end();
+        } else {
+            while self.op_index < op_index && self.op_index + 1 < self.ops.len() {
+                self.player_advance();
+            }
+        }
+    }
+
+    pub fn toggle_follow_end(&mut self) {
+        self.follow_end = !self.follow_end;
+        if self.follow_end {
+            self.player_advance_to_end();
+        }
+    }
+
+    // Marks the last op played to show what changes from there, or clears
+    // the mark.
+    pub fn toggle_diff_mark(&mut self) {
+        self.diff_mark = match self.diff_mark {
+            None => Some(self.op_index),
+            Some(_) => None,
+        };
+        self.diff_histories.clear();
+        self.diff_op_index = self.op_index;
+        self.update_diff();
+    }
+
+    // Adds what the ops played or undone since the last update did to the
+    // cells' histories.  The model is played back to where the histories
+    // were left and then forward again, which for a step at a time is just
+    // a couple of ops.
+    fn update_diff(&mut self) {
+        if self.diff_mark.is_none() {
+            self.diff = None;
+            return;
+        }
+        let op_index = self.op_index;
+        self.graph.seek(&self.ops, op_index, self.diff_op_index);
+        self.graph.seek_histories(&self.ops, self.diff_op_index, op_index, &mut self.diff_histories);
+        self.diff_op_index = op_index;
+        self.diff = Some(ChangeSummary::from_histories(&self.diff_histories, &self.graph));
+    }
+
+    pub fn cell_change(&self, node_id: NId) -> Option<&CellChange> {
+        self.diff.as_ref()?.cells.get(&node_id)
+    }
+
+    pub fn cell_info(&self, node_id: NId) -> CellInfo {
+        let node = self.graph.node(node_id);
+        let value = self.graph.full_value(node_id);
+        let modified = |op_index: &usize| self.ops[*op_index].modifies(node_id);
+
+        CellInfo {
+            label: self.graph.location_label(node_id),
+            value: value.display_string(),
+            type_name: value.type_name(),
+            parent: node.parent_id().map(|parent_id| self.graph.location_label(*parent_id)),
+            index: node.parent_id().map(|_| node.index()),
+            display_type: node.display_type(),
+            last_modified: (1..=self.op_index).rev().find(modified),
+            next_modified: (self.op_index + 1..self.ops.len()).find(modified),
+        }
+    }
+
+    // Selects a cell for the inspector, or clears the selection.
+    pub fn select(&mut self, node_id: Option<NId>) {
+        if node_id != self.selected {
+            self.selected = node_id;
+            self.inspector = None;
+        }
+        self.update_inspector();
+    }
+
+    // Makes the inspector again for the op played, keeping its scroll where
+    // the value still has the lines.
+    fn update_inspector(&mut self) {
+        let node_id = match self.selected {
+            None => {
+                self.inspector = None;
+                return;
+            }
+            Some(node_id) => node_id,
+        };
+        let info = self.cell_info(node_id);
+        let max_width = INSPECTOR_WIDTH - 2.0 * INSPECTOR_PADDING;
+        let value_lines = font::wrap_to_width(&format!("value: {}", info.value), max_width, INSPECTOR_MAX_VALUE_LINES,
+                                              |s| self.font.text_width(VALUE_FONT_SIZE, s));
+        let max_scroll = value_lines.len().saturating_sub(INSPECTOR_VALUE_LINES);
+        let scroll = self.inspector.as_ref().map(|inspector| inspector.scroll.min(max_scroll)).unwrap_or(0);
+        self.inspector = Some(Inspector { info, value_lines, scroll });
+    }
+
+    // Scrolls the value in the inspector by a number of lines, down for a
+    // positive number.
+    pub fn scroll_inspector(&mut self, lines: isize) {
+        if let Some(inspector) = self.inspector.as_mut() {
+            let max_scroll = inspector.value_lines.len().saturating_sub(INSPECTOR_VALUE_LINES);
+            inspector.scroll = inspector.scroll.saturating_add_signed(lines).min(max_scroll);
+        }
+    }
+
+    // Plays or undoes ops on the model alone, without drawing any change.
+    fn graph_seek(&mut self, op_index: usize) {
+        self.graph_seek_changes(op_index);
+    }
+
+    // Like graph_seek, returning what each op played or undone changed.
+    pub fn graph_seek_changes(&mut self, op_index: usize) -> Vec<ChangeType> {
+        let changes = self.graph.seek(&self.ops, self.op_index, op_index);
+        self.op_index = op_index;
+
+        changes
+    }
+
+    fn player_advance_to_end(&mut self) {
+        self.player_go_to(self.ops.len() - 1);
+    }
+
+    // Plays or undoes ops one at a time up to an op, drawing each change.
+    pub fn player_go_to(&mut self, op_index: usize) {
+        while self.op_index < op_index {
+            self.player_advance();
+        }
+        while self.op_index > op_index {
+            self.player_rewind();
+        }
+    }
+
+    // The header of the thread of the last op played.
+    pub fn current_thread_id(&self) -> Option<NId> {
+        let thread = self.op_metas[self.op_index].thread.as_ref()?;
+
+        self.graph.thread_id_by_name(thread)
+    }
+
+    // Seconds from the first timestamp in the trace to the time of the last op
+    // played.
+    pub fn current_time(&self) -> Option<f64> {
+        let start = self.op_times.first?;
+
+        self.op_metas[self.op_index].time.map(|time| time - start)
+    }
+
+    pub fn toggle_playing(&mut self) {
+        self.is_playing = !self.is_playing;
+        if self.is_playing && self.op_index + 1 >= self.ops.len() {
+            // Start over once the end is reached.
+            self.player_reset_to_start();
+            self.init_draw_states();
+            self.update_layout();
+        }
+        self.playback_wait = 0.0;
+    }
+
+    pub fn toggle_timed_playback(&mut self) {
+        self.is_timed_playback = !self.is_timed_playback;
+    }
+
+    // Adds the events queued since the last time, all at once.
+    pub fn apply_queued_events(&mut self) {
+        if !self.queued_events.is_empty() {
+            let events = std::mem::take(&mut self.queued_events);
+            self.append_events(events, None);
+        }
+    }
+
+    // Plays the next op once it's time to.
+    pub fn update_playback(&mut self, dt: Scalar) {
+        if !self.is_playing {
+            return;
+        }
+        self.playback_wait -= dt;
+        if self.playback_wait > 0.0 {
+            return;
+        }
+        if self.op_index + 1 >= self.ops.len() {
+            self.is_playing = false;
+            return;
+        }
+        self.player_advance();
+        self.playback_wait = self.playback_delay();
+    }
+
+    // How long to show the last op played before playing the next one.  In
+    // real time, the delay is in proportion to the time between their events,
+    // scaled so that the average delay is the usual one.
+    fn playback_delay(&self) -> Scalar {
+        if !self.is_timed_playback {
+            return PLAYBACK_DELAY;
+        }
+        let times = (
+            self.op_metas[self.op_index].time,
+            self.op_metas.get(self.op_index + 1).and_then(|meta| meta.time),
+            self.op_times.average(),
+        );
+        match times {
+            (Some(time), Some(next_time), Some(average)) if average > 0.0 => {
+                let delay = (next_time - time) / average * PLAYBACK_DELAY;
+
+                delay.clamp(MIN_TIMED_PLAYBACK_DELAY, MAX_TIMED_PLAYBACK_DELAY)
+            }
+            _ => PLAYBACK_DELAY,
+        }
+    }
+
+    // Reads the source files that events happened in.  Relative paths that
+    // aren't found from the current directory are tried from the base
+    // directory, which is usually the trace's.  Only the ops added since the
+    // last time are looked at, and each file is only tried once.
+    pub fn load_sources(&mut self, base_dir: Option<&Path>) {
+        for meta in self.op_metas[self.num_ops_with_sources..].iter() {
+            let file = match &meta.location {
+                Some(location) if !self.sources.contains_key(&location.file) => &location.file,
+                _ => continue,
+            };
+            let mut path = Path::new(file).to_path_buf();
+            if let (false, Some(base_dir)) = (path.exists(), base_dir) {
+                path = base_dir.join(path);
+            }
+            let lines = match fs::read_to_string(&path) {
+                Ok(source) => Some(source.lines().map(str::to_string).collect()),
+                Err(e) => {
+                    eprintln!("Couldn't read source {}: {}", path.display(), e);
+                    None
+                }
+            };
+            self.sources.insert(file.clone(), lines);
+        }
+        self.num_ops_with_sources = self.op_metas.len();
+    }
+
+    // The location of the last op played that has one.
+    pub fn current_location(&self) -> Option<&SourceLocation> {
+        self.op_metas[..=self.op_index].iter().rev().find_map(|meta| meta.location.as_ref())
+    }
+
+    pub fn has_source_locations(&self) -> bool {
+        self.op_metas.iter().any(|meta| meta.location.is_some())
+    }
+
+    // The part of the window that the canvas is drawn in, beside the code
+    // pane if it's shown.
+    pub fn canvas_rect(&self) -> Rect {
+        let code_pane_width = if self.show_code_pane && self.has_source_locations() {
+            CODE_PANE_WIDTH.min(self.window_size[0])
+        } else {
+            0.0
+        };
+
+        [0.0, 0.0, self.window_size[0] - code_pane_width, self.window_size[1]]
+    }
+
+    // Where a cell and its label are drawn on the canvas.
+    pub fn node_rect(&self, node_id: NId) -> Rect {
+        let pos = self.draw_position(node_id);
+        let value_pos = self.value_position(node_id);
+        let right = value_pos[0] + self.draw_states[node_id].cell_width;
+
+        [pos[0], pos[1], (right - pos[0]).max(0.0), value_pos[1] - pos[1] + self.cell_height]
+    }
+
+    // The bottom right corner of the cells that are shown.
+    pub fn content_extent(&self) -> Vec2d {
+        let mut extent: Vec2d = [0.0, 0.0];
+        for (i, draw_state) in self.draw_states.iter().enumerate() {
+            if draw_state.to_opacity <= 0.0 {
+                continue;
+            }
+            let rect = self.node_rect(i);
+            extent = [extent[0].max(rect[0] + rect[2]), extent[1].max(rect[1] + rect[3])];
+        }
+
+        extent
+    }
+
+    // Moves the camera along, after aiming it at what its mode shows.
+    pub fn update_camera(&mut self, dt: Scalar) {
+        let canvas_rect = self.canvas_rect();
+        match self.camera.mode {
+            CameraMode::Free => (),
+            CameraMode::FitAll => {
+                let extent = self.content_extent();
+                self.camera.fit(extent, canvas_rect);
+            }
+            CameraMode::FollowChanges => {
+                let rects: Vec<Rect> = self.camera_focus.iter().map(|id| self.node_rect(*id)).collect();
+                if let Some(bounds) = bounding_rect(&rects) {
+                    self.camera.reveal(bounds, canvas_rect);
+                }
+            }
+        }
+        self.camera.update(dt);
+    }
+
+    pub fn toggle_fit_all(&mut self) {
+        self.toggle_camera_mode(CameraMode::FitAll);
+    }
+
+    pub fn toggle_follow_changes(&mut self) {
+        self.toggle_camera_mode(CameraMode::FollowChanges);
+    }
+
+    fn toggle_camera_mode(&mut self, mode: CameraMode) {
+        if self.camera.mode == mode {
+            self.camera.mode = CameraMode::Free;
+        } else {
+            self.camera.mode = mode;
+        }
+    }
+
+    pub fn toggle_code_pane(&mut self) {
+        self.show_code_pane = !self.show_code_pane;
+    }
+
+    pub fn toggle_lanes(&mut self) {
+        self.show_lanes = !self.show_lanes;
+        self.update_layout();
+    }
+
+    pub fn player_advance(&mut self) {
+        self.player_step(StepDirection::Forward);
+    }
+
+    pub fn player_rewind(&mut self) {
+        self.player_step(StepDirection::Reverse);
+    }
+
+    fn player_step(&mut self, direction: StepDirection) {
+        if let Some((op_index, new_op_index)) = self.find_step_ops(direction) {
+            let op = &self.ops[op_index];
+            let op_step = op.step_in(direction);
+            let changes = self.graph.step(op_step);
+            self.op_index = new_op_index;
+            self.process_changes(&changes, false);
+            self.update_positions(true);
+            self.animate_copies(&changes);
+            self.update_diff();
+            self.update_inspector();
+            self.camera_focus.clear();
+            changes.collect_ids(&mut self.camera_focus);
+        }
+    }
+
+    fn find_step_ops(&mut self, direction: StepDirection) -> Option<(usize, usize)> {
+        match direction {
+            StepDirection::Forward => {
+                let new_index = self.op_index.saturating_add(1);
+                if new_index == self.ops.len() {
+                    return None;
+                }
+
+                Some((new_index, new_index))
+            }
+            StepDirection::Reverse => {
+                let new_index = self.op_index.saturating_sub(1);
+                if self.op_index == 0 {
+                    return None;
+                }
+
+                Some((self.op_index, new_index))
+            }
+        }
+    }
+
+    fn process_changes(&mut self, change: &ChangeType, parallel: bool) {
+        match change {
+            ChangeType::NoChange => (),
+            ChangeType::Parallel { changes } => {
+                for change in changes.iter() {
+                    self.process_changes(change, true);
+                }
+            }
+            ChangeType::AddCell { id } => {
+                let id = *id;
+                let node = &self.graph.nodes[id];
+                let text = node.value().display_string();
+                self.draw_states[id].from_opacity = 0.0;
+                self.draw_states[id].to_opacity = 1.0;
+                self.draw_states[id].from_text = text.clone();
+                self.draw_states[id].to_text = text;
+                self.draw_states[id].mix = 0.0;
+            }
+            ChangeType::RemoveCell { id } => {
+                let id = *id;
+                self.draw_states[id].from_opacity = 1.0;
+                self.draw_states[id].to_opacity = 0.0;
+                self.draw_states[id].mix = 0.0;
+            }
+            ChangeType::ValueChange { id, from, to }
+            | ChangeType::CopyCell { id, from, to, .. } => {
+                let id = *id;
+                if !parallel {
+                    self.draw_states[id].from_opacity = 1.0;
+                    self.draw_states[id].to_opacity = 1.0;
+                }
+                self.draw_states[id].from_text = from.to_string();
+                self.draw_states[id].to_text = to.to_string();
+                self.draw_states[id].mix = 0.0;
+            }
+            ChangeType::TagChange { .. } => {
+                // The node's tags are drawn as they are.
+            }
+            ChangeType::MoveCell { .. } => {
+                // The new position is picked up by update_positions().
+            }
+            ChangeType::LayoutChange => self.update_layout(),
+        }
+    }
+
+    // Copied values start out where they were copied from.  This is done
+    // after the layout so that it isn't overridden.
+    fn animate_copies(&mut self, change: &ChangeType) {
+        match change {
+            ChangeType::Parallel { changes } => {
+                for change in changes.iter() {
+                    self.animate_copies(change);
+                }
+            }
+            ChangeType::CopyCell { id, from_id, .. } => {
+                // Line up the value cells, since one may have a name and the
+                // other an index.
+                let from_value_position = self.value_position(*from_id);
+                let from_position = [from_value_position[0] - self.value_offset_x(*id), from_value_position[1]];
+                let ds = &mut self.draw_states[*id];
+                ds.from_position = from_position;
+                ds.move_mix = 0.0;
+            }
+            _ => (),
+        }
+    }
+}
+
+impl ChangeType {
+    // The cells changed.
+    fn collect_ids(&self, ids: &mut Vec<NId>) {
+        match self {
+            ChangeType::Parallel { changes } => {
+                for change in changes.iter() {
+                    change.collect_ids(ids);
+                }
+            }
+            ChangeType::ValueChange { id, .. }
+            | ChangeType::CopyCell { id, .. }
+            | ChangeType::AddCell { id }
+            | ChangeType::RemoveCell { id }
+            | ChangeType::TagChange { id }
+            | ChangeType::MoveCell { id } => ids.push(*id),
+            ChangeType::NoChange
+            | ChangeType::LayoutChange => (),
+        }
+    }
+}
+
+impl OpTimes {
+    fn add(&mut self, time: f64) {
+        self.first.get_or_insert(time);
+        self.last = time;
+        self.count += 1;
+    }
+
+    // Average time between ops that have timestamps.
+    fn average(&self) -> Option<f64> {
+        let first = self.first?;
+        if self.count < 2 {
+            return None;
+        }
+
+        Some((self.last - first) / (self.count - 1) as f64)
+    }
+}
+
+impl AppDrawState {
+    pub fn opacity(&self) -> Scalar {
+        math::mix_scalar(self.from_opacity, self.to_opacity, math::quadratic_out(self.mix))
+    }
+}
+
+impl ChangeSummary {
+    // Records what a step did to each cell.  A cell can be added or removed
+    // more than once by one step, and cells added and then removed, or
+    // changed back, don't count, so only the first and last changes matter.
+    fn add_step(histories: &mut BTreeMap<NId, CellHistory>, change: &ChangeType, graph: &ProgramGraph) {
+        match change {
+            ChangeType::Parallel { changes } => {
+                for change in changes.iter() {
+                    ChangeSummary::add_step(histories, change, graph);
+                }
+            }
+            ChangeType::AddCell { id } => {
+                histories.entry(*id).or_insert(CellHistory { was_live: false, from: String::new(), is_live: true })
+                    .is_live = true;
+            }
+            ChangeType::RemoveCell { id } => {
+                let from = graph.nodes[*id].value().display_string();
+                histories.entry(*id).or_insert(CellHistory { was_live: true, from, is_live: false })
+                    .is_live = false;
+            }
+            ChangeType::ValueChange { id, from, .. } | ChangeType::CopyCell { id, from, .. } => {
+                histories.entry(*id).or_insert(CellHistory { was_live: true, from: from.clone(), is_live: true });
+            }
+            ChangeType::NoChange
+            | ChangeType::TagChange { .. }
+            | ChangeType::MoveCell { .. }
+            | ChangeType::LayoutChange => (),
+        }
+    }
+
+    // Compares each cell's history to its value after the last step.
+    fn from_histories(histories: &BTreeMap<NId, CellHistory>, graph: &ProgramGraph) -> ChangeSummary {
+        let mut cells = BTreeMap::new();
+        for (&id, history) in histories.iter() {
+            let to = graph.nodes[id].value().display_string();
+            let cell_change = match (history.was_live, history.is_live) {
+                (false, true) => CellChange::Added { value: to },
+                (true, false) => CellChange::Removed { value: history.from.clone() },
+                (true, true) if history.from != to => CellChange::Changed { from: history.from.clone(), to },
+                _ => continue,
+            };
+            cells.insert(id, cell_change);
+        }
+
+        ChangeSummary { cells }
+    }
+}
+
+// The smallest rectangle around some rectangles, if there are any.
+fn bounding_rect(rects: &[Rect]) -> Option<Rect> {
+    let first = rects.first()?;
+    let (mut left, mut top) = (first[0], first[1]);
+    let (mut right, mut bottom) = (first[0] + first[2], first[1] + first[3]);
+    for rect in rects.iter() {
+        left = left.min(rect[0]);
+        top = top.min(rect[1]);
+        right = right.max(rect[0] + rect[2]);
+        bottom = bottom.max(rect[1] + rect[3]);
+    }
+
+    Some([left, top, right - left, bottom - top])
+}
+
+impl Default for ProgramGraph {
+    fn default() -> ProgramGraph {
+        ProgramGraph::new()
+    }
+}
+
+impl ProgramGraph {
+    pub fn new() -> ProgramGraph {
+        ProgramGraph {
+            gensym: NodeIdGenerator::new(),
+            tags: HashMap::<String, Tag>::with_capacity(32),
+            tag_names: Vec::with_capacity(32),
+            names_by_id: HashMap::<NId, String>::with_capacity(128),
+            field_names_by_id: HashMap::<NId, Identifier>::with_capacity(128),
+            index_into_by_id: BTreeMap::<NId, NId>::new(),
+            shared: Timeline::default(),
+            threads: HashMap::<Identifier, Timeline>::with_capacity(8),
+            thread: None,
+            frame_ids_by_id: HashMap::<NId, NId>::with_capacity(128),
+            frame_depths_by_id: HashMap::<NId, usize>::with_capacity(32),
+            thread_ids_by_id: HashMap::<NId, NId>::with_capacity(128),
+            nodes: Vec::with_capacity(128),
+        }
+    }
+
+    pub fn nodes_iter(&self) -> impl Iterator<Item = &Node> {
+        self.nodes.iter()
+    }
+
+    pub fn node(&self, node_id: NId) -> &Node {
+        &self.nodes[node_id]
+    }
+
+    fn next_id(&mut self) -> NId {
+        self.gensym.next()
+    }
+
+    fn add_unnamed_node(&mut self, node: Node) {
+        self.nodes.push(node);
+    }
+
+    fn add_named_node(&mut self, name: String, node: Node) {
+        let timeline = self.timeline_mut();
+        timeline.scope_mut().insert(name.clone(), node.id());
+        let thread_id = timeline.id;
+        match timeline.frames.last().map(|frame| frame.id) {
+            None => {
+                if let Some(thread_id) = thread_id {
+                    self.thread_ids_by_id.insert(node.id(), thread_id);
+                }
+            }
+            Some(frame_id) => {
+                self.frame_ids_by_id.insert(node.id(), frame_id);
+            }
+        }
+        self.names_by_id.insert(node.id(), name);
+        self.nodes.push(node);
+    }
+
+    fn add_named_value_node(&mut self, name: String, value: Value) -> NId {
+        let id = self.next_id();
+        let node = Node::new_named_container(id, value);
+        self.add_named_node(name, node);
+
+        id
+    }
+
+    fn add_value_node(&mut self, value: Value, parent_id: Option<NId>, index: usize) -> NId {
+        let display_type = parent_id.map(|id| self.nodes[id].display_type())
+                                    .unwrap_or(DisplayType::Default);
+        let id = self.next_id();
+        let node = Node::new(id, value, parent_id, index, display_type);
+        self.add_unnamed_node(node);
+
+        id
+    }
+
+    // The given node and all its descendants.
+    pub fn subtree_ids(&self, node_id: NId) -> Vec<NId> {
+        let mut ids = vec![node_id];
+        let mut i = 0;
+        while i < ids.len() {
+            ids.extend_from_slice(self.nodes[ids[i]].children());
+            i += 1;
+        }
+
+        ids
+    }
+
+    // Where a node falls in a table, if it's in one.  A table is the
+    // outermost of nested arrays displayed as tables.
+    pub fn table_role(&self, node_id: NId) -> Option<TableRole> {
+        if self.is_table_root(node_id) {
+            return Some(TableRole::Table);
+        }
+        let parent_id = *self.nodes[node_id].parent_id()?;
+        if self.is_table_root(parent_id) {
+            return Some(TableRole::Row);
+        }
+        let grandparent_id = *self.nodes[parent_id].parent_id()?;
+        if self.is_table_root(grandparent_id) {
+            return Some(TableRole::Field);
+        }
+
+        None
+    }
+
+    fn is_table_root(&self, node_id: NId) -> bool {
+        let node = &self.nodes[node_id];
+        let parent_is_table = node.parent_id().map(|id| {
+            self.nodes[*id].display_type() == DisplayType::Table
+        }).unwrap_or(false);
+
+        node.display_type() == DisplayType::Table && !parent_is_table
+    }
+
+    // Header text for each column of a table.  Every column any row ever
+    // has gets one, named after the field in that column when the rows are
+    // records.
+    pub fn table_headers(&self, table_id: NId) -> Vec<String> {
+        let rows = self.nodes[table_id].children();
+        let num_columns = rows.iter().map(|row_id| self.nodes[*row_id].children().len()).max().unwrap_or(0);
+
+        (0..num_columns).map(|col| {
+            rows.iter().filter_map(|row_id| self.nodes[*row_id].children().get(col))
+                .find_map(|field_id| self.field_name(*field_id))
+                .map(|name| name.to_string())
+                .unwrap_or_else(|| col.to_string())
+        }).collect()
+    }
+
+    // Variables shown as an index into an array, with the array.
+    pub fn index_pointers(&self) -> impl Iterator<Item = (NId, NId)> + '_ {
+        self.index_into_by_id.iter().map(|(var_id, array_id)| (*var_id, *array_id))
+    }
+
+    pub fn tag_name(&self, tag: Tag) -> &str {
+        &self.tag_names[tag.id()]
+    }
+
+    // Names of all tags, indexed by tag id.
+    pub fn tag_names(&self) -> &[String] {
+        &self.tag_names
+    }
+
+    // A node's own tags after the tags of the arrays it's in, so that tagging
+    // a row of a table highlights its fields.
+    pub fn effective_tags(&self, node_id: NId) -> Vec<Tag> {
+        let node = &self.nodes[node_id];
+        let mut tags = match node.parent_id() {
+            Some(parent_id) => self.effective_tags(*parent_id),
+            None => Vec::new(),
+        };
+        for tag in node.tags().iter() {
+            if !tags.contains(tag) {
+                tags.push(*tag);
+            }
+        }
+
+        tags
+    }
+
+    fn tag_by_name_implicit_create(&mut self, name: &str) -> Tag {
+        if let Some(tag) = self.tags.get(name) {
+            return *tag;
+        }
+        let tag = Tag::new(self.tag_names.len());
+        self.tags.insert(name.to_string(), tag);
+        self.tag_names.push(name.to_string());
+
+        tag
+    }
+
+    pub fn field_name(&self, node_id: NId) -> Option<&str> {
+        self.field_names_by_id.get(&node_id).map(|s| s.as_str())
+    }
+
+    // The label next to an element: its field name in a record, or else its
+    // index.
+    pub fn index_label(&self, node_id: NId) -> String {
+        match self.field_name(node_id) {
+            Some(name) => name.to_string(),
+            None => self.nodes[node_id].index().to_string(),
+        }
+    }
+
+    // The type name shown above a record's fields.  Trees show it in the
+    // record's own cell instead, and tables in the column headers.
+    pub fn record_header(&self, node_id: NId) -> Option<&str> {
+        let node = &self.nodes[node_id];
+        if node.display_type() == DisplayType::Tree || self.table_role(node_id).is_some() {
+            return None;
+        }
+        match node.value() {
+            Value::RecordVal(Some(type_name), _) => Some(type_name),
+            _ => None,
+        }
+    }
+
+    pub fn node_name(&self, node: &Node) -> Option<&str> {
+        self.names_by_id.get(&node.id()).map(|s| s.as_str())
+    }
+
+    // A node's value with the values of its elements or fields in it.
+    pub fn full_value(&self, node_id: NId) -> Value {
+        let node = &self.nodes[node_id];
+        let children = node.children()[..node.num_children()].iter();
+        match node.value() {
+            Value::RecordVal(type_name, _) => {
+                let fields = children.map(|id| {
+                    (self.field_name(*id).unwrap_or_default().to_string(), self.full_value(*id))
+                }).collect();
+
+                Value::RecordVal(type_name.clone(), fields)
+            }
+            _ if node.is_ever_complex() && !node.has_simple_value() => Value::ArrayVal(children.map(|id| self.full_value(*id)).collect()),
+            value => value.clone(),
+        }
+    }
+
+    // Where a cell is, written like a location in a trace, as in `a[2].key`.
+    pub fn location_label(&self, node_id: NId) -> String {
+        let node = &self.nodes[node_id];
+        if node.is_header() {
+            return self.header_label(node_id);
+        }
+        if let Some(name) = self.node_name(node) {
+            return name.to_string();
+        }
+        let parent_label = match node.parent_id() {
+            None => String::new(),
+            Some(parent_id) => self.location_label(*parent_id),
+        };
+        match self.field_name(node_id) {
+            Some(field) => format!("{}.{}", parent_label, field),
+            None => format!("{}[{}]", parent_label, node.index()),
+        }
+    }
+
+    // The frame a local variable was defined in.
+    pub fn frame_id(&self, node_id: NId) -> Option<NId> {
+        self.frame_ids_by_id.get(&node_id).copied()
+    }
+
+    // How deep in its thread's call stack a frame is.  The outermost call is
+    // at depth 0.
+    pub fn frame_depth(&self, frame_id: NId) -> usize {
+        self.frame_depths_by_id.get(&frame_id).copied().unwrap_or(0)
+    }
+
+    // The text of a frame's or thread's header.
+    pub fn header_label(&self, node_id: NId) -> String {
+        let node = &self.nodes[node_id];
+        let name = self.node_name(node).unwrap_or("");
+        match node.node_type {
+            NodeType::Thread => format!("thread {}", name),
+            _ => name.to_string(),
+        }
+    }
+
+    // The header of the thread a variable or frame belongs to.
+    pub fn thread_id(&self, node_id: NId) -> Option<NId> {
+        let node_id = self.frame_id(node_id).unwrap_or(node_id);
+
+        self.thread_ids_by_id.get(&node_id).copied()
+    }
+
+    pub fn thread_id_by_name(&self, name: &str) -> Option<NId> {
+        self.threads.get(name).and_then(|timeline| timeline.id)
+    }
+
+    fn timeline(&self) -> &Timeline {
+        match &self.thread {
+            None => &self.shared,
+            Some(name) => &self.threads[name],
+        }
+    }
+
+    fn timeline_mut(&mut self) -> &mut Timeline {
+        match &self.thread {
+            None => &mut self.shared,
+            Some(name) => self.threads.get_mut(name).expect("thread should be present"),
+        }
+    }
+
+    // A name is looked up in the innermost call, then in the thread's own
+    // variables, then in the shared ones.  Every event resolves names this
+    // way, including forgets.
+    fn node_id_by_name(&self, name: &str) -> Option<NId> {
+        self.timeline().get(name).or_else(|| match self.thread {
+            None => None,
+            Some(_) => self.shared.ids_by_name.get(name).copied(),
+        })
+    }
+
+    // Takes a name out of the scope that node_id_by_name() finds it in.
+    fn forget_name(&mut self, name: &str) -> Option<NId> {
+        let id = self.node_id_by_name(name)?;
+        if self.timeline_mut().remove(name).is_none() {
+            self.shared.ids_by_name.remove(name);
+        }
+
+        Some(id)
+    }
+
+    // The node and its elements, without the ones that were popped.
+    fn live_subtree_ids(&self, node_id: NId) -> Vec<NId> {
+        let mut ids = vec![node_id];
+        let mut i = 0;
+        while i < ids.len() {
+            let node = &self.nodes[ids[i]];
+            ids.extend_from_slice(&node.children()[..node.num_children()]);
+            i += 1;
+        }
+
+        ids
+    }
+
+    // Variables fade in and out along with their elements.
+    fn live_subtree_changes(&self, node_id: NId, change: fn(NId) -> ChangeType) -> ChangeType {
+        let ids = self.live_subtree_ids(node_id);
+        if ids.len() == 1 {
+            return change(node_id);
+        }
+
+        ChangeType::Parallel { changes: ids.into_iter().map(change).collect() }
+    }
+
+    fn node_id_by_name_implicit_declare(&mut self, name: &str, value: Value) -> (NId, OpStep) {
+        match self.node_id_by_name(name) {
+            None => {
+                let new_id = self.add_named_value_node(name.to_string(), value);
+
+                (new_id, OpStep::Define { id: new_id })
+            }
+            Some(id) => (id, OpStep::NoOp),
+        }
+    }
+
+    fn node_id_by_loc_implicit_declare(&mut self, loc: &Location, value: Value) -> (NId, OpStep) {
+        match loc {
+            Location::VariableLoc(name) => {
+                self.node_id_by_name_implicit_declare(name, value)
+            }
+            Location::IndexLoc(loc, index) => {
+                let (array_node_id, step) = self.node_id_by_loc_implicit_declare_array(loc);
+                let array_node = &mut self.nodes[array_node_id];
+                let child_id = array_node.children()[*index];
+
+                (child_id, step)
+            }
+            Location::FieldLoc(loc, field) => {
+                let (record_node_id, step) = self.node_id_by_loc_implicit_declare_array(loc);
+                match self.field_id_by_name(record_node_id, field) {
+                    Some(field_id) => (field_id, step),
+                    None => {
+                        // Like a variable, a field is declared by the first
+                        // event that uses it.
+                        let (field_id, forward, _) = self.node_add_field(record_node_id, field, value);
+
+                        (field_id, step.then(forward))
+                    }
+                }
+            }
+        }
+    }
+
+    // Checks that the elements an event uses are inside their arrays, before
+    // anything is declared for it.  A copy into an element may add elements
+    // after the last one, so only its array is checked here.
+    fn check_indexes(&self, event: &Event) -> Result<(), String> {
+        let locs = match event {
+            Event::Display(loc, _)
+            | Event::Set(loc, _)
+            | Event::Push(loc, _)
+            | Event::Pop(loc)
+            | Event::Insert(loc, ..)
+            | Event::Remove(loc, _) => vec![loc],
+            Event::IndexInto(loc1, loc2) | Event::Swap(loc1, loc2) => vec![loc1, loc2],
+            Event::Tag(loc, _) | Event::Untag(loc, _) => vec![array_loc(loc)],
+            Event::Copy(src, dst) | Event::Move(src, dst) => match dst {
+                Location::IndexLoc(dst_array, _) => vec![array_loc(src), &**dst_array],
+                _ => vec![array_loc(src), dst],
+            },
+            Event::NoOp | Event::Forget(_) | Event::EnterFrame(_) | Event::ExitFrame => Vec::new(),
+        };
+
+        locs.into_iter().try_for_each(|loc| self.node_id_in_bounds(loc).map(|_| ()))
+    }
+
+    // The node at a location, if it exists, or an error if an index in it is
+    // past the end of its array.  A variable or field that doesn't exist yet
+    // is declared empty, so any index into it is past the end.
+    fn node_id_in_bounds(&self, loc: &Location) -> Result<Option<NId>, String> {
+        match loc {
+            Location::VariableLoc(name) => Ok(self.node_id_by_name(name)),
+            Location::IndexLoc(array_loc, index) => {
+                let array = self.node_id_in_bounds(array_loc)?.map(|id| &self.nodes[id]);
+                match array {
+                    Some(array) if *index < array.num_children() => Ok(Some(array.children()[*index])),
+                    _ => Err(format!("index {} past the end of the array", index)),
+                }
+            }
+            Location::FieldLoc(record_loc, field) => {
+                Ok(self.node_id_in_bounds(record_loc)?.and_then(|id| self.field_id_by_name(id, field)))
+            }
+        }
+    }
+
+    fn node_ids_by_slice_loc(&mut self, loc: &SliceLocation) -> (Vec<NId>, OpStep) {
+        match loc {
+            SliceLocation::SimpleLoc(loc) => {
+                let (node_id, step) = self.node_id_by_loc_implicit_declare(loc, Value::UndefinedVal);
+
+                (vec![node_id], step)
+            }
+            SliceLocation::SliceLoc(loc, lo, hi) => {
+                let (array_node_id, step) = self.node_id_by_loc_implicit_declare_array(loc);
+                // Like slices in Python, the part of the range past the end
+                // of the array is left out.
+                let node = &self.nodes[array_node_id];
+                let hi = (*hi).min(node.num_children());
+                let lo = (*lo).min(hi);
+                let ids = node.children()[lo..hi].to_vec();
+
+                (ids, step)
+            }
+        }
+    }
+
+    // The nodes to copy a number of values into, pushing new elements when
+    // copying past the end of an array.  Returns the steps that create the
+    // nodes and the steps that undo that, or, if the copy would start past
+    // the end, the steps that declared the array.
+    fn node_ids_for_copy(&mut self, loc: &Location, values: &[Value], is_slice: bool) -> Result<(Vec<NId>, OpStep, OpStep), OpStep> {
+        let (array_id, start, mut forward) = match loc {
+            Location::IndexLoc(array_loc, index) => {
+                let (array_id, step) = self.node_id_by_loc_implicit_declare_array(array_loc);
+
+                (array_id, *index, step)
+            }
+            _ if is_slice => {
+                let (array_id, step) = self.node_id_by_loc_implicit_declare_array(loc);
+
+                (array_id, 0, step)
+            }
+            _ => {
+                let (node_id, step) = self.node_id_by_loc_implicit_declare(loc, Value::UndefinedVal);
+                let reverse = step.rev();
+
+                return Ok((vec![node_id], step, reverse));
+            }
+        };
+        let len = self.nodes[array_id].num_children();
+        if start > len {
+            return Err(forward);
+        }
+        let mut reverse = forward.rev();
+        let mut ids = Vec::with_capacity(values.len());
+        for (i, value) in values.iter().enumerate() {
+            if start + i < len {
+                ids.push(self.nodes[array_id].children()[start + i]);
+                continue;
+            }
+            let (child_id, define) = self.node_push_implicit_create(array_id, value.clone());
+            let push = OpStep::Push { id: array_id, child_id, value: value.clone() };
+            reverse = push.rev().then(define.rev()).then(reverse);
+            forward = forward.then(define).then(push);
+            ids.push(child_id);
+        }
+
+        Ok((ids, forward, reverse))
+    }
+
+    // Adds or removes a tag on each node.  Nodes that don't change are left
+    // out of the steps so that undoing them doesn't change them either.
+    fn node_tag_steps(&mut self, ids: &[NId], tag: Tag, add: bool) -> (OpStep, OpStep) {
+        let mut forward = OpStep::NoOp;
+        for id in ids.iter().copied() {
+            let node = &mut self.nodes[id];
+            if add && node.add_tag(tag) {
+                forward = forward.then(OpStep::Tag { id, tag });
+            } else if !add && node.remove_tag(tag) {
+                forward = forward.then(OpStep::Untag { id, tag });
+            }
+        }
+        let reverse = forward.rev();
+
+        (forward, reverse)
+    }
+
+    fn field_id_by_name(&self, node_id: NId, name: &str) -> Option<NId> {
+        let node = &self.nodes[node_id];
+        node.children()[..node.num_children()].iter().copied().find(|id| self.field_name(*id) == Some(name))
+    }
+
+    // Adds a field after the record's other fields.  A removed field isn't
+    // reused, since it may have had another name.  Returns the new node and
+    // the steps that add it and undo that.
+    fn node_add_field(&mut self, node_id: NId, name: &str, value: Value) -> (NId, OpStep, OpStep) {
+        let index = self.nodes[node_id].num_children();
+        let field_id = self.add_value_node(value.clone(), Some(node_id), index);
+        self.field_names_by_id.insert(field_id, name.to_string());
+        self.nodes[node_id].set_complex();
+        self.node_insert_id(node_id, field_id, index);
+        let define = OpStep::Define { id: field_id };
+        let insert = OpStep::Insert { id: node_id, child_id: field_id, index, value };
+        let reverse = insert.rev().then(define.rev());
+
+        (field_id, define.then(insert), reverse)
+    }
+
+    // Gives each field of a record a node of its own, so that the fields can
+    // be set individually.  Fields the record's node already has are set, and
+    // ones the record no longer has are removed.  Returns the steps that do
+    // this and the steps that undo it.
+    fn node_set_fields(&mut self, node_id: NId, fields: &[(Identifier, Value)]) -> (OpStep, OpStep) {
+        let (mut forward, mut reverse) = self.node_remove_children(node_id, |graph, field_id| {
+            !graph.field_name(field_id).is_some_and(|name| fields.iter().any(|(n, _)| n == name))
+        });
+        for (name, value) in fields.iter() {
+            let field_value = value.without_fields();
+            let field_id = match self.field_id_by_name(node_id, name) {
+                Some(field_id) => {
+                    let from = self.nodes[field_id].replace_value(field_value.clone());
+                    forward = forward.then(OpStep::Set { id: field_id, value: field_value });
+                    reverse = OpStep::Set { id: field_id, value: from }.then(reverse);
+
+                    field_id
+                }
+                None => {
+                    let (field_id, add, remove) = self.node_add_field(node_id, name, field_value);
+                    reverse = remove.then(reverse);
+                    forward = forward.then(add);
+
+                    field_id
+                }
+            };
+            let (nested_forward, nested_reverse) = match value {
+                Value::RecordVal(_, nested_fields) => self.node_set_fields(field_id, nested_fields),
+                _ => self.node_remove_elements(field_id),
+            };
+            forward = forward.then(nested_forward);
+            reverse = nested_reverse.then(reverse);
+        }
+
+        (forward, reverse)
+    }
+
+    // Removes the elements or fields of a node set to a value that isn't a
+    // record.  Elements of an array value are pushed after it's set.
+    fn node_remove_elements(&mut self, node_id: NId) -> (OpStep, OpStep) {
+        self.node_remove_children(node_id, |_, _| true)
+    }
+
+    fn snapshot(&self, node_id: NId) -> CellSnapshot {
+        let node = &self.nodes[node_id];
+        let children = node.children()[..node.num_children()].iter().map(|id| self.snapshot(*id)).collect();
+
+        CellSnapshot {
+            value: node.value().clone(),
+            field_name: self.field_name(node_id).map(str::to_string),
+            children,
+        }
+    }
+
+    // Replaces the elements or fields of a node with new nodes like the ones
+    // in a snapshot.  Returns the steps that do this and the steps that undo
+    // it.
+    fn node_copy_children(&mut self, node_id: NId, children: &[CellSnapshot]) -> (OpStep, OpStep) {
+        let (mut forward, mut reverse) = self.node_remove_elements(node_id);
+        for child in children.iter() {
+            let (child_id, add, remove) = match &child.field_name {
+                Some(name) => self.node_add_field(node_id, name, child.value.clone()),
+                None => {
+                    let index = self.nodes[node_id].num_children();
+                    let child_id = self.add_value_node(child.value.clone(), Some(node_id), index);
+                    self.nodes[node_id].set_complex();
+                    self.node_insert_id(node_id, child_id, index);
+                    let define = OpStep::Define { id: child_id };
+                    let insert = OpStep::Insert { id: node_id, child_id, index, value: child.value.clone() };
+                    let remove = insert.rev().then(define.rev());
+
+                    (child_id, define.then(insert), remove)
+                }
+            };
+            forward = forward.then(add);
+            reverse = remove.then(reverse);
+            let (nested_forward, nested_reverse) = self.node_copy_children(child_id, &child.children);
+            forward = forward.then(nested_forward);
+            reverse = nested_reverse.then(reverse);
+        }
+
+        (forward, reverse)
+    }
+
+    // Removes the children of a node that a predicate picks, last first, so
+    // that the indexes of the ones before stay the same.  Returns the steps
+    // that do this and the steps that undo it.
+    fn node_remove_children<F>(&mut self, node_id: NId, is_removed: F) -> (OpStep, OpStep)
+    where
+        F: Fn(&ProgramGraph, NId) -> bool,
+    {
+        let mut forward = OpStep::NoOp;
+        let mut reverse = OpStep::NoOp;
+        for index in (0..self.nodes[node_id].num_children()).rev() {
+            let child_id = self.nodes[node_id].children()[index];
+            if !is_removed(self, child_id) {
+                continue;
+            }
+            let value = self.nodes[child_id].value().clone();
+            self.node_remove(node_id, index);
+            forward = forward.then(OpStep::Remove { id: node_id, index });
+            reverse = OpStep::Insert { id: node_id, child_id, index, value }.then(reverse);
+        }
+
+        (forward, reverse)
+    }
+
+    fn node_id_by_loc_implicit_declare_array(&mut self, loc: &Location) -> (NId, OpStep) {
+        self.node_id_by_loc_implicit_declare(loc, Value::UndefinedVal)
+    }
+
+    fn node_push_implicit_create(&mut self, node_id: NId, value: Value) -> (NId, OpStep) {
+        let node = &mut self.nodes[node_id];
+        let capacity = node.children().len();
+        let index = node.num_children();
+        if index < capacity {
+            // The node is already there.  Reuse it.
+            node.increment_num_children();
+            let child_id = node.children()[index];
+            let child_node = &mut self.nodes[child_id];
+            child_node.set_value(value.clone());
+            // A removed node may have been at another index.
+            child_node.set_index(index);
+
+            return (child_id, OpStep::NoOp);
+        }
+
+        // Create a new node.
+        let child_id = self.add_value_node(value.clone(), Some(node_id), index);
+        let node = &mut self.nodes[node_id];
+        node.children_mut().push(child_id);
+        node.set_complex();
+        node.increment_num_children();
+
+        (child_id, OpStep::Define { id: child_id })
+    }
+
+    fn node_push_id(&mut self, node_id: NId, child_id: NId) {
+        let node = &self.nodes[node_id];
+        let index = node.num_children();
+        let child_node = &mut self.nodes[child_id];
+        child_node.set_parent_id(Some(node_id));
+        child_node.set_index(index);
+        let node = &mut self.nodes[node_id];
+        // Popped children stay linked, so when stepping through the ops
+        // again, the child is usually already in place.  Inserts and removes
+        // can leave it after other removed children, though.
+        if node.children().get(index) != Some(&child_id) {
+            node.children_mut().retain(|id| *id != child_id);
+            node.children_mut().insert(index, child_id);
+        }
+        node.increment_num_children();
+    }
+
+    fn node_pop(&mut self, node_id: NId) -> Option<NId> {
+        let node = &mut self.nodes[node_id];
+        let len = node.num_children();
+        if len == 0 {
+            return None;
         }
-        let child_id = node.children().get(len - 1).map(|n| *n).expect("child_id should be present");
+        let child_id = node.children().get(len - 1).copied().expect("child_id should be present");
         node.decrement_num_children();
         // Note: Do not unlink the child from the parent so that it can still be
         // drawn in the correct place.
@@ -457,8 +2362,98 @@ impl ProgramGraph {
         Some(child_id)
     }
 
-    // Used by State.
-    pub(self) fn process(&mut self, event: &Event) -> Option<Op> {
+    // Inserts a node into an array before the element at the index.  The
+    // node may be one that was removed earlier.
+    fn node_insert_id(&mut self, node_id: NId, child_id: NId, index: usize) {
+        let node = &mut self.nodes[node_id];
+        assert!(index <= node.num_children(), "Insert at index {} past the end of {:?}", index, node);
+        node.children_mut().retain(|id| *id != child_id);
+        node.children_mut().insert(index, child_id);
+        node.increment_num_children();
+        self.nodes[child_id].set_parent_id(Some(node_id));
+        self.node_renumber_children(node_id, index);
+    }
+
+    // Removes the element at the index.  Like popping, the node stays linked
+    // just past the elements, so that it can still be drawn where it was.
+    fn node_remove(&mut self, node_id: NId, index: usize) -> NId {
+        let node = &mut self.nodes[node_id];
+        assert!(index < node.num_children(), "Remove at index {} past the end of {:?}", index, node);
+        let child_id = node.children_mut().remove(index);
+        node.decrement_num_children();
+        let len = node.num_children();
+        node.children_mut().insert(len, child_id);
+        self.node_renumber_children(node_id, index);
+
+        child_id
+    }
+
+    // Updates the indexes of the elements from the index on.
+    fn node_renumber_children(&mut self, node_id: NId, from_index: usize) {
+        let node = &self.nodes[node_id];
+        let ids = node.children()[from_index..node.num_children()].to_vec();
+        for (i, id) in ids.into_iter().enumerate() {
+            self.nodes[id].set_index(from_index + i);
+        }
+    }
+
+    // Whether a node is in the subtree under another, like an element of an
+    // element of an array.
+    fn is_ancestor(&self, ancestor_id: NId, node_id: NId) -> bool {
+        let mut parent_id = self.nodes[node_id].parent_id();
+        while let Some(id) = parent_id {
+            if *id == ancestor_id {
+                return true;
+            }
+            parent_id = self.nodes[*id].parent_id();
+        }
+
+        false
+    }
+
+    // Exchanges the places of two nodes in their arrays.  Doing it again
+    // swaps them back.
+    fn node_swap(&mut self, a: NId, b: NId) {
+        let parent_a = *self.nodes[a].parent_id().expect("swapped node should have a parent");
+        let parent_b = *self.nodes[b].parent_id().expect("swapped node should have a parent");
+        let index_a = self.nodes[a].index();
+        let index_b = self.nodes[b].index();
+        self.nodes[parent_a].children_mut()[index_a] = b;
+        self.nodes[parent_b].children_mut()[index_b] = a;
+        self.nodes[a].set_parent_id(Some(parent_b));
+        self.nodes[a].set_index(index_b);
+        self.nodes[b].set_parent_id(Some(parent_a));
+        self.nodes[b].set_index(index_a);
+    }
+
+    // Used by State and the HTML export.
+    pub(crate) fn process(&mut self, meta: &EventMeta, event: &Event) -> Option<Op> {
+        self.thread = meta.thread.clone();
+        let thread_step = match &meta.thread {
+            Some(name) if !self.threads.contains_key(name) => {
+                // The first event of a thread adds its header.
+                let id = self.next_id();
+                self.nodes.push(Node::new_thread(id));
+                self.names_by_id.insert(id, name.clone());
+                self.threads.insert(name.clone(), Timeline { id: Some(id), ..Timeline::default() });
+
+                OpStep::Define { id }
+            }
+            _ => OpStep::NoOp,
+        };
+        let op = self.process_event(event);
+        if thread_step.is_no_op() {
+            return op;
+        }
+        let op = op.unwrap_or(Op { forward: OpStep::NoOp, reverse: OpStep::NoOp });
+
+        Some(Op::from_steps(op.forward, op.reverse, thread_step))
+    }
+
+    fn process_event(&mut self, event: &Event) -> Option<Op> {
+        if let Err(message) = self.check_indexes(event) {
+            return reject(event, &message, OpStep::NoOp);
+        }
         match event {
             Event::NoOp => Some(Op { forward: OpStep::NoOp, reverse: OpStep::NoOp }),
             Event::Display(loc, display_type) => {
@@ -468,22 +2463,82 @@ impl ProgramGraph {
 
                 Some(step.into_op())
             }
+            Event::IndexInto(loc, array_loc) => {
+                let (node_id, step1) = self.node_id_by_loc_implicit_declare(loc, Value::UndefinedVal);
+                let (array_id, step2) = self.node_id_by_loc_implicit_declare_array(array_loc);
+                self.index_into_by_id.insert(node_id, array_id);
+
+                Some(step1.then(step2).into_op())
+            }
             Event::Set(loc, value) => {
                 let (node_id, prev_step) = self.node_id_by_loc_implicit_declare(loc, Value::UndefinedVal);
                 let node = &mut self.nodes[node_id];
-                let from = node.replace_value(value.clone());
-                let forward = OpStep::Set { id: node.id(), value: value.clone() };
-                let reverse = OpStep::Set { id: node.id(), value: from };
+                let from = node.replace_value(value.without_fields());
+                let mut forward = OpStep::Set { id: node_id, value: value.without_fields() };
+                let mut reverse = OpStep::Set { id: node_id, value: from };
+                let (fields_forward, fields_reverse) = match value {
+                    Value::RecordVal(_, fields) => self.node_set_fields(node_id, fields),
+                    _ => self.node_remove_elements(node_id),
+                };
+                forward = forward.then(fields_forward);
+                reverse = fields_reverse.then(reverse);
 
                 Some(Op::from_steps(forward, reverse, prev_step))
             }
+            Event::Forget(name) => {
+                let id = match self.forget_name(name) {
+                    Some(id) => id,
+                    None => return reject(event, "no such variable", OpStep::NoOp),
+                };
+
+                Some(Op { forward: OpStep::Forget { id }, reverse: OpStep::Define { id } })
+            }
+            Event::EnterFrame(name) => {
+                let id = self.next_id();
+                self.nodes.push(Node::new_frame(id));
+                self.names_by_id.insert(id, name.clone());
+                let timeline = self.timeline_mut();
+                let depth = timeline.frames.len();
+                timeline.frames.push(Frame { id, ids_by_name: HashMap::new() });
+                if let Some(thread_id) = timeline.id {
+                    self.thread_ids_by_id.insert(id, thread_id);
+                }
+                self.frame_depths_by_id.insert(id, depth);
+
+                Some(Op { forward: OpStep::Define { id }, reverse: OpStep::Forget { id } })
+            }
+            Event::ExitFrame => {
+                let frame = match self.timeline_mut().frames.pop() {
+                    Some(frame) => frame,
+                    None => return reject(event, "no call to exit", OpStep::NoOp),
+                };
+                let mut var_ids: Vec<NId> = frame.ids_by_name.values().copied().collect();
+                var_ids.sort_unstable();
+                let mut forward = OpStep::NoOp;
+                let mut reverse = OpStep::Define { id: frame.id };
+                for id in var_ids {
+                    forward = forward.then(OpStep::Forget { id });
+                    reverse = reverse.then(OpStep::Define { id });
+                }
+                forward = forward.then(OpStep::Forget { id: frame.id });
+
+                Some(Op { forward, reverse })
+            }
             Event::Push(loc, value) => {
                 let (node_id, step1) = self.node_id_by_loc_implicit_declare_array(loc);
-                let (child_id, step2) = self.node_push_implicit_create(node_id, value.clone());
+                let (child_id, step2) = self.node_push_implicit_create(node_id, value.without_fields());
                 let prev_step = step1.then(step2);
-                let forward = OpStep::Push { id: node_id, child_id, value: value.clone() };
+                let mut forward = OpStep::Push { id: node_id, child_id, value: value.without_fields() };
+                let mut reverse = forward.rev();
+                // A popped element that's pushed again may have had fields.
+                let (fields_forward, fields_reverse) = match value {
+                    Value::RecordVal(_, fields) => self.node_set_fields(child_id, fields),
+                    _ => self.node_remove_elements(child_id),
+                };
+                forward = forward.then(fields_forward);
+                reverse = fields_reverse.then(reverse);
 
-                Some(forward.into_op_with_previous(prev_step))
+                Some(Op::from_steps(forward, reverse, prev_step))
             }
             Event::Pop(loc) => {
                 let (node_id, prev_step) = self.node_id_by_loc_implicit_declare_array(loc);
@@ -502,11 +2557,171 @@ impl ProgramGraph {
 
                 Some(Op::from_steps(forward, reverse, prev_step))
             }
+            Event::Insert(loc, index, value) => {
+                let (node_id, step1) = self.node_id_by_loc_implicit_declare_array(loc);
+                if *index > self.nodes[node_id].num_children() {
+                    return reject(event, "insert past the end of the array", step1);
+                }
+                let child_id = self.add_value_node(value.without_fields(), Some(node_id), *index);
+                self.nodes[node_id].set_complex();
+                self.node_insert_id(node_id, child_id, *index);
+                let prev_step = step1.then(OpStep::Define { id: child_id });
+                let mut forward = OpStep::Insert { id: node_id, child_id, index: *index, value: value.without_fields() };
+                let mut reverse = forward.rev();
+                if let Value::RecordVal(_, fields) = value {
+                    let (fields_forward, fields_reverse) = self.node_set_fields(child_id, fields);
+                    forward = forward.then(fields_forward);
+                    reverse = fields_reverse.then(reverse);
+                }
+
+                Some(Op::from_steps(forward, reverse, prev_step))
+            }
+            Event::Remove(loc, index) => {
+                let (node_id, prev_step) = self.node_id_by_loc_implicit_declare_array(loc);
+                if *index >= self.nodes[node_id].num_children() {
+                    return reject(event, "remove past the end of the array", prev_step);
+                }
+                let child_id = self.node_remove(node_id, *index);
+                let forward = OpStep::Remove { id: node_id, index: *index };
+                let value = self.nodes[child_id].value().clone();
+                let reverse = OpStep::Insert { id: node_id, child_id, index: *index, value };
+
+                Some(Op::from_steps(forward, reverse, prev_step))
+            }
+            Event::Tag(loc, name) | Event::Untag(loc, name) => {
+                let (ids, prev_step) = self.node_ids_by_slice_loc(loc);
+                let tag = self.tag_by_name_implicit_create(name);
+                let add = matches!(event, Event::Tag(..));
+                let (forward, reverse) = self.node_tag_steps(&ids, tag, add);
+
+                Some(Op::from_steps(forward, reverse, prev_step))
+            }
+            Event::Copy(src, dst) | Event::Move(src, dst) => {
+                let (src_ids, prev_step) = self.node_ids_by_slice_loc(src);
+                // Taken before copying, since the slices may overlap.
+                let snapshots: Vec<CellSnapshot> = src_ids.iter().map(|id| self.snapshot(*id)).collect();
+                let values: Vec<Value> = snapshots.iter().map(|snapshot| snapshot.value.clone()).collect();
+                let is_slice = matches!(src, SliceLocation::SliceLoc(..));
+                let (dst_ids, mut forward, push_reverse) = match self.node_ids_for_copy(dst, &values, is_slice) {
+                    Ok(copy) => copy,
+                    Err(step) => return reject(event, "copy past the end of the array", prev_step.then(step)),
+                };
+                let mut reverse = OpStep::NoOp;
+                for ((dst_id, src_id), snapshot) in dst_ids.iter().zip(src_ids.iter()).zip(snapshots.iter()) {
+                    let from = self.nodes[*dst_id].replace_value(snapshot.value.clone());
+                    forward = forward.then(OpStep::Copy { id: *dst_id, from_id: *src_id, value: snapshot.value.clone() });
+                    reverse = OpStep::Set { id: *dst_id, value: from }.then(reverse);
+                    let (children_forward, children_reverse) = self.node_copy_children(*dst_id, &snapshot.children);
+                    forward = forward.then(children_forward);
+                    reverse = children_reverse.then(reverse);
+                }
+                if let Event::Move(..) = event {
+                    // Moved values go back where they came from when undone,
+                    // with their elements or fields.
+                    for ((src_id, dst_id), value) in src_ids.iter().zip(dst_ids.iter()).zip(values.iter()) {
+                        if dst_ids.contains(src_id) {
+                            continue;
+                        }
+                        self.nodes[*src_id].set_value(Value::UndefinedVal);
+                        forward = forward.then(OpStep::Set { id: *src_id, value: Value::UndefinedVal });
+                        let (children_forward, children_reverse) = self.node_remove_elements(*src_id);
+                        forward = forward.then(children_forward);
+                        reverse = OpStep::Copy { id: *src_id, from_id: *dst_id, value: value.clone() }
+                            .then(children_reverse)
+                            .then(reverse);
+                    }
+                }
+
+                Some(Op::from_steps(forward, reverse.then(push_reverse), prev_step))
+            }
+            Event::Swap(loc1, loc2) => {
+                let (a, step1) = self.node_id_by_loc_implicit_declare(loc1, Value::UndefinedVal);
+                let (b, step2) = self.node_id_by_loc_implicit_declare(loc2, Value::UndefinedVal);
+                let prev_step = step1.then(step2);
+                if self.is_ancestor(a, b) || self.is_ancestor(b, a) {
+                    return reject(event, "can't swap an element with its own element", prev_step);
+                }
+                let is_element = |id: NId| self.nodes[id].parent_id().is_some() && self.field_name(id).is_none();
+                if is_element(a) && is_element(b) {
+                    self.node_swap(a, b);
+
+                    return Some(OpStep::Swap { a, b }.into_op_with_previous(prev_step));
+                }
+
+                // Variables can't move, and fields stay in the order of their
+                // record, so exchange their values and elements or fields
+                // instead.
+                let snapshot_a = self.snapshot(a);
+                let snapshot_b = self.snapshot(b);
+                let mut forward = OpStep::NoOp;
+                let mut reverse = OpStep::NoOp;
+                for (id, from, to) in [(a, &snapshot_a, &snapshot_b), (b, &snapshot_b, &snapshot_a)].iter() {
+                    self.nodes[*id].set_value(to.value.clone());
+                    let (children_forward, children_reverse) = self.node_copy_children(*id, &to.children);
+                    forward = forward.then(OpStep::Set { id: *id, value: to.value.clone() }).then(children_forward);
+                    reverse = children_reverse.then(OpStep::Set { id: *id, value: from.value.clone() }).then(reverse);
+                }
+
+                Some(Op::from_steps(forward, reverse, prev_step))
+            }
+        }
+    }
+
+    // Processes a whole trace.  Like AppState's, the ops start with a no-op,
+    // so that op 0 is the start, and the model is left at the last op.
+    pub fn process_events(&mut self, events: &[(EventMeta, Event)]) -> Vec<Op> {
+        let mut ops = vec![Op { forward: OpStep::NoOp, reverse: OpStep::NoOp }];
+        ops.extend(events.iter().filter_map(|(meta, event)| self.process(meta, event)));
+
+        ops
+    }
+
+    // Plays or undoes ops from one op to another, returning what each op
+    // changed.
+    pub fn seek(&mut self, ops: &[Op], from_index: usize, to_index: usize) -> Vec<ChangeType> {
+        let mut changes = Vec::new();
+        let mut op_index = from_index;
+        while op_index < to_index {
+            op_index += 1;
+            changes.push(self.step(&ops[op_index].forward));
+        }
+        while op_index > to_index {
+            changes.push(self.step(&ops[op_index].reverse));
+            op_index -= 1;
+        }
+
+        changes
+    }
+
+    // Like seek, adding what each op did to the histories of the cells.
+    fn seek_histories(&mut self, ops: &[Op], from_index: usize, to_index: usize,
+                      histories: &mut BTreeMap<NId, CellHistory>) {
+        let mut op_index = from_index;
+        while op_index != to_index {
+            let changes = if op_index < to_index {
+                op_index += 1;
+                self.step(&ops[op_index].forward)
+            } else {
+                op_index -= 1;
+                self.step(&ops[op_index + 1].reverse)
+            };
+            ChangeSummary::add_step(histories, &changes, self);
         }
     }
 
+    // What changes from one op to another, which may be before it, with the
+    // model at a third op.  The model is left where it was.
+    pub fn changes_between(&mut self, ops: &[Op], op_index: usize, from_index: usize, to_index: usize) -> ChangeSummary {
+        self.seek(ops, op_index, from_index);
+        let mut histories = BTreeMap::new();
+        self.seek_histories(ops, from_index, to_index, &mut histories);
+        let summary = ChangeSummary::from_histories(&histories, self);
+        self.seek(ops, to_index, op_index);
+
+        summary
+    }
+
     pub(self) fn step(&mut self, op_step: &OpStep) -> ChangeType {
-        eprintln!("OpStep {:?}", op_step);
         match op_step {
             OpStep::NoOp => ChangeType::NoChange,
             OpStep::Atomic { steps } => {
@@ -517,10 +2732,10 @@ impl ProgramGraph {
                 ChangeType::Parallel { changes }
             }
             OpStep::Define { id } => {
-                ChangeType::AddCell { id: *id }
+                self.live_subtree_changes(*id, |id| ChangeType::AddCell { id })
             }
             OpStep::Forget { id } => {
-                ChangeType::RemoveCell { id: *id }
+                self.live_subtree_changes(*id, |id| ChangeType::RemoveCell { id })
             }
             OpStep::Set { id, value } => {
                 let node = &mut self.nodes[*id];
@@ -530,6 +2745,13 @@ impl ProgramGraph {
 
                 ChangeType::ValueChange { id: *id, from, to: value.display_string() }
             }
+            OpStep::Copy { id, from_id, value } => {
+                let node = &mut self.nodes[*id];
+                let from = node.value().display_string();
+                node.set_value(value.clone());
+
+                ChangeType::CopyCell { id: *id, from_id: *from_id, from, to: value.display_string() }
+            }
             OpStep::Push { id, child_id, value } => {
                 let id = *id;
                 let value = value.clone();
@@ -549,6 +2771,493 @@ impl ProgramGraph {
                     None => ChangeType::NoChange,
                 }
             }
+            OpStep::Tag { id, tag } => {
+                self.nodes[*id].add_tag(*tag);
+
+                ChangeType::TagChange { id: *id }
+            }
+            OpStep::Untag { id, tag } => {
+                self.nodes[*id].remove_tag(*tag);
+
+                ChangeType::TagChange { id: *id }
+            }
+            OpStep::Insert { id, child_id, index, value } => {
+                self.nodes[*child_id].set_value(value.clone());
+                self.node_insert_id(*id, *child_id, *index);
+
+                ChangeType::AddCell { id: *child_id }
+            }
+            OpStep::Remove { id, index } => {
+                let child_id = self.node_remove(*id, *index);
+
+                ChangeType::RemoveCell { id: child_id }
+            }
+            OpStep::Swap { a, b } => {
+                self.node_swap(*a, *b);
+
+                ChangeType::Parallel {
+                    changes: vec![ChangeType::MoveCell { id: *a }, ChangeType::MoveCell { id: *b }],
+                }
+            }
+        }
+    }
+}
+
+// The location of a slice's array, or the location itself when it isn't a
+// slice.
+fn array_loc(loc: &SliceLocation) -> &Location {
+    match loc {
+        SliceLocation::SimpleLoc(loc) | SliceLocation::SliceLoc(loc, ..) => loc,
+    }
+}
+
+// Reports an event that can't be applied to the program's state.  Variables
+// it declared on the way stay declared.
+fn reject(event: &Event, message: &str, prev_step: OpStep) -> Option<Op> {
+    eprintln!("Ignoring {}: {}", event, message);
+    if prev_step.is_no_op() {
+        return None;
+    }
+
+    Some(prev_step.into_op())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::trace;
+
+    fn process_trace(source: &str) -> ProgramGraph {
+        let mut graph = ProgramGraph::new();
+        for (meta, event) in trace::parse_trace(source).expect("Invalid trace") {
+            graph.process(&meta, &event);
+        }
+
+        graph
+    }
+
+    // A state showing a trace from its start, laid out with the bundled font.
+    pub(crate) fn state_for_trace(source: &str) -> AppState {
+        let font_path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/fonts/liberation_mono/LiberationMono-Regular.ttf");
+        let mut state = AppState::new(FontMetrics::from_file(font_path).expect("Couldn't load font"));
+        for (meta, event) in trace::parse_trace(source).expect("Invalid trace") {
+            state.add_event(meta, event);
+        }
+        state.player_reset_to_start();
+        state.init_draw_states();
+        state.update_layout();
+
+        state
+    }
+
+    pub(crate) fn id_of(graph: &ProgramGraph, name: &str) -> NId {
+        graph.node_id_by_name(name).unwrap_or_else(|| panic!("No variable {}", name))
+    }
+
+    // The values of variables after the last event of a trace, then with
+    // that event undone, then done again.
+    fn last_event_both_ways(source: &str, names: &[&str]) -> (Vec<String>, Vec<String>) {
+        let mut graph = ProgramGraph::new();
+        let ops = graph.process_events(&trace::parse_trace(source).expect("Invalid trace"));
+        let last = ops.len() - 1;
+        let values = |graph: &ProgramGraph| -> Vec<String> {
+            names.iter().map(|name| graph.full_value(id_of(graph, name)).display_string()).collect()
+        };
+        let after = values(&graph);
+        graph.seek(&ops, last, last - 1);
+        let before = values(&graph);
+        graph.seek(&ops, last - 1, last);
+        assert_eq!(values(&graph), after, "doing the event again");
+
+        (before, after)
+    }
+
+    fn field_names(graph: &ProgramGraph, node_id: NId) -> Vec<&str> {
+        let node = graph.node(node_id);
+        node.children()[..node.num_children()].iter().map(|id| graph.field_name(*id).unwrap_or("")).collect()
+    }
+
+    #[test]
+    fn setting_a_missing_field_adds_it() {
+        let graph = process_trace("set p = Point { x: 1 }\nset p.y = 2\n");
+        let p = id_of(&graph, "p");
+        assert_eq!(field_names(&graph, p), vec!["x", "y"]);
+        assert_eq!(graph.full_value(p).display_string(), "Point { x: 1, y: 2 }");
+    }
+
+    #[test]
+    fn setting_a_record_removes_the_fields_it_lacks() {
+        let mut graph = process_trace("set p = Point { x: 1, y: 2 }\n");
+        let (meta, event) = trace::parse_trace("set p = Point { y: 3, z: 4 }\n").expect("Invalid trace").remove(0);
+        let op = graph.process(&meta, &event).expect("Event should be applied");
+        let p = id_of(&graph, "p");
+        assert_eq!(field_names(&graph, p), vec!["y", "z"]);
+        assert_eq!(graph.full_value(p).display_string(), "Point { y: 3, z: 4 }");
+
+        graph.step(&op.reverse);
+        assert_eq!(field_names(&graph, p), vec!["x", "y"]);
+        assert_eq!(graph.full_value(p).display_string(), "Point { x: 1, y: 2 }");
+        graph.step(&op.forward);
+        assert_eq!(graph.full_value(p).display_string(), "Point { y: 3, z: 4 }");
+    }
+
+    #[test]
+    fn setting_a_record_to_something_else_removes_its_fields() {
+        let mut graph = process_trace("set p = Point { x: 1, y: 2 }\nset q = P { a: Q { x: 1 } }\n");
+        let p = id_of(&graph, "p");
+        let q = id_of(&graph, "q");
+        let a = graph.field_id_by_name(q, "a").expect("No field a");
+        let mut ops = Vec::new();
+        for (meta, event) in trace::parse_trace("set p = 5\nset q = P { a: 5 }\n").expect("Invalid trace") {
+            ops.push(graph.process(&meta, &event).expect("Event should be applied"));
+        }
+        assert!(field_names(&graph, p).is_empty());
+        assert_eq!(graph.full_value(p).display_string(), "5");
+        assert!(field_names(&graph, a).is_empty());
+        assert_eq!(graph.full_value(q).display_string(), "P { a: 5 }");
+
+        for op in ops.iter().rev() {
+            graph.step(&op.reverse);
+        }
+        assert_eq!(graph.full_value(p).display_string(), "Point { x: 1, y: 2 }");
+        assert_eq!(graph.full_value(q).display_string(), "P { a: Q { x: 1 } }");
+        for op in ops.iter() {
+            graph.step(&op.forward);
+        }
+        assert_eq!(graph.full_value(p).display_string(), "5");
+        assert_eq!(graph.full_value(q).display_string(), "P { a: 5 }");
+    }
+
+    #[test]
+    fn index_pointers_are_in_the_order_of_the_variables() {
+        let graph = process_trace("set arr = [1, 2, 3]\nset a = 0\nset b = 1\nset c = 2\n\
+                                   display c index_into arr\ndisplay a index_into arr\ndisplay b index_into arr\n");
+        let arr = id_of(&graph, "arr");
+        let expected: Vec<(NId, NId)> = ["a", "b", "c"].iter().map(|name| (id_of(&graph, name), arr)).collect();
+        assert_eq!(graph.index_pointers().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn tagging_a_slice_leaves_out_the_part_past_the_end() {
+        let graph = process_trace("push arr 1\npush arr 2\npush arr 3\npush arr 4\npop arr\n\
+                                   tag arr[1..10] done\ntag arr[3..1] other\n");
+        let arr = id_of(&graph, "arr");
+        let tagged: Vec<usize> = (0..4).filter(|i| !graph.node(graph.node(arr).children()[*i]).tags().is_empty()).collect();
+        assert_eq!(tagged, vec![1, 2]);
+    }
+
+    #[test]
+    fn copying_copies_the_fields_and_elements_too() {
+        let (before, after) = last_event_both_ways("push a P { x: 1, y: 2 }\npush a P { x: 3, y: Q { z: 4 } }\n\
+                                                    push b 0\ncopy a[0..2] into b[0]\n", &["a", "b"]);
+        assert_eq!(before, vec!["[P { x: 1, y: 2 }, P { x: 3, y: Q { z: 4 } }]", "[0]"]);
+        assert_eq!(after, vec!["[P { x: 1, y: 2 }, P { x: 3, y: Q { z: 4 } }]",
+                               "[P { x: 1, y: 2 }, P { x: 3, y: Q { z: 4 } }]"]);
+
+        let (before, after) = last_event_both_ways("push a []\npush a[0] 5\npush a []\npush a[1] 6\npush a[1] 7\n\
+                                                    copy a[0..2] into a[1]\n", &["a"]);
+        assert_eq!(before, vec!["[[5], [6, 7]]"]);
+        // The slices overlap, so the second element is copied as it was.
+        assert_eq!(after, vec!["[[5], [5], [6, 7]]"]);
+    }
+
+    #[test]
+    fn moving_takes_the_fields_and_elements_along() {
+        let source = "push a P { x: 1 }\npush a []\npush a[1] 2\npush b 0\npush b 0\nmove a[0..2] into b[0]\n";
+        let (before, after) = last_event_both_ways(source, &["a", "b"]);
+        assert_eq!(before, vec!["[P { x: 1 }, [2]]", "[0, 0]"]);
+        assert_eq!(after[1], "[P { x: 1 }, [2]]");
+        let graph = process_trace(source);
+        let a = id_of(&graph, "a");
+        for element in graph.node(a).children()[..2].iter() {
+            assert_eq!(graph.node(*element).value(), &Value::UndefinedVal);
+            assert_eq!(graph.node(*element).num_children(), 0);
+        }
+    }
+
+    #[test]
+    fn copying_past_the_end_of_an_array_is_ignored() {
+        let graph = process_trace("push tmp 7\npush arr 1\ncopy tmp[0..1] into arr[5]\ncopy tmp[0..1] into arr[1]\n");
+        let arr = id_of(&graph, "arr");
+        assert_eq!(graph.full_value(arr), Value::ArrayVal(vec![Value::I32Val(1), Value::I32Val(7)]));
+    }
+
+    #[test]
+    fn inserting_or_removing_past_the_end_of_an_array_is_ignored() {
+        let graph = process_trace("push arr 1\nremove arr 5\ninsert arr 3 2\nremove arr 1\ninsert arr 1 3\n");
+        let arr = id_of(&graph, "arr");
+        assert_eq!(graph.full_value(arr), Value::ArrayVal(vec![Value::I32Val(1), Value::I32Val(3)]));
+    }
+
+    #[test]
+    fn tagging_setting_or_swapping_past_the_end_of_an_array_is_ignored() {
+        let graph = process_trace("push arr 1\npush arr 2\ntag arr[3] x\ntag arr[1] y\nset arr[5] = 3\nset arr[0] = 4\n\
+                                   swap arr[0] arr[4]\nset v = [1, 2]\nset v[1] = 3\n\
+                                   swap arr[0] arr[1]\n");
+        let arr = id_of(&graph, "arr");
+        assert_eq!(graph.full_value(arr), Value::ArrayVal(vec![Value::I32Val(2), Value::I32Val(4)]));
+        let tagged: Vec<usize> = (0..2).filter(|i| !graph.node(graph.node(arr).children()[*i]).tags().is_empty()).collect();
+        // The tagged element was swapped to the front.
+        assert_eq!(tagged, vec![0]);
+        assert_eq!(graph.full_value(id_of(&graph, "v")), Value::ArrayVal(vec![Value::I32Val(1), Value::I32Val(2)]));
+    }
+
+    #[test]
+    fn forgetting_or_exiting_nothing_is_ignored() {
+        let graph = process_trace("set x = 1\nforget nothere\nexit\nenter f\nexit\nexit\n");
+        let x = id_of(&graph, "x");
+        assert_eq!(graph.full_value(x), Value::I32Val(1));
+    }
+
+    #[test]
+    fn calls_see_the_variables_defined_outside_of_calls() {
+        let graph = process_trace("set g = 1\nenter f\nset g = 2\nset n = 3\nenter f\nset n = 4\nexit\n");
+        let g = id_of(&graph, "g");
+        assert_eq!(graph.frame_id(g), None);
+        assert_eq!(graph.full_value(g), Value::I32Val(2));
+        // The outer call's n is hidden by the inner call's own.
+        assert_eq!(graph.full_value(id_of(&graph, "n")), Value::I32Val(3));
+    }
+
+    #[test]
+    fn calls_at_the_same_depth_share_columns() {
+        let state = state_for_trace("enter f\nset a = 1\nexit\nenter g\nset b = 2\nenter h\nset c = 3\nexit\nexit\n");
+        let col_of = |name: &str| {
+            let frame = state.graph.nodes_iter()
+                .find(|node| node.node_type == NodeType::Frame && state.graph.header_label(node.id()) == name)
+                .unwrap_or_else(|| panic!("No frame {}", name));
+            state.grid_coords[frame.id()].expect("Frame should be placed")[0]
+        };
+        assert_eq!(col_of("f"), col_of("g"));
+        assert_eq!(col_of("h"), col_of("g") + 1);
+    }
+
+    #[test]
+    fn threads_look_up_names_in_calls_then_their_own_then_shared_variables() {
+        let graph = process_trace("set s = 1\nthread=t set own = 2\nthread=t enter f\nthread=t set own = 3\n\
+                                   thread=t set s = 4\nthread=t set local = 5\nthread=t forget s\n");
+        let t = graph.thread_id_by_name("t").expect("Thread should be there");
+        let own = graph.nodes_iter()
+            .find(|node| graph.node_name(node) == Some("own"))
+            .expect("Variable should be there");
+        assert_eq!(graph.thread_id(own.id()), Some(t));
+        assert_eq!(graph.frame_id(own.id()), None);
+        assert_eq!(graph.full_value(own.id()), Value::I32Val(3));
+        assert_eq!(graph.node_id_by_name("s"), None);
+        assert_eq!(graph.nodes_iter().filter(|node| graph.node_name(node) == Some("s")).count(), 1);
+    }
+
+    const CHANGES_TRACE: &str = "set a = 1\nset b = 2\npush arr 1\nset a = 5\nforget b\npush arr 2\nset a = 1\n";
+
+    #[test]
+    fn changes_between_ops_either_way() {
+        let mut graph = ProgramGraph::new();
+        let ops = graph.process_events(&trace::parse_trace(CHANGES_TRACE).expect("Invalid trace"));
+        let last = ops.len() - 1;
+        let a = id_of(&graph, "a");
+        let b = graph.nodes_iter().find(|node| graph.node_name(node) == Some("b")).expect("b should be there").id();
+        let new_element = graph.node(id_of(&graph, "arr")).children()[1];
+
+        let summary = graph.changes_between(&ops, last, 3, 6);
+        let expected: BTreeMap<NId, CellChange> = vec![
+            (a, CellChange::Changed { from: "1".to_string(), to: "5".to_string() }),
+            (b, CellChange::Removed { value: "2".to_string() }),
+            (new_element, CellChange::Added { value: "2".to_string() }),
+        ].into_iter().collect();
+        assert_eq!(summary.cells, expected);
+
+        let summary = graph.changes_between(&ops, last, 6, 3);
+        assert_eq!(summary.cells[&b], CellChange::Added { value: "2".to_string() });
+        assert_eq!(summary.cells[&new_element], CellChange::Removed { value: "2".to_string() });
+        // Changed and then changed back.
+        assert_eq!(graph.changes_between(&ops, last, 3, 7).cells.get(&a), None);
+        assert_eq!(graph.full_value(a), Value::I32Val(1));
+    }
+
+    #[test]
+    fn the_diff_follows_the_player_from_the_mark() {
+        let mut state = state_for_trace(CHANGES_TRACE);
+        state.player_go_to(2);
+        state.toggle_diff_mark();
+        for op_index in [5, 7, 4, 1, 6].iter().copied() {
+            state.player_go_to(op_index);
+            let expected = state.graph.changes_between(&state.ops, op_index, 2, op_index);
+            assert_eq!(state.diff.as_ref(), Some(&expected), "at op {}", op_index);
+        }
+    }
+
+    #[test]
+    fn the_inspector_scrolls_a_long_value_and_follows_the_player() {
+        let long = "x".repeat(2000);
+        let source = format!("set s = \"{}\"\nset s = \"short\"\n", long);
+        let mut state = state_for_trace(&source);
+        state.player_go_to(1);
+        state.select(Some(id_of(&state.graph, "s")));
+        let inspector = state.inspector.as_ref().expect("No inspector");
+        // The whole value, wrapped.
+        assert!(inspector.value_lines.len() > INSPECTOR_VALUE_LINES);
+        assert_eq!(inspector.value_lines.concat(), format!("value: {:?}", long));
+        let max_scroll = inspector.value_lines.len() - INSPECTOR_VALUE_LINES;
+        state.scroll_inspector(3);
+        state.scroll_inspector(-1);
+        assert_eq!(state.inspector.as_ref().map(|inspector| inspector.scroll), Some(2));
+        state.scroll_inspector(isize::MAX);
+        assert_eq!(state.inspector.as_ref().map(|inspector| inspector.scroll), Some(max_scroll));
+        state.player_advance();
+        let inspector = state.inspector.as_ref().expect("No inspector");
+        assert_eq!(inspector.value_lines, vec!["value: \"short\"".to_string()]);
+        assert_eq!(inspector.scroll, 0);
+        state.select(None);
+        assert!(state.inspector.is_none());
+    }
+
+    #[test]
+    fn new_events_keep_the_op_unless_following_the_end() {
+        let events = |source: &str| trace::parse_trace(source).expect("Invalid trace");
+        let mut state = state_for_trace("set a = 1\nset a = 2\n");
+        state.follow_end = false;
+        state.player_go_to(1);
+        state.append_events(events("set a = 3\nset b = 4\n"), None);
+        assert_eq!((state.op_index, state.ops.len()), (1, 5));
+        assert_eq!(state.graph.full_value(id_of(&state.graph, "a")), Value::I32Val(1));
+
+        state.toggle_follow_end();
+        assert_eq!(state.op_index, 4);
+        state.append_events(events("set b = 5\n"), None);
+        assert_eq!((state.op_index, state.ops.len()), (5, 6));
+        assert_eq!(state.graph.full_value(id_of(&state.graph, "b")), Value::I32Val(5));
+
+        // A new run is followed to its end.
+        state.replace_events(events("set c = 6\n"), None);
+        assert_eq!((state.op_index, state.ops.len()), (1, 2));
+
+        // Or shown from the same op.
+        state.toggle_follow_end();
+        state.player_go_to(0);
+        state.replace_events(events("set c = 7\nset c = 8\n"), None);
+        assert_eq!((state.op_index, state.ops.len()), (0, 3));
+        state.player_go_to(2);
+        state.replace_events(events("set c = 9\nset d = 10\nset e = 11\n"), None);
+        assert_eq!((state.op_index, state.ops.len()), (2, 4));
+        assert_eq!(state.graph.full_value(id_of(&state.graph, "d")), Value::I32Val(10));
+    }
+
+    #[test]
+    fn timed_playback_delays_in_proportion_to_the_time_between_events() {
+        // Ten one second steps, then a short and a long one.
+        let mut times: Vec<f64> = (0..=10).map(f64::from).collect();
+        times.push(10.01);
+        times.push(40.01);
+        let mut source: String = times.iter().map(|time| format!("time={} set a = 1\n", time)).collect();
+        source.push_str("set a = 2\n");
+        let mut state = state_for_trace(&source);
+        let average = 40.01 / 12.0;
+        let delay_at = |state: &mut AppState, op_index| {
+            state.player_go_to(op_index);
+            state.playback_delay()
+        };
+        assert_eq!(delay_at(&mut state, 1), PLAYBACK_DELAY);
+
+        state.toggle_timed_playback();
+        assert!((delay_at(&mut state, 1) - PLAYBACK_DELAY / average).abs() < 1e-9);
+        assert_eq!(delay_at(&mut state, 11), MIN_TIMED_PLAYBACK_DELAY);
+        assert_eq!(delay_at(&mut state, 12), MAX_TIMED_PLAYBACK_DELAY);
+        // Ops without timestamps play at the usual rate.
+        assert_eq!(delay_at(&mut state, 0), PLAYBACK_DELAY);
+        assert_eq!(delay_at(&mut state, 13), PLAYBACK_DELAY);
+
+        // The average is of the new trace once it's replaced.
+        state.replace_events(trace::parse_trace("time=0 set a = 1\ntime=2 set a = 2\ntime=4 set a = 3\n")
+                                 .expect("Invalid trace"), None);
+        assert_eq!(delay_at(&mut state, 1), PLAYBACK_DELAY);
+    }
+
+    #[test]
+    fn a_growing_nested_array_pushes_its_later_siblings_down() {
+        let mut state = state_for_trace("push t []\npush t 1\npush t[0] 10\npush t[0] 11\npush t[0] 12\n");
+        let t = id_of(&state.graph, "t");
+        let (nested, sibling) = (state.graph.node(t).children()[0], state.graph.node(t).children()[1]);
+        let row_height = state.cell_height + state.cell_margin[1];
+        for (op_index, num_rows) in [(3, 1), (5, 3), (4, 2)].iter().copied() {
+            state.player_go_to(op_index);
+            let nested_y = state.positions[nested][1];
+            assert_eq!(state.positions[sibling][1], nested_y + num_rows as Scalar * row_height, "at op {}", op_index);
+            // The nested elements go beside the element holding them.
+            let elements = state.graph.node(nested).children().clone();
+            for (i, id) in elements[..num_rows].iter().enumerate() {
+                assert!(state.positions[*id][0] > state.positions[sibling][0]);
+                assert_eq!(state.positions[*id][1], nested_y + i as Scalar * row_height);
+            }
+        }
+    }
+
+    #[test]
+    fn table_cells_line_up_in_a_column_per_field() {
+        let mut state = state_for_trace("display people table\npush people P { name: \"al\", age: 3 }\n\
+                                         push people P { name: \"bartholomew\", age: 40 }\n");
+        state.player_go_to(state.ops.len() - 1);
+        let people = id_of(&state.graph, "people");
+        let rows = state.graph.node(people).children().clone();
+        let widths = state.table_widths(people).to_vec();
+        assert_eq!(state.graph.table_headers(people), vec!["name".to_string(), "age".to_string()]);
+        assert_eq!(widths.len(), 2);
+        assert!(widths[0] >= state.font.text_width(VALUE_FONT_SIZE, "\"bartholomew\""));
+
+        let row_height = state.cell_height + state.cell_margin[1];
+        let fields: Vec<Vec<NId>> = rows.iter().map(|row| state.graph.node(*row).children().clone()).collect();
+        let origin = state.children_origin(people, state.positions[people]);
+        let offsets = state.table_column_offsets(people);
+        assert_eq!(offsets[1], offsets[0] + widths[0] + state.cell_margin[0]);
+        for (row, row_fields) in fields.iter().enumerate() {
+            for (col, field) in row_fields.iter().enumerate() {
+                // Below the row of headers.
+                assert_eq!(state.positions[*field], [origin[0] + offsets[col], origin[1] + (row + 1) as Scalar * row_height]);
+                assert_eq!(state.draw_states[*field].cell_width, widths[col]);
+            }
         }
     }
+
+    #[test]
+    fn swapping_fields_exchanges_their_values_in_place() {
+        let source = "set p = P { x: 1, y: Q { z: 2 } }\nswap p.x p.y\n";
+        let (before, after) = last_event_both_ways(source, &["p"]);
+        assert_eq!(before, vec!["P { x: 1, y: Q { z: 2 } }"]);
+        assert_eq!(after, vec!["P { x: Q { z: 2 }, y: 1 }"]);
+        let graph = process_trace(source);
+        assert_eq!(field_names(&graph, id_of(&graph, "p")), vec!["x", "y"]);
+    }
+
+    #[test]
+    fn swapping_array_variables_exchanges_their_elements() {
+        let (before, after) = last_event_both_ways("push a 1\npush b 2\npush b 3\nswap a b\n", &["a", "b"]);
+        assert_eq!(before, vec!["[1]", "[2, 3]"]);
+        assert_eq!(after, vec!["[2, 3]", "[1]"]);
+    }
+
+    #[test]
+    fn sources_are_read_once_for_the_new_ops() {
+        let math_path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/math.rs");
+        let mut state = state_for_trace("at=missing.rs:1 set a = 1\n");
+        state.load_sources(None);
+        assert_eq!(state.sources.get("missing.rs"), Some(&None));
+
+        state.sources.insert("missing.rs".to_string(), Some(vec!["kept".to_string()]));
+        let source = format!("at=missing.rs:2 set a = 2\nat={}:3 set a = 3\n", math_path);
+        state.append_events(trace::parse_trace(&source).expect("Invalid trace"), None);
+        // Not tried again.
+        assert_eq!(state.sources.get("missing.rs"), Some(&Some(vec!["kept".to_string()])));
+        let lines = state.sources.get(math_path).cloned().flatten().expect("Source should be read");
+        assert_eq!(lines[0], "use crate::core::*;");
+    }
+
+    #[test]
+    fn swap_with_an_element_of_itself_is_ignored() {
+        let graph = process_trace("push arr []\npush arr[0] 5\nswap arr[0] arr[0][0]\n");
+        let arr = id_of(&graph, "arr");
+        let row = graph.node(arr).children()[0];
+        let cell = graph.node(row).children()[0];
+        assert_eq!(graph.node(cell).parent_id(), Some(&row));
+        assert_eq!(graph.subtree_ids(arr), vec![arr, row, cell]);
+        assert_eq!(graph.location_label(cell), "arr[0][0]");
+    }
 }
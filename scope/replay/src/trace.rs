@@ -0,0 +1,958 @@
+// The textual trace format.  It's the scope language from language.md with one
+// event per line.  An event may start with attributes like the thread it
+// happened on.
+//
+//     # Comments start with a hash.
+//     set num = 22
+//     set arr[1] = "beta"
+//     set p = Point { x: 1, y: 2 }
+//     set p.x = 3
+//     push alphabet "A"
+//     pop alphabet
+//     insert alphabet 0 "Z"
+//     remove alphabet 1
+//     swap alphabet[0] alphabet[1]
+//     display arr_nested tree
+//     display i index_into arr
+//     tag arr[0..3] sorted
+//     untag arr[2] current
+//     copy arr[2..5] into tmp[0]
+//     move tmp[0..3] into arr[4]
+//     forget tmp
+//     enter factorial
+//     exit
+//     thread=worker_1 push queue 5
+//     time=0.0125 set num = 23
+//     at=src/sort.rs:12:9 swap arr[0] arr[1]
+//     at="my src/sort.rs":12 pop arr
+use std::fmt;
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::CharIndices;
+
+use crate::event::*;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    line: usize,
+}
+
+pub fn read_trace_file<P: AsRef<Path>>(path: P) -> Result<Vec<(EventMeta, Event)>, TraceError> {
+    let source = fs::read_to_string(path).map_err(TraceError::Io)?;
+
+    parse_trace(&source).map_err(TraceError::Parse)
+}
+
+pub fn parse_trace(source: &str) -> Result<Vec<(EventMeta, Event)>, ParseError> {
+    let mut events = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        if let Some(event) = parse_line(line, i + 1)? {
+            events.push(event);
+        }
+    }
+
+    Ok(events)
+}
+
+// Parses a single line.  Blank lines and comments result in None.
+pub fn parse_line(line: &str, line_number: usize) -> Result<Option<(EventMeta, Event)>, ParseError> {
+    let mut parser = Parser::new(line, line_number);
+    parser.skip_whitespace();
+    if parser.at_end_of_line() {
+        return Ok(None);
+    }
+    let meta = parser.parse_meta()?;
+    let event = parser.parse_event()?;
+    parser.skip_whitespace();
+    if !parser.at_end_of_line() {
+        return Err(parser.error("Expected end of line"));
+    }
+
+    Ok(Some((meta, event)))
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str, line: usize) -> Parser<'a> {
+        Parser {
+            source,
+            chars: source.char_indices().peekable(),
+            line,
+        }
+    }
+
+    fn error(&mut self, message: &str) -> ParseError {
+        let column = self.offset() + 1;
+
+        ParseError {
+            line: self.line,
+            column,
+            message: message.to_string(),
+        }
+    }
+
+    fn offset(&mut self) -> usize {
+        self.chars.peek().map(|(i, _)| *i).unwrap_or_else(|| self.source.len())
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn at_end_of_line(&mut self) -> bool {
+        match self.peek() {
+            None | Some('#') => true,
+            Some(_) => false,
+        }
+    }
+
+    fn expect_char(&mut self, expected: char) -> Result<(), ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.chars.next();
+                Ok(())
+            }
+            _ => Err(self.error(&format!("Expected {:?}", expected))),
+        }
+    }
+
+    fn parse_identifier(&mut self) -> Result<Identifier, ParseError> {
+        self.skip_whitespace();
+        let start = self.offset();
+        match self.peek() {
+            Some(c) if c.is_alphabetic() || c == '_' => (),
+            _ => return Err(self.error("Expected an identifier")),
+        }
+        while let Some(c) = self.peek() {
+            if !(c.is_alphanumeric() || c == '_') {
+                break;
+            }
+            self.chars.next();
+        }
+        let end = self.offset();
+
+        Ok(self.source[start..end].to_string())
+    }
+
+    // A record field, which is an identifier or, for tuples, an index.
+    fn parse_field_name(&mut self) -> Result<Identifier, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() => {
+                let start = self.offset();
+                while let Some(c) = self.peek() {
+                    if !c.is_ascii_digit() {
+                        break;
+                    }
+                    self.chars.next();
+                }
+                let end = self.offset();
+
+                Ok(self.source[start..end].to_string())
+            }
+            _ => self.parse_identifier(),
+        }
+    }
+
+    // The rest of a type name after its first word, like an enum variant in
+    // `Shape::Circle`.
+    fn parse_type_path(&mut self, mut type_name: Identifier) -> Result<Identifier, ParseError> {
+        loop {
+            let mut chars = self.chars.clone();
+            let is_path = chars.next().map(|(_, c)| c) == Some(':') && chars.next().map(|(_, c)| c) == Some(':');
+            if !is_path {
+                return Ok(type_name);
+            }
+            self.chars.next();
+            self.chars.next();
+            type_name.push_str("::");
+            type_name.push_str(&self.parse_identifier()?);
+        }
+    }
+
+    // Attributes like `thread=main` before the event.
+    fn parse_meta(&mut self) -> Result<EventMeta, ParseError> {
+        let mut meta = EventMeta::default();
+        while self.at_attribute() {
+            let key = self.parse_identifier()?;
+            self.expect_char('=')?;
+            match key.as_str() {
+                "thread" => meta.thread = Some(self.parse_name()?),
+                "time" => meta.time = Some(self.parse_time()?),
+                "at" => meta.location = Some(self.parse_source_location()?),
+                _ => return Err(self.error(&format!("Unknown attribute {}", key))),
+            }
+        }
+
+        Ok(meta)
+    }
+
+    // Whether the next word is directly followed by an equals sign.
+    fn at_attribute(&mut self) -> bool {
+        self.skip_whitespace();
+        let mut chars = self.chars.clone();
+        let mut is_empty = true;
+        while let Some((_, c)) = chars.peek() {
+            if !(c.is_alphanumeric() || *c == '_') {
+                break;
+            }
+            chars.next();
+            is_empty = false;
+        }
+
+        !is_empty && chars.peek().map(|(_, c)| *c) == Some('=')
+    }
+
+    // A name that may also start with a digit or contain dashes, like a
+    // thread id.
+    fn parse_name(&mut self) -> Result<String, ParseError> {
+        self.skip_whitespace();
+        let start = self.offset();
+        while let Some(c) = self.peek() {
+            if !(c.is_alphanumeric() || c == '_' || c == '-') {
+                break;
+            }
+            self.chars.next();
+        }
+        let end = self.offset();
+        if start == end {
+            return Err(self.error("Expected a name"));
+        }
+
+        Ok(self.source[start..end].to_string())
+    }
+
+    // A path with a line and an optional column, like `src/main.rs:12:5`.
+    // A path with whitespace in it is quoted like a string, as in
+    // `"my src/main.rs":12:5`.
+    fn parse_source_location(&mut self) -> Result<SourceLocation, ParseError> {
+        self.skip_whitespace();
+        let quoted_file = match self.peek() {
+            Some('"') => {
+                let file = self.parse_string()?;
+                self.expect_char(':')?;
+
+                Some(file)
+            }
+            _ => None,
+        };
+        let start = self.offset();
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+        let end = self.offset();
+        let text = &self.source[start..end];
+        let location = match quoted_file {
+            Some(file) => {
+                let mut parts = text.splitn(2, ':');
+                match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+                    (Some(Ok(line)), None) => Some(SourceLocation { file, line, column: None }),
+                    (Some(Ok(line)), Some(Ok(column))) => Some(SourceLocation { file, line, column: Some(column) }),
+                    _ => None,
+                }
+            }
+            None => parse_unquoted_source_location(text),
+        };
+
+        location.ok_or_else(|| self.error("Expected a source location like file:line:column"))
+    }
+
+    // Seconds, like `12` or `0.0125`.
+    fn parse_time(&mut self) -> Result<f64, ParseError> {
+        self.skip_whitespace();
+        let start = self.offset();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || c == '-' || c == '+') {
+                break;
+            }
+            self.chars.next();
+        }
+        let end = self.offset();
+
+        self.source[start..end].parse::<f64>().map_err(|_| self.error("Expected a time in seconds"))
+    }
+
+    fn parse_event(&mut self) -> Result<Event, ParseError> {
+        let keyword = self.parse_identifier()?;
+        match keyword.as_str() {
+            "noop" => Ok(Event::NoOp),
+            "display" => {
+                let loc = self.parse_location()?;
+                let name = self.parse_identifier()?;
+                if name == "index_into" {
+                    let array_loc = self.parse_location()?;
+
+                    return Ok(Event::IndexInto(loc, array_loc));
+                }
+                let display_type = self.parse_display_type(&name)?;
+
+                Ok(Event::Display(loc, display_type))
+            }
+            "set" => {
+                let loc = self.parse_location()?;
+                self.expect_char('=')?;
+                let value = self.parse_value()?;
+
+                Ok(Event::Set(loc, value))
+            }
+            "forget" => {
+                let name = self.parse_identifier()?;
+
+                Ok(Event::Forget(name))
+            }
+            "enter" => {
+                let name = self.parse_identifier()?;
+
+                Ok(Event::EnterFrame(name))
+            }
+            "exit" => Ok(Event::ExitFrame),
+            "push" => {
+                let loc = self.parse_location()?;
+                let value = self.parse_value()?;
+
+                Ok(Event::Push(loc, value))
+            }
+            "pop" => {
+                let loc = self.parse_location()?;
+
+                Ok(Event::Pop(loc))
+            }
+            "tag" => {
+                let loc = self.parse_slice_location()?;
+                let tag = self.parse_identifier()?;
+
+                Ok(Event::Tag(loc, tag))
+            }
+            "untag" => {
+                let loc = self.parse_slice_location()?;
+                let tag = self.parse_identifier()?;
+
+                Ok(Event::Untag(loc, tag))
+            }
+            "insert" => {
+                let loc = self.parse_location()?;
+                let index = self.parse_index()?;
+                let value = self.parse_value()?;
+
+                Ok(Event::Insert(loc, index, value))
+            }
+            "remove" => {
+                let loc = self.parse_location()?;
+                let index = self.parse_index()?;
+
+                Ok(Event::Remove(loc, index))
+            }
+            "copy" | "move" => {
+                let src = self.parse_slice_location()?;
+                let into = self.parse_identifier()?;
+                if into != "into" {
+                    return Err(self.error("Expected into"));
+                }
+                let dst = self.parse_location()?;
+
+                Ok(if keyword == "copy" { Event::Copy(src, dst) } else { Event::Move(src, dst) })
+            }
+            "swap" => {
+                let loc1 = self.parse_location()?;
+                let loc2 = self.parse_location()?;
+
+                Ok(Event::Swap(loc1, loc2))
+            }
+            _ => Err(ParseError {
+                line: self.line,
+                column: 1,
+                message: format!("Unknown operation: {}", keyword),
+            }),
+        }
+    }
+
+    fn parse_display_type(&mut self, name: &str) -> Result<DisplayType, ParseError> {
+        match name {
+            "default" => Ok(DisplayType::Default),
+            "tree" => Ok(DisplayType::Tree),
+            "table" => Ok(DisplayType::Table),
+            _ => Err(self.error(&format!("Unknown display type: {}", name))),
+        }
+    }
+
+    fn parse_location(&mut self) -> Result<Location, ParseError> {
+        match self.parse_slice_location()? {
+            SliceLocation::SimpleLoc(loc) => Ok(loc),
+            SliceLocation::SliceLoc(..) => Err(self.error("Expected a location, not a slice")),
+        }
+    }
+
+    // A location, or a slice of an array like `arr[2..5]`.  The slice must
+    // come last.
+    fn parse_slice_location(&mut self) -> Result<SliceLocation, ParseError> {
+        let name = self.parse_identifier()?;
+        let mut loc = Location::VariableLoc(name);
+        // The index must directly follow the location so that a value starting
+        // with a bracket isn't mistaken for an index, as in `push arr [1, 2]`.
+        loop {
+            match self.peek() {
+                Some('[') => {
+                    self.chars.next();
+                    let index = self.parse_index()?;
+                    self.skip_whitespace();
+                    if self.peek() == Some('.') {
+                        self.chars.next();
+                        self.expect_char('.')?;
+                        let hi = self.parse_index()?;
+                        self.expect_char(']')?;
+
+                        return Ok(SliceLocation::SliceLoc(loc, index, hi));
+                    }
+                    self.expect_char(']')?;
+                    loc = Location::IndexLoc(Box::new(loc), index);
+                }
+                Some('.') => {
+                    self.chars.next();
+                    let field = self.parse_field_name()?;
+                    loc = Location::FieldLoc(Box::new(loc), field);
+                }
+                _ => break,
+            }
+        }
+
+        Ok(SliceLocation::SimpleLoc(loc))
+    }
+
+    fn parse_index(&mut self) -> Result<Index, ParseError> {
+        self.skip_whitespace();
+        let start = self.offset();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            self.chars.next();
+        }
+        let end = self.offset();
+
+        self.source[start..end].parse::<Index>().map_err(|_| self.error("Expected an array index"))
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('[') => {
+                self.chars.next();
+                let mut values = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(']') {
+                    self.chars.next();
+                    return Ok(Value::ArrayVal(values));
+                }
+                loop {
+                    values.push(self.parse_value()?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(',') => { self.chars.next(); }
+                        Some(']') => {
+                            self.chars.next();
+                            break;
+                        }
+                        _ => return Err(self.error("Expected ',' or ']'")),
+                    }
+                }
+
+                Ok(Value::ArrayVal(values))
+            }
+            Some('{') => self.parse_record(None),
+            Some('"') => self.parse_string().map(Value::StringVal),
+            Some('\'') => self.parse_char().map(Value::CharVal),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(c) if c.is_alphabetic() => {
+                let word = self.parse_identifier()?;
+                match word.as_str() {
+                    "b" if self.peek() == Some('"') => self.parse_bytes().map(Value::BytesVal),
+                    "true" => Ok(Value::BoolVal(true)),
+                    "false" => Ok(Value::BoolVal(false)),
+                    "nil" => Ok(Value::NilVal),
+                    "undefined" => Ok(Value::UndefinedVal),
+                    // How Debug writes floats that aren't numbers.
+                    "NaN" => Ok(Value::F64Val(f64::NAN)),
+                    "inf" => Ok(Value::F64Val(f64::INFINITY)),
+                    _ => {
+                        let type_name = self.parse_type_path(word)?;
+                        self.skip_whitespace();
+                        if self.peek() != Some('{') {
+                            return Err(self.error(&format!("Unknown constant: {}", type_name)));
+                        }
+
+                        self.parse_record(Some(type_name))
+                    }
+                }
+            }
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    // Records look like Rust structs, as in `Point { x: 1, y: 2 }`.  The type
+    // name is optional, and may be an enum variant's path.  Tuple fields are
+    // named by their index, as in `Pair { 0: 1, 1: 2 }`.
+    fn parse_record(&mut self, type_name: Option<Identifier>) -> Result<Value, ParseError> {
+        self.expect_char('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.chars.next();
+            return Ok(Value::RecordVal(type_name, fields));
+        }
+        loop {
+            let name = self.parse_field_name()?;
+            self.expect_char(':')?;
+            fields.push((name, self.parse_value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(',') => { self.chars.next(); }
+                Some('}') => {
+                    self.chars.next();
+                    break;
+                }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+
+        Ok(Value::RecordVal(type_name, fields))
+    }
+
+    // Integers are i32 unless they have a suffix, like `7u64`, or don't fit.
+    fn parse_number(&mut self) -> Result<Value, ParseError> {
+        let start = self.offset();
+        let mut is_float = false;
+        if self.peek() == Some('-') {
+            self.chars.next();
+        }
+        while let Some(c) = self.peek() {
+            match c {
+                '0'..='9' => (),
+                '.' | 'e' | 'E' => is_float = true,
+                '+' | '-' if is_float => (),
+                _ => break,
+            }
+            self.chars.next();
+        }
+        let end = self.offset();
+        let text = &self.source[start..end];
+        let suffix = match self.peek() {
+            Some(c) if c.is_alphabetic() => Some(self.parse_identifier()?),
+            _ => None,
+        };
+
+        let value = match suffix.as_deref() {
+            None if is_float => text.parse::<f64>().ok().map(Value::F64Val),
+            None => text.parse::<i32>().map(Value::I32Val)
+                        .or_else(|_| text.parse::<i64>().map(Value::I64Val))
+                        .or_else(|_| text.parse::<u64>().map(Value::U64Val))
+                        .ok(),
+            Some("inf") if text == "-" => Some(Value::F64Val(f64::NEG_INFINITY)),
+            Some("f64") => text.parse::<f64>().ok().map(Value::F64Val),
+            Some("i32") if !is_float => text.parse::<i32>().ok().map(Value::I32Val),
+            Some("i64") if !is_float => text.parse::<i64>().ok().map(Value::I64Val),
+            Some("u64") if !is_float => text.parse::<u64>().ok().map(Value::U64Val),
+            Some(suffix) => return Err(self.error(&format!("Unknown number suffix: {}", suffix))),
+        };
+
+        value.ok_or_else(|| self.error("Invalid number"))
+    }
+
+    // Strings and chars use the same escapes that Rust's Debug formatting
+    // produces so that Value::display_string() round-trips.
+    fn parse_string(&mut self) -> Result<String, ParseError> {
+        self.expect_char('"')?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next() {
+                None => return Err(self.error("Unterminated string")),
+                Some((_, '"')) => break,
+                Some((_, '\\')) => s.push(self.parse_escape()?),
+                Some((_, c)) => s.push(c),
+            }
+        }
+
+        Ok(s)
+    }
+
+    fn parse_char(&mut self) -> Result<char, ParseError> {
+        self.expect_char('\'')?;
+        let c = match self.chars.next() {
+            None | Some((_, '\'')) => return Err(self.error("Expected a char")),
+            Some((_, '\\')) => self.parse_escape()?,
+            Some((_, c)) => c,
+        };
+        match self.chars.next() {
+            Some((_, '\'')) => Ok(c),
+            _ => Err(self.error("Expected a closing quote")),
+        }
+    }
+
+    // Byte strings are written like Rust's, as in `b"\x00\xffabc"`.  The `b`
+    // has already been read.
+    fn parse_bytes(&mut self) -> Result<Vec<u8>, ParseError> {
+        let s = self.parse_string()?;
+        if s.chars().any(|c| c as u32 > 0xff) {
+            return Err(self.error("Invalid character in byte string"));
+        }
+
+        Ok(s.chars().map(|c| c as u8).collect())
+    }
+
+    // The character after a backslash.
+    fn parse_escape(&mut self) -> Result<char, ParseError> {
+        match self.chars.next() {
+            Some((_, 'n')) => Ok('\n'),
+            Some((_, 'r')) => Ok('\r'),
+            Some((_, 't')) => Ok('\t'),
+            Some((_, '0')) => Ok('\0'),
+            Some((_, '\\')) => Ok('\\'),
+            Some((_, '\'')) => Ok('\''),
+            Some((_, '"')) => Ok('"'),
+            Some((_, 'u')) => self.parse_unicode_escape(),
+            Some((_, 'x')) => self.parse_hex_escape(),
+            _ => Err(self.error("Invalid escape")),
+        }
+    }
+
+    fn parse_hex_escape(&mut self) -> Result<char, ParseError> {
+        let start = self.offset();
+        for _ in 0..2 {
+            match self.peek() {
+                Some(c) if c.is_ascii_hexdigit() => { self.chars.next(); }
+                _ => return Err(self.error("Invalid hex escape")),
+            }
+        }
+        let end = self.offset();
+        let byte = u8::from_str_radix(&self.source[start..end], 16).map_err(|_| self.error("Invalid hex escape"))?;
+
+        Ok(byte as char)
+    }
+
+    fn parse_unicode_escape(&mut self) -> Result<char, ParseError> {
+        self.expect_char('{')?;
+        let start = self.offset();
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_hexdigit() {
+                break;
+            }
+            self.chars.next();
+        }
+        let end = self.offset();
+        let code = u32::from_str_radix(&self.source[start..end], 16).ok().and_then(std::char::from_u32);
+        let c = code.ok_or_else(|| self.error("Invalid unicode escape"))?;
+        self.expect_char('}')?;
+
+        Ok(c)
+    }
+}
+
+// Writes a value as a literal that parse_value() can read back.
+fn fmt_literal(value: &Value, f: &mut fmt::Formatter) -> fmt::Result {
+    match value {
+        Value::ArrayVal(vec) => {
+            write!(f, "[")?;
+            for (i, v) in vec.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                fmt_literal(v, f)?;
+            }
+            write!(f, "]")
+        }
+        Value::BoolVal(b) => write!(f, "{}", b),
+        Value::BytesVal(bytes) => {
+            write!(f, "b\"")?;
+            for byte in bytes.iter() {
+                write!(f, "{}", std::ascii::escape_default(*byte))?;
+            }
+            write!(f, "\"")
+        }
+        Value::CharVal(c) => write!(f, "{:?}", c),
+        Value::I32Val(n) => write!(f, "{}", n),
+        Value::I64Val(n) => write!(f, "{}i64", n),
+        Value::U64Val(n) => write!(f, "{}u64", n),
+        // Debug always includes a decimal point so that it reads back as a
+        // float, and writes NaN, inf and -inf, which are read back too.
+        Value::F64Val(x) => write!(f, "{:?}", x),
+        Value::NilVal => write!(f, "nil"),
+        Value::RecordVal(type_name, fields) => {
+            if let Some(type_name) = type_name {
+                write!(f, "{} ", type_name)?;
+            }
+            if fields.is_empty() {
+                return write!(f, "{{}}");
+            }
+            write!(f, "{{ ")?;
+            for (i, (name, v)) in fields.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: ", name)?;
+                fmt_literal(v, f)?;
+            }
+            write!(f, " }}")
+        }
+        Value::StringVal(s) => write!(f, "{:?}", s),
+        Value::UndefinedVal => write!(f, "undefined"),
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::VariableLoc(name) => write!(f, "{}", name),
+            Location::IndexLoc(loc, index) => write!(f, "{}[{}]", loc, index),
+            Location::FieldLoc(loc, field) => write!(f, "{}.{}", loc, field),
+        }
+    }
+}
+
+impl fmt::Display for SliceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SliceLocation::SimpleLoc(loc) => write!(f, "{}", loc),
+            SliceLocation::SliceLoc(loc, lo, hi) => write!(f, "{}[{}..{}]", loc, lo, hi),
+        }
+    }
+}
+
+impl fmt::Display for DisplayType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisplayType::Default => write!(f, "default"),
+            DisplayType::Tree => write!(f, "tree"),
+            DisplayType::Table => write!(f, "table"),
+        }
+    }
+}
+
+fn parse_unquoted_source_location(text: &str) -> Option<SourceLocation> {
+    let mut parts: Vec<&str> = text.rsplitn(3, ':').collect();
+    parts.reverse();
+    // The path itself may have colons, so the column is only taken when
+    // there's a line before it.
+    match parts.as_slice() {
+        [file, line, column] => match (line.parse(), column.parse()) {
+            (Ok(line), Ok(column)) => Some(SourceLocation { file: file.to_string(), line, column: Some(column) }),
+            // What came before the last colon is part of the path.
+            (_, Ok(line_number)) => {
+                Some(SourceLocation { file: format!("{}:{}", file, line), line: line_number, column: None })
+            }
+            _ => None,
+        },
+        [file, line] => line.parse().ok().map(|line| SourceLocation { file: file.to_string(), line, column: None }),
+        _ => None,
+    }
+}
+
+// Each attribute is followed by a space so that this can go right before the
+// event.
+impl fmt::Display for EventMeta {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(thread) = &self.thread {
+            write!(f, "thread={} ", thread)?;
+        }
+        if let Some(time) = self.time {
+            write!(f, "time={} ", time)?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, "at={} ", location)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.file.contains(char::is_whitespace) || self.file.starts_with('"') {
+            write!(f, "{:?}:{}", self.file, self.line)?;
+        } else {
+            write!(f, "{}:{}", self.file, self.line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::NoOp => write!(f, "noop"),
+            Event::Display(loc, display_type) => write!(f, "display {} {}", loc, display_type),
+            Event::IndexInto(loc, array_loc) => write!(f, "display {} index_into {}", loc, array_loc),
+            Event::Set(loc, value) => {
+                write!(f, "set {} = ", loc)?;
+                fmt_literal(value, f)
+            }
+            Event::Push(loc, value) => {
+                write!(f, "push {} ", loc)?;
+                fmt_literal(value, f)
+            }
+            Event::Pop(loc) => write!(f, "pop {}", loc),
+            Event::Forget(name) => write!(f, "forget {}", name),
+            Event::EnterFrame(name) => write!(f, "enter {}", name),
+            Event::ExitFrame => write!(f, "exit"),
+            Event::Insert(loc, index, value) => {
+                write!(f, "insert {} {} ", loc, index)?;
+                fmt_literal(value, f)
+            }
+            Event::Remove(loc, index) => write!(f, "remove {} {}", loc, index),
+            Event::Copy(src, dst) => write!(f, "copy {} into {}", src, dst),
+            Event::Move(src, dst) => write!(f, "move {} into {}", src, dst),
+            Event::Swap(loc1, loc2) => write!(f, "swap {} {}", loc1, loc2),
+            Event::Tag(loc, tag) => write!(f, "tag {} {}", loc, tag),
+            Event::Untag(loc, tag) => write!(f, "untag {} {}", loc, tag),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TraceError::Io(e) => write!(f, "{}", e),
+            TraceError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(event: &Event) -> Event {
+        let line = event.to_string();
+        let (_, parsed) = parse_line(&line, 1)
+            .unwrap_or_else(|e| panic!("Failed to parse {}: {}", line, e))
+            .expect("line should have an event");
+
+        parsed
+    }
+
+    fn set(value: Value) -> Event {
+        Event::Set(Location::VariableLoc("v".to_string()), value)
+    }
+
+    #[test]
+    fn source_locations_may_have_colons_in_the_path() {
+        let (meta, _) = parse_line("at=C:/src/main.rs:12 exit", 1).expect("Invalid line").expect("line should have an event");
+        assert_eq!(meta.location, Some(SourceLocation { file: "C:/src/main.rs".to_string(), line: 12, column: None }));
+        let (meta, _) = parse_line("at=C:/src/main.rs:12:5 exit", 1).expect("Invalid line").expect("line should have an event");
+        assert_eq!(meta.location, Some(SourceLocation { file: "C:/src/main.rs".to_string(), line: 12, column: Some(5) }));
+    }
+
+    #[test]
+    fn source_locations_with_spaces_in_the_path_round_trip() {
+        for column in [None, Some(5)].iter().copied() {
+            let location = SourceLocation { file: "C:/My Projects/\"a\" b.rs".to_string(), line: 12, column };
+            let meta = EventMeta { location: Some(location), ..EventMeta::default() };
+            let line = format!("{}exit", meta);
+            let (parsed, event) = parse_line(&line, 1).expect("Invalid line").expect("line should have an event");
+            assert_eq!((parsed, event), (meta, Event::ExitFrame));
+        }
+        assert!(parse_line("at=\"a b.rs\" exit", 1).is_err());
+    }
+
+    #[test]
+    fn floats_round_trip() {
+        for x in [0.5, -2.0, 1e300, f64::INFINITY, f64::NEG_INFINITY].iter() {
+            let event = Event::Set(Location::VariableLoc("x".to_string()), Value::F64Val(*x));
+            assert_eq!(round_trip(&event), event);
+        }
+        let event = Event::Push(Location::VariableLoc("xs".to_string()), Value::F64Val(f64::NAN));
+        match round_trip(&event) {
+            Event::Push(_, Value::F64Val(x)) => assert!(x.is_nan()),
+            parsed => panic!("Expected a push of NaN, got {:?}", parsed),
+        }
+    }
+
+    #[test]
+    fn bools_chars_and_bytes_round_trip() {
+        let values = vec![
+            Value::BoolVal(true),
+            Value::BoolVal(false),
+            Value::CharVal('a'),
+            Value::CharVal('\''),
+            Value::CharVal('\\'),
+            Value::CharVal('"'),
+            Value::CharVal('\u{200b}'),
+            Value::BytesVal(vec![0x00, b'"', b'\\', b'a', 0x7f, 0x80, 0xff]),
+            Value::BytesVal(Vec::new()),
+        ];
+        for value in values {
+            let event = set(value);
+            assert_eq!(round_trip(&event), event);
+        }
+        assert_eq!(set(Value::CharVal('\u{200b}')).to_string(), "set v = '\\u{200b}'");
+    }
+
+    #[test]
+    fn wide_integers_keep_their_types() {
+        let values = vec![
+            (Value::I64Val(-5), "-5i64"),
+            (Value::I64Val(i64::MIN), "-9223372036854775808i64"),
+            (Value::U64Val(7), "7u64"),
+            (Value::U64Val(i64::MAX as u64 + 1), "9223372036854775808u64"),
+            (Value::U64Val(u64::MAX), "18446744073709551615u64"),
+        ];
+        for (value, text) in values {
+            let event = set(value);
+            assert_eq!(event.to_string(), format!("set v = {}", text));
+            assert_eq!(round_trip(&event), event);
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let event = set(Value::RecordVal(Some("Node".to_string()), vec![
+            ("key".to_string(), Value::U64Val(3)),
+            ("name".to_string(), Value::StringVal("a \"b\"".to_string())),
+            ("next".to_string(), Value::RecordVal(None, vec![("ok".to_string(), Value::BoolVal(true))])),
+            ("children".to_string(), Value::ArrayVal(vec![Value::CharVal('x'), Value::NilVal])),
+        ]));
+        assert_eq!(round_trip(&event), event);
+    }
+
+    #[test]
+    fn bytes_are_shown_in_hex() {
+        assert_eq!(Value::BytesVal(vec![0x00, 0x0f, 0xab, 0xff]).display_string(), "0x000fabff");
+        assert_eq!(Value::BytesVal(Vec::new()).display_string(), "0x");
+    }
+}
@@ -0,0 +1,335 @@
+// Records what a program already reports with the tracing crate, so that it
+// can be replayed without adding scope_* calls.
+//
+//     replay::recorder::record_to_file("run.trace")?;
+//     tracing_subscriber::registry().with(replay::tracing_layer::ScopeLayer::new()).init();
+//
+// Entering a span the first time starts a call frame named after the span,
+// with the span's fields as its variables, and closing the span ends the
+// frame.  A span that's entered again, like an async task each time it's
+// polled, is still one call.  Fields of events set the variables of the same
+// names in the frame of the span they're in.
+//
+// Only the innermost frame of a thread takes variables, but the spans of
+// async tasks polled on one thread needn't close in the order they started.
+// So the frames of spans that started later end before a change to an
+// earlier frame, or before it ends, and start again after it.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id, Record};
+use tracing_core::{Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::event::*;
+use crate::recorder::{record_on, thread_name, ToValue};
+
+#[derive(Clone, Debug, Default)]
+pub struct ScopeLayer {
+    // The spans with frames on each thread, in the order of their frames.
+    frames: Arc<Mutex<HashMap<Identifier, Vec<Id>>>>,
+}
+
+// The fields recorded for a span or an event, in the order they were given.
+// For a span, they're the variables of its frame, so they include the fields
+// of the events in it.
+#[derive(Default)]
+struct FieldValues {
+    values: Vec<(Identifier, Value)>,
+}
+
+// A span whose frame has started, and the thread it started on.  The span
+// may be closed on another thread.
+struct OpenFrame {
+    thread: Identifier,
+}
+
+impl ScopeLayer {
+    pub fn new() -> ScopeLayer {
+        ScopeLayer::default()
+    }
+
+    // Sets variables in the frame of a span, or with no span, outside of all
+    // the frames of the thread.
+    fn record_in<S>(&self, ctx: &Context<'_, S>, thread: &Identifier, id: Option<&Id>, fields: &FieldValues,
+                    metadata: &Metadata<'_>)
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut frames = self.frames.lock().expect("Frames lock poisoned");
+        let stack = frames.entry(thread.clone()).or_default();
+        let depth = match id {
+            None => 0,
+            Some(id) => stack.iter().position(|open| open == id).expect("Open frame not on its thread") + 1,
+        };
+        let above = exit_frames(ctx, thread, stack, depth);
+        record_fields(thread, fields, metadata);
+        enter_frames(ctx, thread, stack, above);
+    }
+}
+
+impl<S> Layer<S> for ScopeLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("New span not found");
+        let mut fields = FieldValues::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Recorded span not found");
+        let mut changed = FieldValues::default();
+        values.record(&mut changed);
+        let thread = {
+            let mut extensions = span.extensions_mut();
+            let fields = extensions.get_mut::<FieldValues>().expect("Span without fields");
+            for (name, value) in changed.values.iter() {
+                fields.set(name.clone(), value.clone());
+            }
+            extensions.get_mut::<OpenFrame>().map(|frame| frame.thread.clone())
+        };
+        // A span that hasn't been entered shows the change when its frame
+        // starts.
+        if let Some(thread) = thread {
+            self.record_in(&ctx, &thread, Some(id), &changed, span.metadata());
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Entered span not found");
+        if span.extensions().get::<OpenFrame>().is_some() {
+            return;
+        }
+        let thread = thread_name();
+        let mut frames = self.frames.lock().expect("Frames lock poisoned");
+        enter_frames(&ctx, &thread, frames.entry(thread.clone()).or_default(), vec![id.clone()]);
+        span.extensions_mut().insert(OpenFrame { thread });
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).expect("Closed span not found");
+        // A span that was never entered has no frame.
+        let frame = span.extensions_mut().remove::<OpenFrame>();
+        let frame = match frame {
+            Some(frame) => frame,
+            None => return,
+        };
+        let mut frames = self.frames.lock().expect("Frames lock poisoned");
+        let stack = frames.entry(frame.thread.clone()).or_default();
+        let depth = stack.iter().position(|open| *open == id).expect("Open frame not on its thread");
+        let mut above = exit_frames(&ctx, &frame.thread, stack, depth);
+        above.remove(0);
+        enter_frames(&ctx, &frame.thread, stack, above);
+    }
+
+    fn on_event(&self, event: &tracing_core::Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = FieldValues::default();
+        event.record(&mut fields);
+        if fields.values.is_empty() {
+            return;
+        }
+        // The innermost span the event is in that has a frame.
+        let span = ctx.event_scope(event)
+            .and_then(|mut scope| scope.find(|span| span.extensions().get::<OpenFrame>().is_some()));
+        match span {
+            None => self.record_in(&ctx, &thread_name(), None, &fields, event.metadata()),
+            Some(span) => {
+                let thread = {
+                    let mut extensions = span.extensions_mut();
+                    let variables = extensions.get_mut::<FieldValues>().expect("Span without fields");
+                    for (name, value) in fields.values.iter() {
+                        variables.set(name.clone(), value.clone());
+                    }
+                    extensions.get_mut::<OpenFrame>().expect("Span without frame").thread.clone()
+                };
+                self.record_in(&ctx, &thread, Some(&span.id()), &fields, event.metadata());
+            }
+        }
+    }
+}
+
+// Ends the frames of a thread from a depth on, innermost first, and returns
+// their spans.
+fn exit_frames<S>(ctx: &Context<'_, S>, thread: &Identifier, stack: &mut Vec<Id>, depth: usize) -> Vec<Id>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let above = stack.split_off(depth);
+    for id in above.iter().rev() {
+        let span = ctx.span(id).expect("Span with a frame not found");
+        record_on(thread.clone(), Event::ExitFrame, source_location(span.metadata()));
+    }
+
+    above
+}
+
+// Starts frames for spans on top of those of a thread, with their variables.
+fn enter_frames<S>(ctx: &Context<'_, S>, thread: &Identifier, stack: &mut Vec<Id>, ids: Vec<Id>)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    for id in ids {
+        let span = ctx.span(&id).expect("Span with a frame not found");
+        record_on(thread.clone(), Event::EnterFrame(to_identifier(span.name())), source_location(span.metadata()));
+        let extensions = span.extensions();
+        let fields = extensions.get::<FieldValues>().expect("Span without fields");
+        record_fields(thread, fields, span.metadata());
+        stack.push(id);
+    }
+}
+
+impl FieldValues {
+    fn set(&mut self, name: Identifier, value: Value) {
+        match self.values.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.values.push((name, value)),
+        }
+    }
+
+    // The message of an event is text about it rather than a variable, so
+    // it's left out.
+    fn record_value(&mut self, field: &Field, value: Value) {
+        if field.name() == "message" {
+            return;
+        }
+        self.set(to_identifier(field.name()), value);
+    }
+}
+
+impl Visit for FieldValues {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record_value(field, value.to_value());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record_value(field, value.to_value());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record_value(field, value.to_value());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record_value(field, value.to_value());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_value(field, value.to_value());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_value(field, Value::StringVal(format!("{:?}", value)));
+    }
+}
+
+fn record_fields(thread: &Identifier, fields: &FieldValues, metadata: &Metadata<'_>) {
+    for (name, value) in fields.values.iter() {
+        record_on(thread.clone(), Event::Set(Location::VariableLoc(name.clone()), value.clone()), source_location(metadata));
+    }
+}
+
+fn source_location(metadata: &Metadata<'_>) -> Option<SourceLocation> {
+    match (metadata.file(), metadata.line()) {
+        (Some(file), Some(line)) => Some(SourceLocation { file: file.to_string(), line: line as usize, column: None }),
+        _ => None,
+    }
+}
+
+// Span and field names can be any text, like `http.method`, but the trace
+// format needs identifiers.
+fn to_identifier(name: &str) -> Identifier {
+    let name = name.strip_prefix("r#").unwrap_or(name);
+    let mut identifier: Identifier = name.chars().map(|c| if c.is_alphanumeric() { c } else { '_' }).collect();
+    if !identifier.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+        identifier.insert(0, '_');
+    }
+
+    identifier
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::recorder::tests::record_events as record;
+
+    fn record_events<F: FnOnce()>(f: F) -> Vec<Event> {
+        record(|| tracing::subscriber::with_default(tracing_subscriber::registry().with(ScopeLayer::new()), f))
+    }
+
+    fn set(name: &str, n: i64) -> Event {
+        Event::Set(Location::VariableLoc(name.to_string()), Value::I64Val(n))
+    }
+
+    #[test]
+    fn a_span_entered_again_is_one_frame() {
+        let events = record_events(|| {
+            let span = tracing::info_span!("task", n = 1);
+            span.in_scope(|| tracing::info!(step = 1));
+            span.in_scope(|| tracing::info!(step = 2));
+        });
+        assert_eq!(events, vec![Event::EnterFrame("task".to_string()), set("n", 1), set("step", 1), set("step", 2),
+                                Event::ExitFrame]);
+    }
+
+    #[test]
+    fn nested_spans_are_nested_frames() {
+        let events = record_events(|| {
+            tracing::info_span!("outer").in_scope(|| {
+                tracing::info_span!("inner").in_scope(|| tracing::info!(x = 3));
+            });
+            // Never entered, so never a frame.
+            let _unused = tracing::info_span!("unused");
+        });
+        assert_eq!(events, vec![Event::EnterFrame("outer".to_string()), Event::EnterFrame("inner".to_string()),
+                                set("x", 3), Event::ExitFrame, Event::ExitFrame]);
+    }
+
+    #[test]
+    fn spans_that_close_in_the_order_they_started_keep_their_frames() {
+        let enter = |name: &str| Event::EnterFrame(name.to_string());
+        let events = record_events(|| {
+            let a = tracing::info_span!("a", n = 1);
+            a.in_scope(|| tracing::info!(x = 1));
+            let b = tracing::info_span!("b", n = 2);
+            b.in_scope(|| tracing::info!(y = 2));
+            a.in_scope(|| tracing::info!(x = 3));
+            drop(a);
+            b.in_scope(|| tracing::info!(y = 4));
+            drop(b);
+            tracing::info!(z = 5);
+        });
+        assert_eq!(events, vec![
+            enter("a"), set("n", 1), set("x", 1),
+            enter("b"), set("n", 2), set("y", 2),
+            // a's variables are set in a's frame.
+            Event::ExitFrame, set("x", 3), enter("b"), set("n", 2), set("y", 2),
+            // a ends and b goes on.
+            Event::ExitFrame, Event::ExitFrame, enter("b"), set("n", 2), set("y", 2),
+            set("y", 4),
+            Event::ExitFrame, set("z", 5),
+        ]);
+    }
+
+    #[test]
+    fn fields_recorded_on_an_outer_span_are_set_in_its_frame() {
+        let events = record_events(|| {
+            let parent = tracing::info_span!("parent", x = tracing::field::Empty);
+            parent.in_scope(|| {
+                tracing::info_span!("child", y = 1).in_scope(|| parent.record("x", 5));
+            });
+        });
+        assert_eq!(events, vec![
+            Event::EnterFrame("parent".to_string()), Event::EnterFrame("child".to_string()), set("y", 1),
+            Event::ExitFrame, set("x", 5), Event::EnterFrame("child".to_string()), set("y", 1),
+            Event::ExitFrame, Event::ExitFrame,
+        ]);
+    }
+}
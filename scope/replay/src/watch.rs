@@ -0,0 +1,138 @@
+// Follows a trace file as a program writes it.
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::event::*;
+use crate::trace;
+
+// How much of the start of the file is kept to tell when it's been replaced.
+const HEAD_LEN: u64 = 1024;
+
+pub struct TraceWatcher {
+    path: PathBuf,
+    // Bytes read so far, which always end with a whole line.
+    offset: u64,
+    num_lines: usize,
+    // The start of the file as it was read.  A new run of the program writes
+    // a new file whose start differs, if only in its timestamps.
+    head: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TraceChange {
+    Appended(Vec<(EventMeta, Event)>),
+    // The file was truncated or replaced, so these are all its events.
+    Replaced(Vec<(EventMeta, Event)>),
+}
+
+impl TraceWatcher {
+    pub fn new<P: AsRef<Path>>(path: P) -> TraceWatcher {
+        TraceWatcher {
+            path: path.as_ref().to_path_buf(),
+            offset: 0,
+            num_lines: 0,
+            head: Vec::new(),
+        }
+    }
+
+    // Reads the events written since the last time.  A file that doesn't
+    // exist yet has no events.  Lines that fail to parse are skipped, so
+    // that a mistake doesn't stop the viewer.
+    pub fn poll(&mut self) -> io::Result<Option<TraceChange>> {
+        let len = match fs::metadata(&self.path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+            Ok(metadata) => metadata.len(),
+        };
+        let mut file = File::open(&self.path)?;
+        let mut head = Vec::new();
+        (&mut file).take(HEAD_LEN).read_to_end(&mut head)?;
+        let is_replaced = len < self.offset || !head.starts_with(&self.head);
+        if is_replaced {
+            self.offset = 0;
+            self.num_lines = 0;
+        } else if len == self.offset {
+            return Ok(None);
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        // A line that's still being written is read once it's whole.
+        let end = match bytes.iter().rposition(|b| *b == b'\n') {
+            None if is_replaced => 0,
+            None => return Ok(None),
+            Some(i) => i + 1,
+        };
+        self.offset += end as u64;
+        head.truncate(self.offset.min(HEAD_LEN) as usize);
+        self.head = head;
+
+        let events = self.parse_lines(&String::from_utf8_lossy(&bytes[..end]));
+        if is_replaced {
+            return Ok(Some(TraceChange::Replaced(events)));
+        }
+
+        Ok(Some(TraceChange::Appended(events)))
+    }
+
+    fn parse_lines(&mut self, source: &str) -> Vec<(EventMeta, Event)> {
+        let mut events = Vec::new();
+        for line in source.lines() {
+            self.num_lines += 1;
+            match trace::parse_line(line, self.num_lines) {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => (),
+                Err(e) => eprintln!("Skipping line of {}: {}", self.path.display(), e),
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn set(name: &str, n: i32) -> (EventMeta, Event) {
+        (EventMeta::default(), Event::Set(Location::VariableLoc(name.to_string()), Value::I32Val(n)))
+    }
+
+    fn append(path: &Path, text: &str) {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path).expect("Couldn't open trace");
+        file.write_all(text.as_bytes()).expect("Couldn't write trace");
+    }
+
+    #[test]
+    fn follows_appends_truncation_and_replacement() {
+        let path = std::env::temp_dir().join(format!("replay-watch-{}.trace", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut watcher = TraceWatcher::new(&path);
+        assert_eq!(watcher.poll().expect("Couldn't poll"), None);
+
+        // The line still being written waits until it's whole.
+        append(&path, "set a = 1\nset b = ");
+        assert_eq!(watcher.poll().expect("Couldn't poll"), Some(TraceChange::Appended(vec![set("a", 1)])));
+        assert_eq!(watcher.poll().expect("Couldn't poll"), None);
+        append(&path, "2\nset c = \nset d = 4\n");
+        assert_eq!(watcher.poll().expect("Couldn't poll"), Some(TraceChange::Appended(vec![set("b", 2), set("d", 4)])));
+
+        // Shorter than what was read.
+        fs::write(&path, "set e = 5\n").expect("Couldn't write trace");
+        assert_eq!(watcher.poll().expect("Couldn't poll"), Some(TraceChange::Replaced(vec![set("e", 5)])));
+
+        // Rewritten with the same start is still appending.
+        fs::write(&path, "set e = 5\nset g = 7\n").expect("Couldn't write trace");
+        assert_eq!(watcher.poll().expect("Couldn't poll"), Some(TraceChange::Appended(vec![set("g", 7)])));
+        // As long as what was read, but with a different start.
+        fs::write(&path, "set h = 8\nset i = 9\n").expect("Couldn't write trace");
+        assert_eq!(watcher.poll().expect("Couldn't poll"),
+                   Some(TraceChange::Replaced(vec![set("h", 8), set("i", 9)])));
+
+        fs::remove_file(&path).expect("Couldn't remove trace");
+    }
+}
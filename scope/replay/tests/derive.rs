@@ -0,0 +1,94 @@
+// Runs the ToValue derive from replay-derive on the kinds of types it takes,
+// the way a program recording events would.
+use replay::event::{DisplayType, Value};
+use replay::recorder::ToValue;
+
+#[derive(ToValue)]
+struct Node {
+    key: i32,
+    #[scope(display = "tree")]
+    children: Vec<Node>,
+    #[scope(skip)]
+    cache: Option<String>,
+}
+
+#[derive(ToValue)]
+struct Pair(i32, #[scope(skip)] bool, String);
+
+#[derive(ToValue)]
+enum Shape {
+    Circle { radius: u8 },
+    Square(u8),
+    Empty,
+}
+
+#[derive(ToValue)]
+struct Views {
+    #[scope(display = "default")]
+    plain: Vec<i32>,
+    #[scope(display = "tree")]
+    tree: Vec<i32>,
+    #[scope(display = "table")]
+    table: Vec<Pair>,
+    unmarked: i32,
+}
+
+#[derive(ToValue)]
+struct Wrapper<T> {
+    inner: T,
+}
+
+fn record(type_name: &str, fields: Vec<(&str, Value)>) -> Value {
+    let fields = fields.into_iter().map(|(name, value)| (name.to_string(), value)).collect();
+
+    Value::RecordVal(Some(type_name.to_string()), fields)
+}
+
+#[test]
+fn named_structs_are_records_without_their_skipped_fields() {
+    let leaf = Node { key: 2, children: Vec::new(), cache: None };
+    let node = Node { key: 1, children: vec![leaf], cache: Some("cached".to_string()) };
+    assert!(node.cache.is_some());
+    let leaf_value = record("Node", vec![("key", Value::I32Val(2)), ("children", Value::ArrayVal(Vec::new()))]);
+    assert_eq!(node.to_value(),
+               record("Node", vec![("key", Value::I32Val(1)), ("children", Value::ArrayVal(vec![leaf_value]))]));
+    assert_eq!(node.field_display_types(), vec![("children".to_string(), DisplayType::Tree)]);
+}
+
+#[test]
+fn tuple_struct_fields_are_named_by_their_index() {
+    let pair = Pair(3, true, "three".to_string());
+    assert!(pair.1);
+    assert_eq!(pair.to_value(),
+               record("Pair", vec![("0", Value::I32Val(3)), ("2", Value::StringVal("three".to_string()))]));
+    assert!(pair.field_display_types().is_empty());
+}
+
+#[test]
+fn enums_are_records_tagged_with_the_variant() {
+    assert_eq!(Shape::Circle { radius: 4 }.to_value(), record("Shape::Circle", vec![("radius", Value::I32Val(4))]));
+    assert_eq!(Shape::Square(5).to_value(), record("Shape::Square", vec![("0", Value::I32Val(5))]));
+    assert_eq!(Shape::Empty.to_value(), record("Shape::Empty", Vec::new()));
+}
+
+#[test]
+fn fields_are_shown_the_way_they_ask() {
+    let views = Views { plain: vec![1], tree: vec![2], table: Vec::new(), unmarked: 3 };
+    assert_eq!(views.field_display_types(), vec![
+        ("plain".to_string(), DisplayType::Default),
+        ("tree".to_string(), DisplayType::Tree),
+        ("table".to_string(), DisplayType::Table),
+    ]);
+    assert_eq!(views.to_value(), record("Views", vec![
+        ("plain", Value::ArrayVal(vec![Value::I32Val(1)])),
+        ("tree", Value::ArrayVal(vec![Value::I32Val(2)])),
+        ("table", Value::ArrayVal(Vec::new())),
+        ("unmarked", Value::I32Val(3)),
+    ]));
+}
+
+#[test]
+fn type_parameters_are_converted_too() {
+    assert_eq!(Wrapper { inner: Shape::Empty }.to_value(),
+               record("Wrapper", vec![("inner", record("Shape::Empty", Vec::new()))]));
+}